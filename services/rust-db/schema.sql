@@ -1,18 +1,233 @@
 CREATE TABLE projects (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
-    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    name TEXT,
+    description TEXT,
+    -- most bytes of live entry content the project may hold, NULL means no limit
+    quota_bytes BIGINT,
+    -- for static sites: appended to keys ending in `/` which aren't found, e.g. `index.html`
+    index_key TEXT,
+    -- served for keys which aren't found, e.g. `404.html`, with `fallback_status` (NULL means 404)
+    fallback_key TEXT,
+    fallback_status SMALLINT,
+    -- regular expression (Rust `regex` syntax) every stored key must match, NULL allows any key
+    key_pattern TEXT,
+    -- applied to entries stored without an explicit `X-TTL-Seconds`, NULL means no expiry
+    default_ttl_secs BIGINT,
+    -- last time any of the project's entries was stored, changed or deleted, maintained by `touch_project`
+    entries_modified_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
 );
 
 CREATE TABLE entries (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
     project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    -- second level below the project so the same key can exist in e.g. `dev` and `prod`
+    namespace TEXT NOT NULL DEFAULT 'default',
     key TEXT NOT NULL,
     mime_type TEXT NOT NULL,
     content BYTEA NOT NULL,
     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
     updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
-    CONSTRAINT unique_project_key UNIQUE (project_id, key)
+    expires_at TIMESTAMPTZ,
+    immutable BOOLEAN NOT NULL DEFAULT FALSE,
+    response_headers JSONB NOT NULL DEFAULT '{}',
+    -- from `X-Actor`, NULL when the writer didn't identify themselves
+    created_by TEXT,
+    updated_by TEXT,
+    CONSTRAINT unique_project_key UNIQUE (project_id, namespace, key)
+);
+
+CREATE INDEX idx_entries_project_key ON entries (project_id, namespace, key);
+CREATE INDEX idx_entries_key_pattern ON entries (project_id, namespace, key text_pattern_ops);
+-- byte ordered, serves both the prefix match and the ordering of lists with `LIST_COLLATE_C`
+CREATE INDEX idx_entries_key_c ON entries (project_id, namespace, key COLLATE "C");
+-- serves the changes feed, ordered by when entries were last written
+CREATE INDEX idx_entries_updated ON entries (project_id, namespace, updated_at, key);
+
+-- other representations of an entry, e.g. `image/webp` alongside a `image/jpeg` entry, get serves whichever the
+-- request's `Accept` prefers; they share the entry's key, expiry and headers, and go when it's overwritten or deleted
+CREATE TABLE entry_variants (
+    entry_id UUID NOT NULL REFERENCES entries(id) ON DELETE CASCADE,
+    mime_type TEXT NOT NULL,
+    content BYTEA NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (entry_id, mime_type)
+);
+
+-- a trigger rather than an update in each handler, so no write path can forget it
+CREATE FUNCTION touch_project() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        UPDATE projects SET entries_modified_at = NOW() WHERE id = OLD.project_id;
+    ELSIF TG_OP = 'UPDATE' AND OLD.project_id <> NEW.project_id THEN
+        UPDATE projects SET entries_modified_at = NOW() WHERE id IN (OLD.project_id, NEW.project_id);
+    ELSE
+        UPDATE projects SET entries_modified_at = NOW() WHERE id = NEW.project_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER entries_touch_project
+    AFTER INSERT OR UPDATE OR DELETE ON entries
+    FOR EACH ROW EXECUTE FUNCTION touch_project();
+
+-- append-only record of every change to entries and their variants, written by `audit_entry` and `audit_variant` in
+-- the changing transaction; there's no foreign key to projects so the trail outlives the project
+CREATE TABLE audit_log (
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    project_id UUID NOT NULL,
+    namespace TEXT NOT NULL,
+    key TEXT NOT NULL,
+    -- `insert`, `update` (including expiry changes) or `delete`
+    op TEXT NOT NULL,
+    -- `X-Actor` of the request, from the transaction's `forgettable.actor` setting
+    actor TEXT,
+    -- content size after the change, or before a delete
+    size BIGINT NOT NULL,
+    -- mime type of the variant changed, NULL for a change to the entry itself
+    variant TEXT,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_audit_log_project ON audit_log (project_id, id);
+CREATE INDEX idx_audit_log_deletes ON audit_log (project_id, namespace, recorded_at)
+    WHERE op = 'delete' AND variant IS NULL;
+
+CREATE FUNCTION audit_entry() RETURNS TRIGGER AS $$
+BEGIN
+    IF current_setting('forgettable.moving', true) = 'on' THEN
+        -- moved between tables by `TENANT_SCHEMAS` rather than changed
+        RETURN NULL;
+    END IF;
+    IF TG_OP = 'DELETE' THEN
+        INSERT INTO audit_log (project_id, namespace, key, op, actor, size)
+        VALUES (
+            OLD.project_id, OLD.namespace, OLD.key, 'delete',
+            nullif(current_setting('forgettable.actor', true), ''), octet_length(OLD.content)
+        );
+    ELSIF TG_OP = 'UPDATE' AND OLD.project_id <> NEW.project_id THEN
+        -- moved by a project merge: gone from one project, new to the other
+        INSERT INTO audit_log (project_id, namespace, key, op, actor, size)
+        VALUES
+            (
+                OLD.project_id, OLD.namespace, OLD.key, 'delete',
+                nullif(current_setting('forgettable.actor', true), ''), octet_length(OLD.content)
+            ),
+            (
+                NEW.project_id, NEW.namespace, NEW.key, 'insert',
+                nullif(current_setting('forgettable.actor', true), ''), octet_length(NEW.content)
+            );
+    ELSE
+        INSERT INTO audit_log (project_id, namespace, key, op, actor, size)
+        VALUES (
+            NEW.project_id, NEW.namespace, NEW.key, lower(TG_OP),
+            nullif(current_setting('forgettable.actor', true), ''), octet_length(NEW.content)
+        );
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER entries_audit
+    AFTER INSERT OR UPDATE OR DELETE ON entries
+    FOR EACH ROW EXECUTE FUNCTION audit_entry();
+
+-- variants deleted along with their entry aren't recorded, the entry's delete already is
+CREATE FUNCTION audit_variant() RETURNS TRIGGER AS $$
+DECLARE
+    changed RECORD;
+    entry RECORD;
+BEGIN
+    IF current_setting('forgettable.moving', true) = 'on' THEN
+        RETURN NULL;
+    END IF;
+    IF TG_OP = 'DELETE' THEN
+        changed := OLD;
+    ELSE
+        changed := NEW;
+    END IF;
+    -- the entries table alongside this one, public or the tenant's schema
+    EXECUTE format('SELECT project_id, namespace, key FROM %I.entries WHERE id = $1', TG_TABLE_SCHEMA)
+        INTO entry USING changed.entry_id;
+    -- `EXECUTE` doesn't set `FOUND`
+    IF entry.project_id IS NULL THEN
+        RETURN NULL;
+    END IF;
+    INSERT INTO audit_log (project_id, namespace, key, op, actor, size, variant)
+    VALUES (
+        entry.project_id, entry.namespace, entry.key, lower(TG_OP),
+        nullif(current_setting('forgettable.actor', true), ''), octet_length(changed.content), changed.mime_type
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER entry_variants_audit
+    AFTER INSERT OR UPDATE OR DELETE ON entry_variants
+    FOR EACH ROW EXECUTE FUNCTION audit_variant();
+
+CREATE FUNCTION reject_audit_change() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE OR TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE FUNCTION reject_audit_change();
+
+-- content as jsonb for `jsonquery`, NULL when it isn't UTF-8 JSON, so one malformed entry doesn't fail the query
+CREATE FUNCTION try_jsonb(content BYTEA) RETURNS JSONB AS $$
+BEGIN
+    RETURN convert_from(content, 'UTF8')::jsonb;
+EXCEPTION WHEN OTHERS THEN
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+-- resumable uploads in progress; each chunk is its own row so appending doesn't rewrite what's already arrived, and
+-- the chunk which completes an upload stores it as an entry and removes it. Abandoned uploads are removed once
+-- they've expired, when the project next starts one
+CREATE TABLE uploads (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    namespace TEXT NOT NULL,
+    key TEXT NOT NULL,
+    mime_type TEXT NOT NULL,
+    immutable BOOLEAN NOT NULL DEFAULT FALSE,
+    -- size of the whole content, declared when the upload starts
+    length BIGINT NOT NULL,
+    received BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- pushed back by each chunk
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_uploads_project_expiry ON uploads (project_id, expires_at);
+
+CREATE TABLE upload_chunks (
+    upload_id UUID NOT NULL REFERENCES uploads(id) ON DELETE CASCADE,
+    "offset" BIGINT NOT NULL,
+    content BYTEA NOT NULL,
+    PRIMARY KEY (upload_id, "offset")
+);
+
+-- request counts per project per hour, flushed from each instance's in-memory counters; no foreign key to
+-- projects since requests for projects which don't exist yet are counted too
+CREATE TABLE project_usage (
+    project_id UUID NOT NULL,
+    bucket TIMESTAMPTZ NOT NULL,
+    reads BIGINT NOT NULL DEFAULT 0,
+    writes BIGINT NOT NULL DEFAULT 0,
+    deletes BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (project_id, bucket)
+);
+
+-- bump along with `SCHEMA_VERSION` in src/handlers/health.rs whenever this schema changes
+CREATE TABLE schema_migrations (
+    version INTEGER PRIMARY KEY,
+    applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
 );
 
-CREATE INDEX idx_entries_project_key ON entries (project_id, key);
-CREATE INDEX idx_entries_key_pattern ON entries (project_id, key text_pattern_ops);
+INSERT INTO schema_migrations (version) VALUES (18);
@@ -0,0 +1,123 @@
+//! Uniform per-request access logs, independent of the domain events logged by handlers.
+
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::{Request, State},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use opentelemetry::trace::{TraceContextExt, TraceId};
+use tower_http::{
+    LatencyUnit,
+    trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
+};
+use tracing::Level;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+use crate::{client_ip::ClientIp, config::Config, error::AppError};
+
+/// Request bodies longer than this are truncated in the log
+const MAX_LOGGED_BODY: usize = 1024;
+
+/// Log method, path, status and duration of every request at `ACCESS_LOG_LEVEL`, plus request bodies with
+/// `ACCESS_LOG_BODIES`
+pub fn add_layers<S>(router: Router<S>, config: &Arc<Config>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let Some(level) = config.access_log_level else {
+        return router;
+    };
+    let router = if config.access_log_bodies {
+        router.layer(middleware::from_fn_with_state(config.clone(), log_request_body))
+    } else {
+        router
+    };
+    // requests are logged by `log_json` instead
+    if config.json_access_log {
+        return router;
+    }
+    router.layer(
+        TraceLayer::new_for_http()
+            .make_span_with(DefaultMakeSpan::new().level(level))
+            .on_request(())
+            .on_response(DefaultOnResponse::new().level(level).latency_unit(LatencyUnit::Millis)),
+    )
+}
+
+/// With `LOG_FORMAT=json`, write a JSON line per request to stdout for log pipelines which ingest JSON; added
+/// inside `resolve_client_ip`, so the resolved address is known
+pub fn add_json_layer<S>(router: Router<S>, config: &Config) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    if config.json_access_log && config.access_log_level.is_some() {
+        router.layer(middleware::from_fn(log_json))
+    } else {
+        router
+    }
+}
+
+/// The request id is the client's `X-Request-Id` if it sent one, otherwise the request's trace id
+async fn log_json(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let client_ip = request.extensions().get::<ClientIp>().map(ToString::to_string);
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| {
+            let trace_id = tracing::Span::current().context().span().span_context().trace_id();
+            (trace_id != TraceId::INVALID).then(|| trace_id.to_string())
+        });
+    let response = next.run(request).await;
+    let line = serde_json::json!({
+        "timestamp": Utc::now(),
+        "method": method,
+        "path": path,
+        "status": response.status().as_u16(),
+        "duration_ms": start.elapsed().as_secs_f64() * 1000.0,
+        "project": path_project(&path),
+        "client_ip": client_ip,
+        "request_id": request_id,
+    });
+    println!("{line}");
+    response
+}
+
+/// Project id in a data, signed URL or admin path
+fn path_project(path: &str) -> Option<Uuid> {
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["project" | "signed", project, ..] | ["admin", "project" | "collisions", project, ..] => project.parse().ok(),
+        _ => None,
+    }
+}
+
+async fn log_request_body(State(config): State<Arc<Config>>, request: Request, next: Next) -> Response {
+    let (parts, body) = request.into_parts();
+    let bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => return AppError::Validation(format!("Failed to read request body: {e}")).into_response(),
+    };
+    if !bytes.is_empty() {
+        let logged = String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_LOGGED_BODY)]);
+        let truncated = bytes.len() > MAX_LOGGED_BODY;
+        match config.access_log_level {
+            Some(Level::ERROR) => tracing::error!(body = %logged, truncated, "request body"),
+            Some(Level::WARN) => tracing::warn!(body = %logged, truncated, "request body"),
+            Some(Level::INFO) => tracing::info!(body = %logged, truncated, "request body"),
+            Some(Level::DEBUG) => tracing::debug!(body = %logged, truncated, "request body"),
+            _ => tracing::trace!(body = %logged, truncated, "request body"),
+        }
+    }
+    next.run(Request::from_parts(parts, Body::from(bytes))).await
+}
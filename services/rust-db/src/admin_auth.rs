@@ -0,0 +1,32 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+use crate::{config::Config, error::AppError};
+
+/// With `ADMIN_TOKEN` set, admin routes require `Authorization: Bearer <token>`
+pub async fn require_admin_token(State(config): State<Arc<Config>>, request: Request, next: Next) -> Response {
+    if let Some(admin_token) = &config.admin_token
+        && !bearer_token(request.headers()).is_some_and(|token| tokens_match(token, admin_token))
+    {
+        return AppError::Unauthorized("a valid admin token is required".to_string()).into_response();
+    }
+    next.run(request).await
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+/// Compare digests rather than the tokens themselves, so the comparison time doesn't leak a matching prefix
+fn tokens_match(token: &str, expected: &str) -> bool {
+    Sha256::digest(token.as_bytes()) == Sha256::digest(expected.as_bytes())
+}
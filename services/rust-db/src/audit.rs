@@ -0,0 +1,36 @@
+//! Every change to `entries` is appended to `audit_log` by the `entries_audit` trigger, so no write path can skip
+//! it and the record commits or rolls back with the change itself.
+//!
+//! The trigger can't see the request, so handlers make their changes inside [`begin`], which sets the
+//! transaction-local `forgettable.actor` the trigger attributes them to.
+
+use axum::http::HeaderMap;
+use sqlx::{Connection, Postgres, Transaction};
+
+use crate::{
+    error::{AppError, Result},
+    tenant::PoolConnection,
+};
+
+/// `X-Actor` header identifying who's making a change, recorded for auditing
+pub fn actor(headers: &HeaderMap) -> Result<Option<String>> {
+    headers
+        .get("x-actor")
+        .map(|v| {
+            v.to_str()
+                .map(|v| v.trim().to_string())
+                .map_err(|_| AppError::Validation("X-Actor header must be visible ASCII".to_string()))
+        })
+        .transpose()
+        .map(|actor| actor.filter(|actor| !actor.is_empty()))
+}
+
+/// Start a transaction whose changes to entries are recorded in the audit log as made by `actor`
+pub async fn begin<'c>(conn: &'c mut PoolConnection, actor: Option<&str>) -> Result<Transaction<'c, Postgres>> {
+    let mut tx = conn.as_mut().begin().await?;
+    sqlx::query("SELECT set_config('forgettable.actor', $1, true)")
+        .bind(actor.unwrap_or_default())
+        .execute(&mut *tx)
+        .await?;
+    Ok(tx)
+}
@@ -0,0 +1,50 @@
+//! `BODY_READ_TIMEOUT_SECS`: a deadline for receiving the whole request body, so a client trickling an upload
+//! can't hold a connection open indefinitely.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::{StreamExt, stream};
+use tokio::time::{Instant, timeout_at};
+
+use crate::{config::Config, error::AppError};
+
+/// Fail the body's stream once the deadline passes, then answer `408` whatever the handler made of the failed read
+pub async fn limit_body_read_time(State(config): State<Arc<Config>>, request: Request, next: Next) -> Response {
+    let Some(timeout) = config.body_read_timeout else {
+        return next.run(request).await;
+    };
+    let deadline = Instant::now() + timeout;
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let flag = timed_out.clone();
+
+    let (parts, body) = request.into_parts();
+    // the stream is dropped after reporting the timeout, so it ends rather than being polled again
+    let frames = stream::unfold(Some(body.into_data_stream()), move |frames| {
+        let flag = flag.clone();
+        async move {
+            let mut frames = frames?;
+            match timeout_at(deadline, frames.next()).await {
+                Ok(frame) => frame.map(|frame| (frame, Some(frames))),
+                Err(_) => {
+                    flag.store(true, Ordering::Relaxed);
+                    Some((Err(axum::Error::new("timed out reading the request body")), None))
+                }
+            }
+        }
+    });
+    let response = next.run(Request::from_parts(parts, Body::from_stream(frames))).await;
+    if timed_out.load(Ordering::Relaxed) {
+        AppError::BodyTimeout(timeout.as_secs()).into_response()
+    } else {
+        response
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+
+use crate::config::Config;
+
+/// The resolved address of the client, inserted as a request extension by [`resolve_client_ip`]
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+pub async fn resolve_client_ip(State(config): State<Arc<Config>>, mut request: Request, next: Next) -> Response {
+    if let Some(ConnectInfo(peer)) = request.extensions().get::<ConnectInfo<SocketAddr>>().copied() {
+        let client_ip = ClientIp(client_ip(peer.ip(), request.headers(), &config.trusted_proxies));
+        tracing::Span::current().record("http.client.address", client_ip.to_string());
+        request.extensions_mut().insert(client_ip);
+    }
+    next.run(request).await
+}
+
+/// Work out the real client address.
+///
+/// Forwarding headers are only consulted when the socket peer is a trusted proxy, the chain is then walked
+/// from the right and the first hop which isn't itself a trusted proxy is taken as the client.
+fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    if !is_trusted(trusted_proxies, peer) {
+        return peer;
+    }
+
+    let mut client = peer;
+    for hop in forwarded_chain(headers).into_iter().rev() {
+        // an unparseable hop means we can't trust anything further left
+        let Some(ip) = hop else { break };
+        client = ip;
+        if !is_trusted(trusted_proxies, ip) {
+            break;
+        }
+    }
+    client
+}
+
+fn is_trusted(trusted_proxies: &[IpNet], ip: IpAddr) -> bool {
+    trusted_proxies.iter().any(|net| net.contains(&ip))
+}
+
+/// Addresses from `Forwarded` (preferred) or `X-Forwarded-For`, in the order they were appended
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<Option<IpAddr>> = header_elements(headers, "forwarded")
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.trim().split_once('='))
+                .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                .and_then(|(_, value)| parse_node(value))
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    header_elements(headers, "x-forwarded-for").map(parse_node).collect()
+}
+
+fn header_elements<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+}
+
+/// Parse a node which may be quoted and may include a port, e.g. `"[2001:db8::1]:4711"` or `192.0.2.60:80`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| node.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn nets(nets: &[&str]) -> Vec<IpNet> {
+        nets.iter().map(|net| net.parse().unwrap()).collect()
+    }
+
+    fn header_map(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_headers() {
+        let headers = header_map("x-forwarded-for", "203.0.113.7");
+        assert_eq!(
+            client_ip(ip("198.51.100.1"), &headers, &nets(&["10.0.0.0/8"])),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn no_trusted_proxies() {
+        let headers = header_map("forwarded", "for=203.0.113.7");
+        assert_eq!(client_ip(ip("10.0.0.1"), &headers, &[]), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn walk_stops_at_first_untrusted_hop() {
+        // the client can put anything on the left, only hops appended by our proxies are believed
+        let headers = header_map("x-forwarded-for", "192.0.2.99, 203.0.113.7, 10.0.0.2");
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &nets(&["10.0.0.0/8"])),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn all_hops_trusted() {
+        let headers = header_map("x-forwarded-for", "10.0.0.3, 10.0.0.2");
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &nets(&["10.0.0.0/8"])),
+            ip("10.0.0.3")
+        );
+    }
+
+    #[test]
+    fn forwarded_preferred_over_x_forwarded_for() {
+        let mut headers = header_map("forwarded", "for=203.0.113.7;proto=https");
+        headers.insert("x-forwarded-for", HeaderValue::from_static("192.0.2.99"));
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &nets(&["10.0.0.0/8"])),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn forwarded_bracketed_ipv6_with_port() {
+        let headers = header_map("forwarded", r#"for="[::1]:80""#);
+        assert_eq!(client_ip(ip("10.0.0.1"), &headers, &nets(&["10.0.0.0/8"])), ip("::1"));
+    }
+
+    #[test]
+    fn parse_nodes() {
+        assert_eq!(parse_node("192.0.2.60"), Some(ip("192.0.2.60")));
+        assert_eq!(parse_node("192.0.2.60:80"), Some(ip("192.0.2.60")));
+        assert_eq!(parse_node(r#""[2001:db8::1]:4711""#), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("[2001:db8::1]"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+    }
+
+    #[test]
+    fn unknown_node_stops_the_walk() {
+        // nothing left of an unparseable hop can be trusted, so the last trusted hop is taken
+        let headers = header_map("forwarded", "for=203.0.113.7, for=unknown, for=_hidden");
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &nets(&["10.0.0.0/8"])),
+            ip("10.0.0.1")
+        );
+        let headers = header_map("forwarded", "for=unknown, for=203.0.113.7");
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &nets(&["10.0.0.0/8"])),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn cidr_membership() {
+        let trusted = nets(&["10.0.0.0/8", "2001:db8::/32", "192.0.2.1/32"]);
+        assert!(is_trusted(&trusted, ip("10.255.0.1")));
+        assert!(!is_trusted(&trusted, ip("11.0.0.1")));
+        assert!(is_trusted(&trusted, ip("2001:db8:ffff::1")));
+        assert!(!is_trusted(&trusted, ip("2001:db9::1")));
+        assert!(is_trusted(&trusted, ip("192.0.2.1")));
+        assert!(!is_trusted(&trusted, ip("192.0.2.2")));
+    }
+}
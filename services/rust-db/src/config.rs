@@ -1,6 +1,9 @@
-use std::env;
+use std::{env, net::IpAddr, num::NonZeroU32, str::FromStr, time::Duration};
 
+use chrono::TimeDelta;
+use ipnet::IpNet;
 use thiserror::Error;
+use tracing::Level;
 
 #[derive(Error, Debug)]
 pub enum ConfigError {
@@ -8,11 +11,116 @@ pub enum ConfigError {
     MissingDatabaseUrl,
     #[error("PORT environment variable is not a valid number")]
     InvalidPort,
+    #[error("ADMIN_PORT environment variable is not a valid number")]
+    InvalidAdminPort,
+    #[error("{0} environment variable is not a valid number")]
+    InvalidNumber(&'static str),
+    #[error("{0} environment variable must be \"true\" or \"false\"")]
+    InvalidBool(&'static str),
+    #[error("ACCESS_LOG_LEVEL must be one of \"off\", \"error\", \"warn\", \"info\", \"debug\" or \"trace\"")]
+    InvalidAccessLogLevel,
+    #[error("LOG_FORMAT must be \"text\" or \"json\"")]
+    InvalidLogFormat,
+    #[error("TRACE_SAMPLE_RATIO must be a number from 0.0 to 1.0")]
+    InvalidTraceSampleRatio,
+    #[error("ALLOWED_MIME_TYPES must be a comma-separated list of mime types like \"image/png\" or \"image/*\"")]
+    InvalidAllowedMimeTypes,
+    #[error("MIME_SIZE_LIMITS must be a comma-separated list of mime-type=bytes rules like \"image/*=10485760\"")]
+    InvalidMimeSizeLimits,
+    #[error("PUBLIC_BASE_URL must start with \"http://\" or \"https://\"")]
+    InvalidPublicBaseUrl,
+    #[error("TRUSTED_PROXIES must be a comma-separated list of IP addresses or CIDR ranges")]
+    InvalidTrustedProxies,
 }
 
 pub struct Config {
     pub database_url: String,
     pub port: u16,
+    /// How many times to try connecting to the database at startup before giving up
+    pub db_connect_attempts: u32,
+    /// Delay before the first connection retry, doubled after each failed attempt
+    pub db_connect_interval: Duration,
+    /// Accept HTTP/2 (prior knowledge, h2c) as well as HTTP/1.1
+    pub http2: bool,
+    /// Keep HTTP/1.1 connections open between requests
+    pub keep_alive: bool,
+    /// Close HTTP/1.1 connections which don't send the next request's headers within this time
+    pub idle_timeout: Option<Duration>,
+    /// Interval between HTTP/2 keep-alive pings, connections which don't acknowledge a ping are closed
+    pub http2_keep_alive_interval: Option<Duration>,
+    /// Data routes taking longer than this return `504`, `None` disables the timeout
+    pub request_timeout: Option<Duration>,
+    /// Requests whose body hasn't all arrived after this long get `408`, `None` disables the timeout
+    pub body_read_timeout: Option<Duration>,
+    /// Requests spending longer than this in SQL queries are logged as a warning
+    pub slow_query: Option<Duration>,
+    /// How long to refuse new requests after a shutdown signal before closing the listener
+    pub shutdown_drain: Duration,
+    /// How often request counts are added to `project_usage`
+    pub usage_flush_interval: Duration,
+    /// When set, admin and health routes are served on this port instead of alongside the data API
+    pub admin_port: Option<u16>,
+    /// Level of the per-request access log, `None` disables it
+    pub access_log_level: Option<Level>,
+    /// Include request bodies in the access log
+    pub access_log_bodies: bool,
+    /// Write the access log to stdout as one JSON object per request (`LOG_FORMAT=json`), rather than as tracing
+    /// events
+    pub json_access_log: bool,
+    /// Fraction of requests traced when the caller didn't send a sampling decision, `None` traces them all
+    pub trace_sample_ratio: Option<f64>,
+    /// Scheme, host and any path prefix the service is reachable at, without a trailing slash, used to make
+    /// `Location` headers absolute
+    pub public_base_url: Option<String>,
+    /// Bearer token required by `/admin/` routes, they're unauthenticated when unset
+    pub admin_token: Option<String>,
+    /// Render error bodies as RFC 9457 `application/problem+json` rather than `{"error": ...}`
+    pub problem_json: bool,
+    /// Peers allowed to set `X-Forwarded-For`/`Forwarded`, empty means headers are never trusted
+    pub trusted_proxies: Vec<IpNet>,
+    /// Secret used to sign time-limited public URLs, signed URLs are disabled when unset
+    pub signing_key: Option<String>,
+    /// Mime types accepted by store, `type/*` matches any subtype, all types are accepted when unset
+    pub allowed_mime_types: Option<Vec<String>>,
+    /// Accept `X-Created-At`/`X-Updated-At` on store, for imports which keep entries' original timestamps
+    pub client_timestamps: bool,
+    /// Reject raw body stores without a `Content-Type` rather than defaulting to `application/octet-stream`
+    pub require_content_type: bool,
+    /// Reject stores of JSON entries whose content doesn't parse as JSON
+    pub validate_json: bool,
+    /// Largest entry store accepts, in bytes after decompression, unless a `mime_size_limits` rule matches
+    pub max_entry_size: usize,
+    /// Size limits for particular mime types, `type/*` matches any subtype, overriding `max_entry_size`
+    pub mime_size_limits: Vec<(String, usize)>,
+    /// Collapse repeated slashes and strip a leading slash from keys and prefixes in all key operations
+    pub normalize_keys: bool,
+    /// Entries expiring within this window get a `Sunset` header on get
+    pub sunset_window: TimeDelta,
+    /// Listing a project which doesn't exist returns 404 rather than an empty list
+    pub strict_project_check: bool,
+    /// Stores to a project which doesn't exist create it, otherwise they get 404 and projects must be created via
+    /// the admin API
+    pub auto_create_projects: bool,
+    /// Data API requests allowed in flight at once, beyond which requests get an immediate `503`
+    pub max_concurrent_requests: Option<usize>,
+    /// Stores to any one project allowed in flight at once, beyond which the project's stores get `429`
+    pub max_project_uploads: Option<usize>,
+    /// How long a resumable upload waits for its next chunk before it's abandoned
+    pub upload_expiry: Duration,
+    /// Stores leaving a project using at least this percentage of its `quota_bytes` get an `X-Quota-Warning`
+    /// header, `0` disables the warning
+    pub quota_warning_percent: u8,
+    /// Keep each project's entries in its own Postgres schema rather than the shared `public.entries`
+    pub tenant_schemas: bool,
+    /// Entries larger than this many bytes get a weak ETag from `updated_at` and size on get, rather than
+    /// hashing their content
+    pub weak_etag_threshold: i64,
+    /// Most keys a list returns, larger `?limit=`s are clamped to this
+    pub max_list_limit: NonZeroU32,
+    /// Order lists by the bytes of the key (`COLLATE "C"`) rather than the database's locale collation
+    pub list_collate_c: bool,
+    /// Generate entry ids as time-ordered UUIDv7s in Rust rather than random ones in Postgres
+    pub uuid_v7_ids: bool,
 }
 
 impl Config {
@@ -24,6 +132,279 @@ impl Config {
             .parse()
             .map_err(|_| ConfigError::InvalidPort)?;
 
-        Ok(Self { database_url, port })
+        let db_connect_attempts = number_var("DB_CONNECT_ATTEMPTS", 10)?;
+        let db_connect_interval = Duration::from_millis(number_var("DB_CONNECT_INTERVAL_MS", 500)?);
+
+        let http2 = bool_var("HTTP2", false)?;
+        let keep_alive = bool_var("KEEP_ALIVE", true)?;
+        let idle_timeout = optional_number_var("IDLE_TIMEOUT_SECS")?.map(Duration::from_secs);
+        let http2_keep_alive_interval = optional_number_var("HTTP2_KEEP_ALIVE_INTERVAL_SECS")?.map(Duration::from_secs);
+
+        let request_timeout = match number_var("REQUEST_TIMEOUT_MS", 30_000)? {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        };
+        let body_read_timeout = match number_var("BODY_READ_TIMEOUT_SECS", 300)? {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        let slow_query = optional_number_var("SLOW_QUERY_MS")?.map(Duration::from_millis);
+        let shutdown_drain = Duration::from_secs(number_var("SHUTDOWN_DRAIN_SECS", 0)?);
+
+        let usage_flush_interval = Duration::from_secs(number_var("USAGE_FLUSH_SECS", 10)?.max(1));
+
+        let admin_port = env::var("ADMIN_PORT")
+            .ok()
+            .map(|v| v.parse().map_err(|_| ConfigError::InvalidAdminPort))
+            .transpose()?;
+
+        let access_log_level = match env::var("ACCESS_LOG_LEVEL") {
+            Ok(v) if v.eq_ignore_ascii_case("off") => None,
+            Ok(v) => Some(v.parse().map_err(|_| ConfigError::InvalidAccessLogLevel)?),
+            Err(_) => Some(Level::INFO),
+        };
+        let access_log_bodies = bool_var("ACCESS_LOG_BODIES", false)?;
+        let json_access_log = match env::var("LOG_FORMAT") {
+            Ok(v) if v.eq_ignore_ascii_case("json") => true,
+            Ok(v) if v.eq_ignore_ascii_case("text") => false,
+            Ok(_) => return Err(ConfigError::InvalidLogFormat),
+            Err(_) => false,
+        };
+        let trace_sample_ratio = match optional_number_var::<f64>("TRACE_SAMPLE_RATIO") {
+            Ok(Some(ratio)) if (0.0..=1.0).contains(&ratio) => (ratio < 1.0).then_some(ratio),
+            Ok(None) => None,
+            _ => return Err(ConfigError::InvalidTraceSampleRatio),
+        };
+
+        let trusted_proxies = env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(parse_ip_net)
+            .collect::<Option<Vec<_>>>()
+            .ok_or(ConfigError::InvalidTrustedProxies)?;
+
+        let public_base_url = env::var("PUBLIC_BASE_URL")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(|v| {
+                if v.starts_with("http://") || v.starts_with("https://") {
+                    Ok(v.trim_end_matches('/').to_string())
+                } else {
+                    Err(ConfigError::InvalidPublicBaseUrl)
+                }
+            })
+            .transpose()?;
+
+        let problem_json = bool_var("PROBLEM_JSON", false)?;
+
+        let admin_token = env::var("ADMIN_TOKEN").ok().filter(|v| !v.is_empty());
+
+        let signing_key = env::var("SIGNING_KEY").ok().filter(|v| !v.is_empty());
+
+        let allowed_mime_types = env::var("ALLOWED_MIME_TYPES")
+            .ok()
+            .map(|v| {
+                v.split(',')
+                    .map(|s| s.trim().to_lowercase())
+                    .filter(|s| !s.is_empty())
+                    .map(|s| if s.contains('/') { Some(s) } else { None })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or(ConfigError::InvalidAllowedMimeTypes)
+            })
+            .transpose()?;
+
+        let client_timestamps = bool_var("CLIENT_TIMESTAMPS", false)?;
+
+        let require_content_type = bool_var("REQUIRE_CONTENT_TYPE", false)?;
+
+        let validate_json = bool_var("VALIDATE_JSON", false)?;
+
+        let max_entry_size = number_var("MAX_ENTRY_SIZE", 2 * 1024 * 1024)?;
+
+        let mime_size_limits = env::var("MIME_SIZE_LIMITS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|rule| {
+                let (pattern, size) = rule.split_once('=')?;
+                let pattern = pattern.trim().to_lowercase();
+                let size = size.trim().parse().ok()?;
+                pattern.contains('/').then_some((pattern, size))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(ConfigError::InvalidMimeSizeLimits)?;
+
+        let normalize_keys = bool_var("NORMALIZE_KEYS", false)?;
+
+        let strict_project_check = bool_var("STRICT_PROJECT_CHECK", false)?;
+        let auto_create_projects = bool_var("AUTO_CREATE_PROJECTS", true)?;
+
+        let max_concurrent_requests = optional_number_var("MAX_CONCURRENT_REQUESTS")?;
+
+        let max_project_uploads = optional_number_var("MAX_PROJECT_UPLOADS")?;
+        let upload_expiry = Duration::from_secs(number_var("UPLOAD_EXPIRY_SECS", 86_400)?);
+
+        let quota_warning_percent = number_var("QUOTA_WARNING_PERCENT", 90)?;
+
+        let tenant_schemas = bool_var("TENANT_SCHEMAS", false)?;
+
+        let weak_etag_threshold = number_var("WEAK_ETAG_THRESHOLD", 1024 * 1024)?;
+
+        let max_list_limit = number_var("MAX_LIST_LIMIT", NonZeroU32::new(1000).expect("1000 is non-zero"))?;
+
+        let sunset_window = TimeDelta::seconds(number_var("SUNSET_WINDOW_SECS", 86_400)?);
+
+        let list_collate_c = bool_var("LIST_COLLATE_C", false)?;
+
+        let uuid_v7_ids = bool_var("UUID_V7_IDS", false)?;
+
+        Ok(Self {
+            database_url,
+            port,
+            db_connect_attempts,
+            db_connect_interval,
+            http2,
+            keep_alive,
+            idle_timeout,
+            http2_keep_alive_interval,
+            request_timeout,
+            body_read_timeout,
+            slow_query,
+            shutdown_drain,
+            usage_flush_interval,
+            admin_port,
+            access_log_level,
+            access_log_bodies,
+            json_access_log,
+            trace_sample_ratio,
+            public_base_url,
+            admin_token,
+            problem_json,
+            trusted_proxies,
+            signing_key,
+            allowed_mime_types,
+            client_timestamps,
+            require_content_type,
+            validate_json,
+            max_entry_size,
+            mime_size_limits,
+            normalize_keys,
+            sunset_window,
+            strict_project_check,
+            auto_create_projects,
+            max_concurrent_requests,
+            max_project_uploads,
+            upload_expiry,
+            quota_warning_percent,
+            tenant_schemas,
+            weak_etag_threshold,
+            max_list_limit,
+            list_collate_c,
+            uuid_v7_ids,
+        })
+    }
+
+    /// Check a `Content-Type` against `ALLOWED_MIME_TYPES`, parameters like `charset` are ignored
+    pub fn is_mime_type_allowed(&self, mime_type: &str) -> bool {
+        let Some(allowed) = &self.allowed_mime_types else {
+            return true;
+        };
+        let essence = mime_essence(mime_type);
+        allowed.iter().any(|pattern| mime_matches(pattern, &essence))
+    }
+
+    /// Largest entry allowed for a mime type: an exact `MIME_SIZE_LIMITS` rule, else the first matching wildcard,
+    /// else `MAX_ENTRY_SIZE`
+    pub fn entry_size_limit(&self, mime_type: &str) -> usize {
+        let essence = mime_essence(mime_type);
+        self.mime_size_limits
+            .iter()
+            .find(|(pattern, _)| *pattern == essence)
+            .or_else(|| {
+                self.mime_size_limits
+                    .iter()
+                    .find(|(pattern, _)| mime_matches(pattern, &essence))
+            })
+            .map_or(self.max_entry_size, |(_, size)| *size)
+    }
+
+    /// Largest body any store could need, the request body limit for data routes
+    pub fn max_body_size(&self) -> usize {
+        self.mime_size_limits
+            .iter()
+            .map(|(_, size)| *size)
+            .fold(self.max_entry_size, usize::max)
+    }
+
+    /// With `NORMALIZE_KEYS`, `/foo//bar` and `foo/bar` refer to the same key
+    pub fn normalize_key(&self, key: String) -> String {
+        if !self.normalize_keys {
+            return key;
+        }
+        let mut normalized = String::with_capacity(key.len());
+        for c in key.chars() {
+            if !(c == '/' && normalized.ends_with('/')) {
+                normalized.push(c);
+            }
+        }
+        match normalized.strip_prefix('/') {
+            Some(stripped) => stripped.to_string(),
+            None => normalized,
+        }
+    }
+}
+
+/// Lowercase mime type without parameters like `charset`
+pub fn mime_essence(mime_type: &str) -> String {
+    mime_type.split(';').next().unwrap_or_default().trim().to_lowercase()
+}
+
+/// `application/json` or a `+json` type like `application/ld+json`
+pub fn is_json_mime(mime_type: &str) -> bool {
+    let essence = mime_essence(mime_type);
+    essence == "application/json" || essence.ends_with("+json")
+}
+
+/// Match a mime type essence against `type/subtype`, `type/*` or `*/*`
+pub fn mime_matches(pattern: &str, essence: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some("*") => true,
+        Some(type_) => essence.split_once('/').is_some_and(|(t, _)| t == type_),
+        None => pattern == essence,
     }
 }
+
+fn number_var<T: FromStr>(name: &'static str, default: T) -> Result<T, ConfigError> {
+    match env::var(name) {
+        Ok(v) => v.parse().map_err(|_| ConfigError::InvalidNumber(name)),
+        Err(_) => Ok(default),
+    }
+}
+
+fn optional_number_var<T: FromStr>(name: &'static str) -> Result<Option<T>, ConfigError> {
+    env::var(name)
+        .ok()
+        .map(|v| v.parse().map_err(|_| ConfigError::InvalidNumber(name)))
+        .transpose()
+}
+
+fn bool_var(name: &'static str, default: bool) -> Result<bool, ConfigError> {
+    match env::var(name) {
+        Ok(v) => match v.to_lowercase().as_str() {
+            "true" | "1" => Ok(true),
+            "false" | "0" => Ok(false),
+            _ => Err(ConfigError::InvalidBool(name)),
+        },
+        Err(_) => Ok(default),
+    }
+}
+
+/// Parse either a CIDR range (`10.0.0.0/8`) or a bare address (`10.0.0.1`)
+fn parse_ip_net(s: &str) -> Option<IpNet> {
+    s.parse::<IpNet>()
+        .ok()
+        .or_else(|| s.parse::<IpAddr>().ok().map(IpNet::from))
+}
@@ -1,28 +1,180 @@
+use std::sync::Arc;
+
 use axum::{
     Json,
-    http::StatusCode,
+    body::Body,
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::Next,
     response::{IntoResponse, Response},
 };
 use thiserror::Error;
 
+use crate::config::Config;
+
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
 
-    #[error("Key not found: {0}")]
-    KeyNotFound(String),
+    #[error("Project not found: {0}")]
+    ProjectNotFound(uuid::Uuid),
+
+    #[error("Key not found: {key} in project {project}")]
+    KeyNotFound { project: uuid::Uuid, key: String },
+
+    #[error("Upload not found: {0}")]
+    UploadNotFound(uuid::Uuid),
+
+    #[error("Validation error: {0}")]
+    Validation(String),
+
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Precondition failed: {0}")]
+    PreconditionFailed(String),
+
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    #[error("Project {0} is over its storage quota")]
+    QuotaExceeded(uuid::Uuid),
+
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    #[error("Internal error: {0}")]
+    Internal(String),
+
+    #[error("Request timed out after {0}ms")]
+    Timeout(u128),
+
+    #[error("Timed out after {0}s reading the request body")]
+    BodyTimeout(u64),
+
+    #[error("Too many concurrent requests, try again later")]
+    Overloaded,
+
+    #[error("Too many concurrent uploads to project {0}, try again later")]
+    ProjectBusy(uuid::Uuid),
+
+    #[error("Shutting down, try again later")]
+    ShuttingDown,
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Database(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ProjectNotFound(_) | Self::KeyNotFound { .. } | Self::UploadNotFound(_) => StatusCode::NOT_FOUND,
+            Self::Validation(_) | Self::InvalidKey(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::QuotaExceeded(_) => StatusCode::INSUFFICIENT_STORAGE,
+            Self::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Self::BodyTimeout(_) => StatusCode::REQUEST_TIMEOUT,
+            Self::ProjectBusy(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Overloaded | Self::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// Members added to JSON error bodies alongside the message, so clients needn't parse it
+    fn details(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut details = serde_json::Map::new();
+        if let Self::KeyNotFound { project, key } = self {
+            details.insert("project".to_string(), project.to_string().into());
+            details.insert("key".to_string(), key.clone().into());
+        }
+        details
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, message) = match &self {
-            Self::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
-            Self::KeyNotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
-        };
+        let message = self.to_string();
+        let details = self.details();
+        let mut body = serde_json::Map::from_iter([("error".to_string(), message.clone().into())]);
+        body.extend(details.clone());
+        let mut response = (self.status(), Json(body)).into_response();
+        response.extensions_mut().insert(ErrorMessage { message, details });
+        response
+    }
+}
+
+/// Message and details of an [`AppError`] response, lets [`negotiate_error_body`] re-render it after the fact
+#[derive(Clone)]
+struct ErrorMessage {
+    message: String,
+    details: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Re-render error responses: with the bare message as plain text when the client asks for `text/plain`, or as
+/// `application/problem+json` (RFC 9457, formerly 7807) with `PROBLEM_JSON`. Only the body and its `Content-Type`
+/// change, headers set along with the error (e.g. `Connection: close` while draining) are kept.
+pub async fn negotiate_error_body(State(config): State<Arc<Config>>, request: Request, next: Next) -> Response {
+    let wants_text = prefers_text(request.headers());
+    let path = request.uri().path().to_string();
+    let response = next.run(request).await;
+    let Some(ErrorMessage { message, details }) = response.extensions().get::<ErrorMessage>().cloned() else {
+        return response;
+    };
+    let status = response.status();
+    let (content_type, body) = if wants_text {
+        ("text/plain; charset=utf-8", message)
+    } else if config.problem_json {
+        let mut problem = serde_json::json!({
+            "type": "about:blank",
+            "title": status.canonical_reason().unwrap_or_default(),
+            "status": status.as_u16(),
+            "detail": message,
+            "instance": path,
+        });
+        // extension members, as RFC 9457 allows
+        if let Some(problem) = problem.as_object_mut() {
+            problem.extend(details);
+        }
+        ("application/problem+json", problem.to_string())
+    } else {
+        return response;
+    };
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    Response::from_parts(parts, Body::from(body))
+}
 
-        (status, Json(serde_json::json!({ "error": message }))).into_response()
+/// `Accept` includes `text/plain` but not JSON, anything else (including no header or `*/*`) gets JSON
+fn prefers_text(headers: &HeaderMap) -> bool {
+    let mut text = false;
+    for range in headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+    {
+        match range.split(';').next().unwrap_or_default().trim() {
+            "text/plain" => text = true,
+            "application/json" => return false,
+            _ => {}
+        }
     }
+    text
 }
 
 pub type Result<T> = std::result::Result<T, AppError>;
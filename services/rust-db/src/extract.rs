@@ -0,0 +1,36 @@
+use axum::{
+    extract::{FromRequestParts, path::ErrorKind, rejection::PathRejection},
+    http::request::Parts,
+};
+use serde::de::DeserializeOwned;
+
+use crate::error::AppError;
+
+/// Drop-in for [`axum::extract::Path`] which rejects with [`AppError`], so bad path segments get our
+/// usual `{"error": ...}` body
+pub struct Path<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for Path<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match axum::extract::Path::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Path(value)) => Ok(Self(value)),
+            Err(rejection) => Err(path_error(rejection)),
+        }
+    }
+}
+
+fn path_error(rejection: PathRejection) -> AppError {
+    if let PathRejection::FailedToDeserializePathParams(e) = &rejection
+        && let ErrorKind::DeserializeError { key, .. } | ErrorKind::ParseErrorAtKey { key, .. } = e.kind()
+        && key == "project"
+    {
+        return AppError::Validation("invalid project id, expected a UUID".to_string());
+    }
+    AppError::Validation(rejection.body_text())
+}
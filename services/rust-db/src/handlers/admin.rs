@@ -0,0 +1,645 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::Instant,
+};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{HeaderMap, HeaderName, StatusCode, header},
+};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    audit,
+    config::Config,
+    error::{AppError, Result},
+    extract::Path,
+    handlers::entries,
+    like::escape_like_prefix,
+    models::{
+        CollisionGroup, DEFAULT_NAMESPACE, FindKeyQuery, KeyCollisions, KeyInfo, KeyLocation, KeyLocations,
+        MaintenanceQuery, MaintenanceReport, MergeConflict, MergeReport, MergeRequest, MultiListRequest, Project,
+        ProjectSettings, RouteTiming,
+    },
+    state::Pool,
+    telemetry::{self, DbTimer},
+    tenant::{self, Access},
+    timings,
+};
+
+/// Delete an entry even if it's immutable
+pub async fn force_delete_entry(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Path((project, key)): Path<(Uuid, String)>,
+    headers: HeaderMap,
+) -> Result<StatusCode> {
+    let key = config.normalize_key(key);
+    telemetry::record_key(project, &key);
+    let actor = audit::actor(&headers)?;
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    let mut db = DbTimer::default();
+    let mut tx = db.time(audit::begin(&mut conn, actor.as_deref())).await?;
+    let result = db
+        .time(
+            sqlx::query("DELETE FROM entries WHERE project_id = $1 AND namespace = $2 AND key = $3")
+                .bind(project)
+                .bind(DEFAULT_NAMESPACE)
+                .bind(&key)
+                .execute(&mut *tx),
+        )
+        .await?;
+    db.time(tx.commit()).await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::KeyNotFound { project, key });
+    }
+
+    logfire::info!(
+        "force deleted entry project={project} key={key} actor={actor:?} db_ms={db_ms}",
+        project = project.to_string(),
+        key = &key,
+        actor = actor,
+        db_ms = db.ms(),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Report groups of live keys in a project which collide under case folding, or under `NORMALIZE_KEYS`' slash
+/// normalization, so they can be cleaned up before enabling either; the later store of a colliding key would
+/// overwrite the others.
+pub async fn key_collisions(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Path(project): Path<Uuid>,
+) -> Result<Json<KeyCollisions>> {
+    telemetry::record_prefix(project, "");
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    let mut db = DbTimer::default();
+    let case_folding = db
+        .time(
+            sqlx::query_as!(
+                CollisionGroup,
+                r#"
+        SELECT namespace, lower(key) AS "normalized!", array_agg(key ORDER BY key) AS "keys!"
+        FROM entries
+        WHERE project_id = $1 AND (expires_at IS NULL OR expires_at > NOW())
+        GROUP BY namespace, lower(key)
+        HAVING count(*) > 1
+        ORDER BY namespace, lower(key)
+        "#,
+                project
+            )
+            .fetch_all(&mut conn),
+        )
+        .await?;
+    // the same as `Config::normalize_key`, collapse repeated slashes then strip a leading slash
+    let slash_normalization = db
+        .time(
+            sqlx::query_as!(
+                CollisionGroup,
+                r#"
+        WITH normalized AS (
+            SELECT namespace, key, regexp_replace(regexp_replace(key, '/{2,}', '/', 'g'), '^/', '') AS normalized
+            FROM entries
+            WHERE project_id = $1 AND (expires_at IS NULL OR expires_at > NOW())
+        )
+        SELECT namespace, normalized AS "normalized!", array_agg(key ORDER BY key) AS "keys!"
+        FROM normalized
+        GROUP BY namespace, normalized
+        HAVING count(*) > 1
+        ORDER BY namespace, normalized
+        "#,
+                project
+            )
+            .fetch_all(&mut conn),
+        )
+        .await?;
+
+    logfire::info!(
+        "reported key collisions project={project} case_groups={case_groups} slash_groups={slash_groups} db_ms={db_ms}",
+        project = project.to_string(),
+        case_groups = case_folding.len(),
+        slash_groups = slash_normalization.len(),
+        db_ms = db.ms(),
+    );
+    Ok(Json(KeyCollisions {
+        case_folding,
+        slash_normalization,
+    }))
+}
+
+/// Find every project (and namespace) with a live entry under exactly `?key=`, for tracking down where data lives
+/// when only the key is known.
+///
+/// With `TENANT_SCHEMAS` every project schema's entries are searched along with `public.entries`.
+pub async fn find_key(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<FindKeyQuery>,
+) -> Result<Json<KeyLocations>> {
+    let limit = match query.limit {
+        Some(0) => return Err(AppError::Validation("limit must be at least 1".to_string())),
+        Some(limit) => limit.min(config.max_list_limit.get()),
+        None => config.max_list_limit.get(),
+    };
+    let key = config.normalize_key(query.key);
+    let mut conn = pool.acquire().await?;
+    let mut db = DbTimer::default();
+    let tables = db.time(tenant::entries_tables(conn.as_mut(), &config)).await?;
+    let searches: Vec<String> = tables
+        .iter()
+        .map(|table| {
+            format!(
+                "SELECT id, project_id, namespace, key FROM {table} \
+                 WHERE key = $1 AND (expires_at IS NULL OR expires_at > NOW()) AND ($2::uuid IS NULL OR id > $2)"
+            )
+        })
+        .collect();
+    // one extra row tells us whether there's another page
+    let rows: Vec<(Uuid, Uuid, String, String)> = db
+        .time(
+            sqlx::query_as(&format!(
+                "SELECT * FROM ({}) found ORDER BY id LIMIT $3",
+                searches.join(" UNION ALL ")
+            ))
+            .persistent(false)
+            .bind(&key)
+            .bind(query.after)
+            .bind(i64::from(limit) + 1)
+            .fetch_all(&mut conn),
+        )
+        .await?;
+    let mut matches: Vec<KeyLocation> = rows
+        .into_iter()
+        .map(|(id, project_id, namespace, key)| KeyLocation {
+            id,
+            project_id,
+            namespace,
+            key,
+        })
+        .collect();
+
+    let next = if matches.len() > limit as usize {
+        matches.truncate(limit as usize);
+        matches.last().map(|location| location.id)
+    } else {
+        None
+    };
+
+    logfire::info!(
+        "found key key={key} count={count} db_ms={db_ms}",
+        key = &key,
+        count = matches.len(),
+        db_ms = db.ms(),
+    );
+    Ok(Json(KeyLocations { matches, next }))
+}
+
+/// Most projects `list_projects` accepts in one request
+const MAX_LISTED_PROJECTS: usize = 100;
+
+/// List live keys under a prefix in several projects at once, up to `limit` per project, for cross-project
+/// dashboards. Projects without matching keys map to an empty list.
+///
+/// With `TENANT_SCHEMAS` the requested projects' schemas are read along with `public.entries`.
+pub async fn list_projects(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Json(request): Json<MultiListRequest>,
+) -> Result<Json<BTreeMap<Uuid, Vec<KeyInfo>>>> {
+    if request.projects.len() > MAX_LISTED_PROJECTS {
+        return Err(AppError::Validation(format!(
+            "at most {MAX_LISTED_PROJECTS} projects can be listed at once"
+        )));
+    }
+    let limit = match request.limit {
+        Some(0) => return Err(AppError::Validation("limit must be at least 1".to_string())),
+        Some(limit) => limit.min(config.max_list_limit.get()),
+        None => config.max_list_limit.get(),
+    };
+    let prefix = config.normalize_key(request.prefix);
+    let mut conn = pool.acquire().await?;
+    let mut db = DbTimer::default();
+    let requested: BTreeSet<String> = request
+        .projects
+        .iter()
+        .map(|project| format!("{}.entries", tenant::schema_name(*project)))
+        .collect();
+    let mut tables = db.time(tenant::entries_tables(conn.as_mut(), &config)).await?;
+    tables.retain(|table| table == "public.entries" || requested.contains(table));
+    let lists: Vec<String> = tables
+        .iter()
+        .map(|table| {
+            format!(
+                "SELECT project_id, key, mime_type, octet_length(content)::bigint AS size FROM {table} \
+                 WHERE project_id = ANY($1) AND namespace = $2 AND key LIKE $3 \
+                 AND (expires_at IS NULL OR expires_at > NOW())"
+            )
+        })
+        .collect();
+    let rows: Vec<(Uuid, String, String, i64)> = db
+        .time(
+            sqlx::query_as(&format!(
+                "SELECT project_id, key, mime_type, size FROM ( \
+                 SELECT *, row_number() OVER (PARTITION BY project_id ORDER BY key) AS rank FROM ({}) listed \
+                 ) ranked WHERE rank <= $4 ORDER BY project_id, key",
+                lists.join(" UNION ALL ")
+            ))
+            .persistent(false)
+            .bind(&request.projects)
+            .bind(&request.namespace)
+            .bind(escape_like_prefix(&prefix))
+            .bind(i64::from(limit))
+            .fetch_all(&mut conn),
+        )
+        .await?;
+
+    let mut listed: BTreeMap<Uuid, Vec<KeyInfo>> =
+        request.projects.iter().map(|project| (*project, Vec::new())).collect();
+    for (project, key, mime_type, size) in rows {
+        listed
+            .entry(project)
+            .or_default()
+            .push(KeyInfo { key, mime_type, size });
+    }
+
+    logfire::info!(
+        "listed projects projects={projects} prefix={prefix} db_ms={db_ms}",
+        projects = listed.len(),
+        prefix = &prefix,
+        db_ms = db.ms(),
+    );
+    Ok(Json(listed))
+}
+
+/// Replace a project's settings, creating the project if it doesn't exist; `201` if it was created and `200` if it
+/// already existed
+pub async fn set_project_settings(
+    State(pool): State<Pool>,
+    Path(project): Path<Uuid>,
+    Json(settings): Json<ProjectSettings>,
+) -> Result<(StatusCode, Json<Project>)> {
+    let fallback_status = check_settings(&settings)?;
+    let mut db = DbTimer::default();
+    let (inserted, created_at): (bool, DateTime<Utc>) = db
+        .time(
+            sqlx::query_as(
+                r#"
+        INSERT INTO projects (
+            id, name, description, default_ttl_secs, quota_bytes, index_key, fallback_key, fallback_status,
+            key_pattern
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (id) DO UPDATE SET
+            name = EXCLUDED.name,
+            description = EXCLUDED.description,
+            default_ttl_secs = EXCLUDED.default_ttl_secs,
+            quota_bytes = EXCLUDED.quota_bytes,
+            index_key = EXCLUDED.index_key,
+            fallback_key = EXCLUDED.fallback_key,
+            fallback_status = EXCLUDED.fallback_status,
+            key_pattern = EXCLUDED.key_pattern
+        RETURNING (xmax = 0) AS inserted, created_at
+        "#,
+            )
+            .bind(project)
+            .bind(settings.name.as_deref())
+            .bind(settings.description.as_deref())
+            .bind(settings.default_ttl_secs)
+            .bind(settings.quota_bytes)
+            .bind(settings.index_key.as_deref())
+            .bind(settings.fallback_key.as_deref())
+            .bind(fallback_status)
+            .bind(settings.key_pattern.as_deref())
+            .fetch_one(&*pool),
+        )
+        .await?;
+
+    logfire::info!(
+        "updated project settings project={project} inserted={inserted} default_ttl_secs={default_ttl_secs:?} \
+         quota_bytes={quota_bytes:?} db_ms={db_ms}",
+        project = project.to_string(),
+        inserted = inserted,
+        default_ttl_secs = settings.default_ttl_secs,
+        quota_bytes = settings.quota_bytes,
+        db_ms = db.ms(),
+    );
+    let status = if inserted { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((
+        status,
+        Json(Project {
+            id: project,
+            name: settings.name,
+            description: settings.description,
+            default_ttl_secs: settings.default_ttl_secs,
+            quota_bytes: settings.quota_bytes,
+            index_key: settings.index_key,
+            fallback_key: settings.fallback_key,
+            fallback_status: settings.fallback_status,
+            key_pattern: settings.key_pattern,
+            created_at,
+        }),
+    ))
+}
+
+/// Create a project with an id chosen by the service, a time-ordered UUIDv7 with `UUID_V7_IDS`, otherwise a random
+/// one from Postgres; `201` with the new project, and its settings' path (absolute with `PUBLIC_BASE_URL`) as
+/// `Location`
+pub async fn create_project(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Json(settings): Json<ProjectSettings>,
+) -> Result<(StatusCode, [(HeaderName, String); 1], Json<Project>)> {
+    let fallback_status = check_settings(&settings)?;
+    let mut db = DbTimer::default();
+    let (id, created_at): (Uuid, DateTime<Utc>) = db
+        .time(
+            sqlx::query_as(
+                r#"
+        INSERT INTO projects (
+            id, name, description, default_ttl_secs, quota_bytes, index_key, fallback_key, fallback_status,
+            key_pattern
+        )
+        VALUES (COALESCE($1, gen_random_uuid()), $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING id, created_at
+        "#,
+            )
+            .bind(config.uuid_v7_ids.then(Uuid::now_v7))
+            .bind(settings.name.as_deref())
+            .bind(settings.description.as_deref())
+            .bind(settings.default_ttl_secs)
+            .bind(settings.quota_bytes)
+            .bind(settings.index_key.as_deref())
+            .bind(settings.fallback_key.as_deref())
+            .bind(fallback_status)
+            .bind(settings.key_pattern.as_deref())
+            .fetch_one(&*pool),
+        )
+        .await?;
+
+    logfire::info!(
+        "created project project={project} db_ms={db_ms}",
+        project = id.to_string(),
+        db_ms = db.ms(),
+    );
+    let base = config.public_base_url.as_deref().unwrap_or_default();
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, format!("{base}/project/{id}"))],
+        Json(Project {
+            id,
+            name: settings.name,
+            description: settings.description,
+            default_ttl_secs: settings.default_ttl_secs,
+            quota_bytes: settings.quota_bytes,
+            index_key: settings.index_key,
+            fallback_key: settings.fallback_key,
+            fallback_status: settings.fallback_status,
+            key_pattern: settings.key_pattern,
+            created_at,
+        }),
+    ))
+}
+/// Validate project settings, returning `fallback_status` as stored
+fn check_settings(settings: &ProjectSettings) -> Result<Option<i16>> {
+    if settings.default_ttl_secs.is_some_and(|ttl| ttl < 0) {
+        return Err(AppError::Validation(
+            "default_ttl_secs must be a non-negative integer or null".to_string(),
+        ));
+    }
+    if settings.quota_bytes.is_some_and(|quota| quota < 0) {
+        return Err(AppError::Validation(
+            "quota_bytes must be a non-negative integer or null".to_string(),
+        ));
+    }
+    if settings.index_key.as_deref().is_some_and(str::is_empty)
+        || settings.fallback_key.as_deref().is_some_and(str::is_empty)
+    {
+        return Err(AppError::Validation(
+            "index_key and fallback_key must not be empty".to_string(),
+        ));
+    }
+    if let Some(pattern) = &settings.key_pattern {
+        Regex::new(pattern).map_err(|e| AppError::Validation(format!("key_pattern is invalid: {e}")))?;
+    }
+    let fallback_status = match settings.fallback_status {
+        Some(_) if settings.fallback_key.is_none() => {
+            return Err(AppError::Validation(
+                "fallback_status requires a fallback_key".to_string(),
+            ));
+        }
+        Some(status) if !(200..=599).contains(&status) => {
+            return Err(AppError::Validation(
+                "fallback_status must be from 200 to 599".to_string(),
+            ));
+        }
+        status => status
+            .map(i16::try_from)
+            .transpose()
+            .expect("checked to be at most 599"),
+    };
+    Ok(fallback_status)
+}
+
+/// Move every entry of `from_project` into `to_project` in one transaction, for consolidating tenants.
+///
+/// Entries keep their ids, variants and `created_at`, while `updated_at` is bumped so the destination's changes
+/// feed picks them up. Keys live in both projects are handled as `on_conflict` says, expired entries never
+/// conflict and are dropped instead. The destination's quota applies to the result.
+///
+/// With `TENANT_SCHEMAS` the projects' entries are in different schemas, so merging is refused.
+pub async fn merge_projects(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Json(request): Json<MergeRequest>,
+) -> Result<Json<MergeReport>> {
+    let MergeRequest {
+        from_project: from,
+        to_project: to,
+        on_conflict,
+    } = request;
+    if config.tenant_schemas {
+        return Err(AppError::Validation(
+            "projects can't be merged with TENANT_SCHEMAS".to_string(),
+        ));
+    }
+    if from == to {
+        return Err(AppError::Validation(
+            "from_project and to_project must be different".to_string(),
+        ));
+    }
+    let actor = audit::actor(&headers)?;
+    let mut conn = pool.acquire().await?;
+    let mut db = DbTimer::default();
+    let mut tx = db.time(audit::begin(&mut conn, actor.as_deref())).await?;
+
+    if !entries::project_exists(&mut tx, &mut db, from).await? {
+        return Err(AppError::ProjectNotFound(from));
+    }
+    if config.auto_create_projects {
+        db.time(
+            sqlx::query("INSERT INTO projects (id) VALUES ($1) ON CONFLICT (id) DO NOTHING")
+                .bind(to)
+                .execute(&mut *tx),
+        )
+        .await?;
+    } else if !entries::project_exists(&mut tx, &mut db, to).await? {
+        return Err(AppError::ProjectNotFound(to));
+    }
+
+    // an expired entry sharing its key with one in the other project is already gone as far as clients can tell
+    db.time(
+        sqlx::query(
+            r#"
+        DELETE FROM entries
+        USING entries other
+        WHERE entries.project_id IN ($1, $2)
+            AND other.project_id = CASE WHEN entries.project_id = $1 THEN $2 ELSE $1 END
+            AND other.namespace = entries.namespace
+            AND other.key = entries.key
+            AND entries.expires_at <= NOW()
+        "#,
+        )
+        .bind(from)
+        .bind(to)
+        .execute(&mut *tx),
+    )
+    .await?;
+
+    let conflicts: i64 = db
+        .time(
+            sqlx::query_scalar(
+                r#"
+        SELECT count(*)
+        FROM entries source
+        JOIN entries dest ON dest.project_id = $2 AND dest.namespace = source.namespace AND dest.key = source.key
+        WHERE source.project_id = $1
+        "#,
+            )
+            .bind(from)
+            .bind(to)
+            .fetch_one(&mut *tx),
+        )
+        .await?;
+    let conflicts = conflicts as u64;
+    let (overwritten, skipped) = match on_conflict {
+        MergeConflict::Fail if conflicts > 0 => {
+            return Err(AppError::Conflict(format!("{conflicts} keys exist in both projects")));
+        }
+        MergeConflict::Fail => (0, 0),
+        MergeConflict::Skip => (0, conflicts),
+        MergeConflict::Overwrite => {
+            let result = db
+                .time(
+                    sqlx::query(
+                        r#"
+        DELETE FROM entries dest
+        USING entries source
+        WHERE dest.project_id = $2
+            AND source.project_id = $1
+            AND source.namespace = dest.namespace
+            AND source.key = dest.key
+        "#,
+                    )
+                    .bind(from)
+                    .bind(to)
+                    .execute(&mut *tx),
+                )
+                .await?;
+            (result.rows_affected(), 0)
+        }
+    };
+
+    let moved = db
+        .time(
+            sqlx::query(
+                r#"
+        UPDATE entries source
+        SET project_id = $2, updated_at = NOW()
+        WHERE source.project_id = $1
+            AND NOT EXISTS (
+                SELECT 1 FROM entries dest
+                WHERE dest.project_id = $2 AND dest.namespace = source.namespace AND dest.key = source.key
+            )
+        "#,
+            )
+            .bind(from)
+            .bind(to)
+            .execute(&mut *tx),
+        )
+        .await?
+        .rows_affected();
+    entries::check_quota(&mut tx, &mut db, &config, to).await?;
+    db.time(tx.commit()).await?;
+
+    logfire::info!(
+        "merged projects from={from} to={to} moved={moved} overwritten={overwritten} skipped={skipped} \
+         actor={actor:?} db_ms={db_ms}",
+        from = from.to_string(),
+        to = to.to_string(),
+        moved = moved,
+        overwritten = overwritten,
+        skipped = skipped,
+        actor = actor,
+        db_ms = db.ms(),
+    );
+    Ok(Json(MergeReport {
+        moved,
+        overwritten,
+        skipped,
+    }))
+}
+
+/// Request counts, latency percentiles and error rates per route since startup, slowest first
+pub async fn timings() -> Json<Vec<RouteTiming>> {
+    Json(timings::summary())
+}
+
+/// Held while maintenance runs, so concurrent requests get a 409 rather than queueing up vacuums
+static MAINTENANCE: Mutex<()> = Mutex::const_new(());
+
+/// Run `VACUUM (ANALYZE)` on entries, then `REINDEX ... CONCURRENTLY` with `?reindex=true`.
+///
+/// Neither takes locks which block reads or writes, so this is safe while the service is live. Both refuse to
+/// run inside a transaction, hence the dedicated connection rather than the pool.
+pub async fn run_maintenance(
+    State(pool): State<Pool>,
+    Query(query): Query<MaintenanceQuery>,
+) -> Result<Json<MaintenanceReport>> {
+    let Ok(_guard) = MAINTENANCE.try_lock() else {
+        return Err(AppError::Conflict("maintenance is already running".to_string()));
+    };
+    let mut conn = pool.acquire().await?;
+
+    let start = Instant::now();
+    sqlx::query("VACUUM (ANALYZE) entries")
+        .persistent(false)
+        .execute(&mut conn)
+        .await?;
+    let vacuum_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    let reindex_ms = if query.reindex {
+        let start = Instant::now();
+        sqlx::query("REINDEX TABLE CONCURRENTLY entries")
+            .persistent(false)
+            .execute(&mut conn)
+            .await?;
+        Some(start.elapsed().as_secs_f64() * 1000.0)
+    } else {
+        None
+    };
+
+    logfire::info!(
+        "ran maintenance vacuum_ms={vacuum_ms} reindex_ms={reindex_ms:?}",
+        vacuum_ms = vacuum_ms,
+        reindex_ms = reindex_ms,
+    );
+    Ok(Json(MaintenanceReport { vacuum_ms, reindex_ms }))
+}
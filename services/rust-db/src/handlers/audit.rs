@@ -0,0 +1,75 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+    extract::Path,
+    handlers::entries,
+    models::{AuditEvent, AuditPage, AuditQuery},
+    state::Pool,
+    telemetry::DbTimer,
+};
+
+/// Page through a project's audit log, oldest first, optionally only changes recorded between `?from=` and `?to=`.
+///
+/// There's deliberately no route which changes the log, and the table rejects updates and deletes.
+pub async fn audit_log(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Path(project): Path<Uuid>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<AuditPage>> {
+    let limit = match query.limit {
+        Some(0) => return Err(AppError::Validation("limit must be at least 1".to_string())),
+        Some(limit) => limit.min(config.max_list_limit.get()),
+        None => config.max_list_limit.get(),
+    };
+    let mut db = DbTimer::default();
+    entries::check_project_exists(&pool, &mut db, &config, project).await?;
+
+    // one extra row tells us whether there's another page
+    let mut events = db
+        .time(
+            sqlx::query_as!(
+                AuditEvent,
+                r#"
+        SELECT id, namespace, key, op, actor, size, variant, recorded_at
+        FROM audit_log
+        WHERE project_id = $1
+            AND ($2::timestamptz IS NULL OR recorded_at >= $2)
+            AND ($3::timestamptz IS NULL OR recorded_at < $3)
+            AND ($4::bigint IS NULL OR id > $4)
+        ORDER BY id
+        LIMIT $5
+        "#,
+                project,
+                query.from,
+                query.to,
+                query.after,
+                i64::from(limit) + 1,
+            )
+            .fetch_all(&*pool),
+        )
+        .await?;
+
+    let next = if events.len() > limit as usize {
+        events.truncate(limit as usize);
+        events.last().map(|event| event.id)
+    } else {
+        None
+    };
+
+    logfire::info!(
+        "read audit log project={project} count={count} db_ms={db_ms}",
+        project = project.to_string(),
+        count = events.len(),
+        db_ms = db.ms(),
+    );
+    Ok(Json(AuditPage { events, next }))
+}
@@ -0,0 +1,174 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use sqlx::{Connection, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    audit,
+    config::Config,
+    error::{AppError, Result},
+    extract::Path,
+    handlers::entries,
+    models::{BatchOperation, BatchQuery, BatchRequest, BatchResponse, BatchResult, DEFAULT_NAMESPACE},
+    repository::{EntryRepository, NewEntry, Ttl},
+    state::{Pool, Repository},
+    telemetry::DbTimer,
+    tenant::{self, Access},
+    uploads,
+};
+
+/// Store and delete several entries in one request, returning `207 Multi-Status` with a result per operation.
+///
+/// Each operation runs in its own savepoint, so one failing doesn't affect the others and clients can retry just
+/// the failed ones. With `?atomic=true` any failure rolls back the whole batch instead, and the operations which
+/// had succeeded are reported as `424 Failed Dependency`.
+///
+/// With `?dry_run=true` the deletes run as usual and the batch is then always rolled back, so the results preview
+/// exactly which keys they'd remove; stores are refused with `400` rather than previewed.
+pub async fn batch(
+    State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path(project): Path<Uuid>,
+    Query(query): Query<BatchQuery>,
+    headers: HeaderMap,
+    Json(request): Json<BatchRequest>,
+) -> Result<Response> {
+    let actor = audit::actor(&headers)?;
+    let _permit = uploads::acquire(&config, project)?;
+    let mut conn = tenant::connection(&pool, &config, project, Access::Create).await?;
+    let mut db = DbTimer::default();
+    let mut tx = db.time(audit::begin(&mut conn, actor.as_deref())).await?;
+
+    let mut results = Vec::with_capacity(request.operations.len());
+    for (index, operation) in request.operations.into_iter().enumerate() {
+        let raw_key = operation.get("key").and_then(|key| key.as_str()).map(str::to_string);
+        let (key, outcome) = match serde_json::from_value::<BatchOperation>(operation) {
+            Ok(operation) => {
+                let mut savepoint = db.time(tx.begin()).await?;
+                let (key, outcome) = apply(
+                    &mut savepoint,
+                    &mut db,
+                    &*repository,
+                    &config,
+                    project,
+                    operation,
+                    actor.as_deref(),
+                    query.dry_run,
+                )
+                .await;
+                if outcome.is_ok() {
+                    db.time(savepoint.commit()).await?;
+                } else {
+                    db.time(savepoint.rollback()).await?;
+                }
+                (Some(key), outcome)
+            }
+            Err(e) => (raw_key, Err(AppError::Validation(format!("invalid operation: {e}")))),
+        };
+        results.push(BatchResult {
+            index,
+            key,
+            status: outcome
+                .as_ref()
+                .map_or_else(AppError::status, |status| *status)
+                .as_u16(),
+            error: outcome.err().map(|e| e.to_string()),
+        });
+    }
+
+    let failed = results.iter().filter(|result| result.error.is_some()).count();
+    if query.atomic && failed > 0 {
+        db.time(tx.rollback()).await?;
+        for result in results.iter_mut().filter(|result| result.error.is_none()) {
+            result.status = StatusCode::FAILED_DEPENDENCY.as_u16();
+            result.error = Some("not applied, another operation in the atomic batch failed".to_string());
+        }
+    } else if query.dry_run {
+        db.time(tx.rollback()).await?;
+    } else {
+        db.time(tx.commit()).await?;
+    }
+
+    logfire::info!(
+        "batch project={project} count={count} failed={failed} atomic={atomic} dry_run={dry_run} actor={actor:?} \
+         db_ms={db_ms}",
+        project = project.to_string(),
+        count = results.len(),
+        failed = failed,
+        atomic = query.atomic,
+        dry_run = query.dry_run,
+        actor = actor,
+        db_ms = db.ms(),
+    );
+    Ok((StatusCode::MULTI_STATUS, Json(BatchResponse { results })).into_response())
+}
+
+/// Apply one operation, returning the normalized key and the status the single-entry endpoint would return
+#[allow(clippy::too_many_arguments)]
+async fn apply(
+    conn: &mut PgConnection,
+    db: &mut DbTimer,
+    repository: &dyn EntryRepository,
+    config: &Config,
+    project: Uuid,
+    operation: BatchOperation,
+    actor: Option<&str>,
+    dry_run: bool,
+) -> (String, Result<StatusCode>) {
+    match operation {
+        BatchOperation::Store(request) if dry_run => (
+            request.key,
+            Err(AppError::Validation(
+                "stores can't be previewed with dry_run".to_string(),
+            )),
+        ),
+        BatchOperation::Store(request) => {
+            let raw_key = request.key.clone();
+            let immutable = request.immutable;
+            let (key, mime_type, content) = match entries::decode_store_request(config, request) {
+                Ok(decoded) => decoded,
+                Err(e) => return (raw_key, Err(e)),
+            };
+            let entry = NewEntry {
+                namespace: DEFAULT_NAMESPACE,
+                key: &key,
+                mime_type: &mime_type,
+                content: &content,
+                immutable,
+                ttl: Ttl::ProjectDefault,
+                response_headers: BTreeMap::new(),
+                actor: actor.map(str::to_string),
+                created_at: None,
+                updated_at: None,
+                create_only: false,
+            };
+            let status = match entries::write_entry(conn, db, repository, config, project, &entry).await {
+                Ok(Some((true, _))) => Ok(StatusCode::CREATED),
+                Ok(Some((false, _))) => Ok(StatusCode::OK),
+                Ok(None) => Err(AppError::Conflict(format!("entry {key:?} is immutable"))),
+                Err(e) => Err(e),
+            };
+            (key, status)
+        }
+        BatchOperation::Delete { key } => {
+            let key = config.normalize_key(key);
+            let status = match db.time(repository.delete(conn, project, DEFAULT_NAMESPACE, &key)).await {
+                Ok(Some(false)) => Ok(StatusCode::NO_CONTENT),
+                Ok(Some(true)) => Err(AppError::Forbidden(format!("entry {key:?} is immutable"))),
+                Ok(None) => Err(AppError::KeyNotFound {
+                    project,
+                    key: key.clone(),
+                }),
+                Err(e) => Err(e.into()),
+            };
+            (key, status)
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Content-addressed entries, stored in the `cas` namespace under the hex SHA-256 of their content.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, header},
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    audit,
+    config::Config,
+    error::{AppError, Result},
+    extract::Path,
+    handlers::entries::{self, Rendering},
+    models::{CAS_NAMESPACE, StoredEntry},
+    repository::NewEntry,
+    state::{Pool, Repository},
+    telemetry::{self, DbTimer},
+    tenant::{self, Access},
+    uploads,
+};
+
+/// Store the body under its SHA-256, `201` if it's new and `200` if the same content was already stored, in which
+/// case the existing entry (including its mime type) is kept. Entries are immutable, since changing the content
+/// would change the key.
+pub async fn store_cas(
+    State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path(project): Path<Uuid>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    let mime_type = entries::body_mime_type(&config, &headers)?;
+    entries::check_mime_type_allowed(&config, &mime_type)?;
+    let ttl = entries::store_ttl(&headers)?;
+    let content = entries::decode_body(&headers, body, config.entry_size_limit(&mime_type))?;
+    entries::check_entry_size(&config, &mime_type, content.len())?;
+    let hash = hex::encode(Sha256::digest(&content));
+    telemetry::record_key(project, &hash);
+    telemetry::record_content(&mime_type, content.len());
+
+    let entry = NewEntry {
+        namespace: CAS_NAMESPACE,
+        key: &hash,
+        mime_type: &mime_type,
+        content: &content,
+        immutable: true,
+        ttl,
+        response_headers: BTreeMap::new(),
+        actor: audit::actor(&headers)?,
+        created_at: None,
+        updated_at: None,
+        create_only: false,
+    };
+    let _permit = uploads::acquire(&config, project)?;
+    let mut conn = tenant::connection(&pool, &config, project, Access::Create).await?;
+    let mut db = DbTimer::default();
+    let mut tx = db.time(audit::begin(&mut conn, entry.actor.as_deref())).await?;
+    // `None` means the entry is already stored, and being immutable wasn't overwritten
+    let inserted = entries::write_entry(&mut tx, &mut db, &*repository, &config, project, &entry)
+        .await?
+        .is_some();
+    db.time(tx.commit()).await?;
+
+    logfire::info!(
+        "stored content-addressed entry project={project} sha256={sha256} size={size} inserted={inserted} \
+         db_ms={db_ms}",
+        project = project.to_string(),
+        sha256 = &hash,
+        size = content.len(),
+        inserted = inserted,
+        db_ms = db.ms(),
+    );
+
+    let base = config.public_base_url.as_deref().unwrap_or_default();
+    let location = format!("{base}/project/{project}/cas/{hash}");
+    let stored = StoredEntry {
+        key: hash.clone(),
+        size: content.len(),
+        mime_type,
+        etag: entries::etag(&hash),
+        sha256: hash,
+    };
+    Ok((
+        entries::store_status(inserted),
+        [(header::LOCATION, location)],
+        Json(stored),
+    )
+        .into_response())
+}
+
+/// Get a content-addressed entry by its hex SHA-256
+pub async fn get_cas(
+    State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path((project, hash)): Path<(Uuid, String)>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(AppError::Validation("hash must be a hex encoded SHA-256".to_string()));
+    }
+    entries::fetch_entry(
+        &pool,
+        &*repository,
+        &config,
+        &headers,
+        project,
+        CAS_NAMESPACE,
+        hash.to_ascii_lowercase(),
+        Rendering::Stored,
+    )
+    .await
+}
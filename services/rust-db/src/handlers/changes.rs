@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+    extract::Path,
+    handlers::entries,
+    models::{Change, ChangesCursor, ChangesPage, ChangesQuery},
+    state::Pool,
+    telemetry::{self, DbTimer},
+    tenant::{self, Access},
+};
+
+/// Keys in a namespace stored, deleted or expired after `?since=`, oldest change first, for clients keeping an
+/// incremental copy of a project.
+///
+/// Deletes come from the audit log, so keys deleted and then stored again only appear as stored. A write which
+/// commits after the query can still have started before `now`, so clients which must see every change should pass
+/// a `since` a little before the previous `now`, changes are safe to apply more than once.
+pub async fn list_changes(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Path(project): Path<Uuid>,
+    Query(query): Query<ChangesQuery>,
+) -> Result<Json<ChangesPage>> {
+    let limit = match query.limit {
+        Some(0) => return Err(AppError::Validation("limit must be at least 1".to_string())),
+        Some(limit) => limit.min(config.max_list_limit.get()),
+        None => config.max_list_limit.get(),
+    };
+    telemetry::record_namespace(&query.namespace);
+    let mut db = DbTimer::default();
+    entries::check_project_exists(&pool, &mut db, &config, project).await?;
+
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    let now = db
+        .time(sqlx::query_scalar!(r#"SELECT NOW() AS "now!""#).fetch_one(&mut conn))
+        .await?;
+    // one extra row tells us whether there's another page
+    let mut changes = db
+        .time(
+            sqlx::query_as!(
+                Change,
+                r#"
+        WITH changes AS (
+            SELECT key, mime_type, octet_length(content)::bigint AS size, updated_at, FALSE AS deleted
+            FROM entries
+            WHERE project_id = $1 AND namespace = $2 AND updated_at >= $3
+                AND (expires_at IS NULL OR expires_at > $6)
+            UNION ALL
+            SELECT key, NULL, NULL, expires_at, TRUE
+            FROM entries
+            WHERE project_id = $1 AND namespace = $2 AND expires_at >= $3 AND expires_at <= $6
+            UNION ALL
+            SELECT key, NULL, NULL, max(recorded_at), TRUE
+            FROM audit_log
+            WHERE project_id = $1 AND namespace = $2 AND op = 'delete' AND variant IS NULL
+                AND recorded_at >= $3
+                AND NOT EXISTS (
+                    SELECT 1 FROM entries
+                    WHERE entries.project_id = $1 AND entries.namespace = $2 AND entries.key = audit_log.key
+                )
+            GROUP BY key
+        )
+        SELECT
+            key AS "key!",
+            mime_type,
+            size,
+            updated_at AS "updated_at!",
+            deleted AS "deleted!"
+        FROM changes
+        WHERE updated_at > $3 OR ($4::text IS NOT NULL AND updated_at = $3 AND key > $4)
+        ORDER BY updated_at, key
+        LIMIT $5
+        "#,
+                project,
+                &query.namespace,
+                query.since,
+                query.after.as_deref(),
+                i64::from(limit) + 1,
+                now,
+            )
+            .fetch_all(&mut conn),
+        )
+        .await?;
+
+    let next = if changes.len() > limit as usize {
+        changes.truncate(limit as usize);
+        changes.last().map(|change| ChangesCursor {
+            since: change.updated_at,
+            after: change.key.clone(),
+        })
+    } else {
+        None
+    };
+
+    logfire::info!(
+        "listed changes project={project} count={count} db_ms={db_ms}",
+        project = project.to_string(),
+        count = changes.len(),
+        db_ms = db.ms(),
+    );
+    Ok(Json(ChangesPage { now, changes, next }))
+}
@@ -1,148 +1,1877 @@
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    sync::{Arc, Mutex},
+};
+
 use axum::{
     Json,
-    body::Bytes,
-    extract::{Path, State},
-    http::{HeaderMap, StatusCode, header},
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri, header},
     response::{IntoResponse, Response},
 };
+use base64::prelude::*;
+use chrono::{DateTime, Utc};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use futures::{StreamExt, TryStreamExt, stream};
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use sqlx::PgConnection;
+use tokio::sync::mpsc;
+use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::{
+    audit,
+    config::{self, Config},
     error::{AppError, Result},
-    models::{Entry, KeyInfo},
+    extract::Path,
+    handlers::signed::KEY_ENCODE_SET,
+    like::{self, escape_like_prefix},
+    models::{
+        AffectedRows, CAS_NAMESPACE, DEFAULT_NAMESPACE, DeleteQuery, DryRunQuery, EntryMeta, EntryPath, GetEntryQuery,
+        KeyInfo, ListQuery, ListResponse, PrefixPath, RetagRequest, ShadowedPath, StoreEntryRequest, StoredEntry,
+        SwapValueRequest, TouchAllQuery, TranscodeFormat,
+    },
+    repository::{EntryRepository, NewEntry, Swapped, Ttl},
+    state::{Pool, Repository},
+    telemetry::{self, DbTimer, SizeOperation},
+    tenant::{self, Access, PoolConnection},
+    transcode, uploads,
 };
 
-type Pool = std::sync::Arc<sqlx_tracing::Pool<sqlx::Postgres>>;
+pub async fn get_entry(
+    State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path(path): Path<EntryPath>,
+    Query(query): Query<GetEntryQuery>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let key = config.normalize_key(path.key);
+    let rendering = match (query.content_type, query.as_format) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::Validation(
+                "content_type and as can't be used together".to_string(),
+            ));
+        }
+        (Some(content_type), None) => Rendering::ContentType(validate_mime_type(content_type)?),
+        (None, Some(TranscodeFormat::Json)) => Rendering::Json,
+        (None, None) => Rendering::Stored,
+    };
+    let fetch = |key: String| {
+        fetch_entry(
+            &pool,
+            &*repository,
+            &config,
+            &headers,
+            path.project,
+            &path.namespace,
+            key,
+            rendering.clone(),
+        )
+    };
+    let missing = match fetch(key.clone()).await {
+        Err(missing @ AppError::KeyNotFound { .. }) => missing,
+        found => return found,
+    };
 
-pub async fn get_entry(State(pool): State<Pool>, Path((project, key)): Path<(Uuid, String)>) -> Result<Response> {
-    let opt_entry: Option<Entry> = sqlx::query_as!(
-        Entry,
-        r#"
-        SELECT mime_type, content
-        FROM entries
-        WHERE project_id = $1 AND key = $2
-        "#,
-        project,
-        key
-    )
-    .fetch_optional(&*pool)
-    .await?;
+    // a static site's index pages and not found page, if the project has them
+    let mut db = DbTimer::default();
+    let Some(site) = db
+        .time(
+            sqlx::query!(
+                "SELECT index_key, fallback_key, fallback_status FROM projects WHERE id = $1",
+                path.project
+            )
+            .fetch_optional(&*pool),
+        )
+        .await?
+    else {
+        return Err(missing);
+    };
+    if let Some(index_key) = site.index_key
+        && key.ends_with('/')
+    {
+        match fetch(format!("{key}{index_key}")).await {
+            Err(AppError::KeyNotFound { .. }) => {}
+            found => return found,
+        }
+    }
+    let Some(fallback_key) = site.fallback_key else {
+        return Err(missing);
+    };
+    match fetch(fallback_key).await {
+        Err(AppError::KeyNotFound { .. }) => Err(missing),
+        Ok(mut response) => {
+            // `304`s are left alone, the client already has the not found page
+            if response.status() == StatusCode::OK {
+                *response.status_mut() = site
+                    .fallback_status
+                    .and_then(|status| StatusCode::from_u16(u16::try_from(status).ok()?).ok())
+                    .unwrap_or(StatusCode::NOT_FOUND);
+            }
+            Ok(response)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// How `fetch_entry` serves an entry's content
+#[derive(Debug, Clone)]
+pub enum Rendering {
+    /// As stored, with the stored mime type
+    Stored,
+    /// As stored, with this `Content-Type`
+    ContentType(String),
+    /// Converted to JSON by `transcode::to_json`
+    Json,
+}
 
-    if let Some(entry) = opt_entry {
+/// Serve an entry's content, shared by `get_entry` and signed URLs, `304` if it matches `If-None-Match`; entries
+/// with variants serve whichever the request's `Accept` prefers
+#[allow(clippy::too_many_arguments)]
+pub async fn fetch_entry(
+    pool: &Pool,
+    repository: &dyn EntryRepository,
+    config: &Config,
+    request_headers: &HeaderMap,
+    project: Uuid,
+    namespace: &str,
+    key: String,
+    rendering: Rendering,
+) -> Result<Response> {
+    telemetry::record_key(project, &key);
+    telemetry::record_namespace(namespace);
+
+    let mut conn = tenant::connection(pool, config, project, Access::Existing).await?;
+    let mut db = DbTimer::default();
+    let opt_entry = db.time(repository.get(conn.as_mut(), project, namespace, &key)).await?;
+
+    if let Some(mut entry) = opt_entry {
+        if !entry.variants.is_empty() {
+            let mime_types: Vec<&str> = std::iter::once(entry.mime_type.as_str())
+                .chain(entry.variants.iter().map(String::as_str))
+                .collect();
+            let chosen = negotiate(request_headers, &mime_types);
+            if chosen > 0 {
+                let mime_type = entry.variants[chosen - 1].clone();
+                let variant = db
+                    .time(repository.get_variant(conn.as_mut(), entry.id, &mime_type))
+                    .await?
+                    // deleted since the entry was read
+                    .ok_or_else(|| AppError::KeyNotFound {
+                        project,
+                        key: key.clone(),
+                    })?;
+                entry.mime_type = mime_type;
+                entry.content = variant.content;
+                entry.updated_at = variant.updated_at;
+                entry.sha256 = variant.sha256;
+            }
+        }
         logfire::info!(
-            "retrieved value project={project} key={key} mime_type={mime_type} size={size}",
+            "retrieved value project={project} key={key} mime_type={mime_type} size={size} db_ms={db_ms}",
             project = project.to_string(),
             key = key,
             mime_type = &entry.mime_type,
-            size = entry.content.len()
+            size = entry.content.len(),
+            db_ms = db.ms(),
         );
+        telemetry::record_content(&entry.mime_type, entry.content.len());
 
-        Ok((StatusCode::OK, [(header::CONTENT_TYPE, entry.mime_type)], entry.content).into_response())
+        let mut etag = match &entry.sha256 {
+            Some(sha256) => etag(sha256),
+            None => weak_etag(entry.updated_at, entry.content.len()),
+        };
+        let (content_type, content) = match rendering {
+            Rendering::Stored => (entry.mime_type, entry.content),
+            Rendering::ContentType(content_type) => (content_type, entry.content),
+            Rendering::Json => {
+                // a different representation of the same content, so it needs a different tag
+                etag.insert_str(etag.len() - 1, "-json");
+                let json = transcode::to_json(&entry.mime_type, &entry.content)?;
+                (mime::APPLICATION_JSON.to_string(), json)
+            }
+        };
+        let etag = HeaderValue::from_str(&etag).expect("ETag is a valid header value");
+        let mut headers = replayed_headers(&entry.response_headers);
+        if !entry.variants.is_empty() {
+            let vary = match headers.get(header::VARY).and_then(|v| v.to_str().ok()) {
+                Some(vary) => format!("{vary}, Accept"),
+                None => "Accept".to_string(),
+            };
+            headers.insert(
+                header::VARY,
+                HeaderValue::from_str(&vary).expect("Vary is a valid header value"),
+            );
+        }
+        if etag_matches(request_headers, &etag) {
+            let vary = headers.remove(header::VARY).map(|vary| [(header::VARY, vary)]);
+            return Ok((StatusCode::NOT_MODIFIED, vary, [(header::ETAG, etag)]).into_response());
+        }
+
+        let range = byte_range(request_headers, content.len(), &etag, entry.updated_at);
+        headers.insert(header::ETAG, etag);
+        headers.insert(header::LAST_MODIFIED, http_date(entry.updated_at));
+        headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        if let Some(expires_at) = entry.expires_at
+            && expires_at - Utc::now() <= config.sunset_window
+        {
+            headers.insert(SUNSET, http_date(expires_at));
+        }
+        telemetry::record_entry_size(SizeOperation::Get, content.len());
+        let content_type = [(header::CONTENT_TYPE, content_type)];
+        match range {
+            ByteRange::Whole => Ok((StatusCode::OK, headers, content_type, content).into_response()),
+            ByteRange::Part(range) => {
+                let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, content.len());
+                headers.insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::from_str(&content_range).expect("Content-Range is a valid header value"),
+                );
+                let part = content[range].to_vec();
+                Ok((StatusCode::PARTIAL_CONTENT, headers, content_type, part).into_response())
+            }
+            ByteRange::Unsatisfiable => {
+                let content_range = format!("bytes */{}", content.len());
+                headers.insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::from_str(&content_range).expect("Content-Range is a valid header value"),
+                );
+                Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response())
+            }
+        }
     } else {
         logfire::info!(
-            "key not found project={project} key={key}",
+            "key not found project={project} key={key} db_ms={db_ms}",
             project = project.to_string(),
             key = &key,
+            db_ms = db.ms(),
         );
-        Err(AppError::KeyNotFound(key))
+        Err(AppError::KeyNotFound { project, key })
     }
 }
 
-pub async fn list_entries_all(State(pool): State<Pool>, Path(project): Path<Uuid>) -> Result<Json<Vec<KeyInfo>>> {
-    let entries: Vec<KeyInfo> = sqlx::query_as!(
-        KeyInfo,
-        r#"
-        SELECT key, mime_type
+pub async fn get_entry_meta(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Path(EntryPath {
+        project,
+        namespace,
+        key,
+    }): Path<EntryPath>,
+) -> Result<Json<EntryMeta>> {
+    let key = config.normalize_key(key);
+    telemetry::record_key(project, &key);
+    telemetry::record_namespace(&namespace);
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    let row = DbTimer::default()
+        .time(
+            sqlx::query!(
+                r#"
+        SELECT
+            key,
+            mime_type,
+            octet_length(content)::bigint AS "size!",
+            created_at,
+            updated_at,
+            encode(sha256(content), 'hex') AS "sha256!",
+            created_by,
+            updated_by,
+            ARRAY(
+                SELECT mime_type FROM entry_variants WHERE entry_id = entries.id ORDER BY mime_type
+            ) AS "variants!"
         FROM entries
-        WHERE project_id = $1
-        ORDER BY key
+        WHERE project_id = $1 AND namespace = $2 AND key = $3 AND (expires_at IS NULL OR expires_at > NOW())
         "#,
-        project
-    )
-    .fetch_all(&*pool)
-    .await?;
+                project,
+                namespace,
+                key
+            )
+            .fetch_optional(&mut conn),
+        )
+        .await?
+        .ok_or(AppError::KeyNotFound { project, key })?;
+    telemetry::record_content(&row.mime_type, row.size as usize);
+    // the same tag get returns, even though the hash is at hand
+    let etag = if row.size > config.weak_etag_threshold {
+        weak_etag(row.updated_at, row.size as usize)
+    } else {
+        etag(&row.sha256)
+    };
 
-    Ok(Json(entries))
+    Ok(Json(EntryMeta {
+        key: row.key,
+        mime_type: row.mime_type,
+        size: row.size,
+        created_at: row.created_at,
+        updated_at: row.updated_at,
+        etag,
+        sha256: row.sha256,
+        created_by: row.created_by,
+        updated_by: row.updated_by,
+        variants: row.variants,
+    }))
+}
+
+/// Headers which `store_entry` captures and `get_entry` replays, anything else a client sends is ignored so
+/// stored entries can't set headers like `Set-Cookie` or `Location`
+const REPLAYED_HEADERS: [HeaderName; 4] = [
+    header::CACHE_CONTROL,
+    header::CONTENT_DISPOSITION,
+    header::CONTENT_LANGUAGE,
+    header::VARY,
+];
+
+/// Capture allowlisted headers from a store request, repeated headers are joined with `, `
+fn capture_response_headers(headers: &HeaderMap) -> Result<BTreeMap<String, String>> {
+    let mut captured = BTreeMap::new();
+    for name in &REPLAYED_HEADERS {
+        let values = headers
+            .get_all(name)
+            .iter()
+            .map(|v| {
+                v.to_str()
+                    .map_err(|_| AppError::Validation(format!("{name} header must be visible ASCII")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !values.is_empty() {
+            captured.insert(name.to_string(), values.join(", "));
+        }
+    }
+    Ok(captured)
+}
+
+/// Check restored response headers are ones a store could have captured
+pub fn check_response_headers(headers: &BTreeMap<String, String>) -> Result<()> {
+    for (name, value) in headers {
+        if !REPLAYED_HEADERS.iter().any(|replayed| replayed.as_str() == name) {
+            return Err(AppError::Validation(format!("{name} isn't a stored response header")));
+        }
+        if HeaderValue::from_str(value).is_err() {
+            return Err(AppError::Validation(format!("{name} header must be visible ASCII")));
+        }
+    }
+    Ok(())
+}
+
+fn replayed_headers(stored: &BTreeMap<String, String>) -> HeaderMap {
+    REPLAYED_HEADERS
+        .iter()
+        .filter_map(|name| {
+            let value = HeaderValue::from_str(stored.get(name.as_str())?).ok()?;
+            Some((name.clone(), value))
+        })
+        .collect()
+}
+
+/// Which part of an entry a get serves
+enum ByteRange {
+    Whole,
+    Part(std::ops::Range<usize>),
+    /// The `Range` starts beyond the end of the entry, `416`
+    Unsatisfiable,
+}
+
+/// A single `Range: bytes=...` of an entry `len` bytes long. Several ranges would need a multipart response, so
+/// like a missing or malformed `Range` they get the whole entry, as RFC 9110 allows; so does an `If-Range` which
+/// doesn't match.
+fn byte_range(headers: &HeaderMap, len: usize, etag: &HeaderValue, last_modified: DateTime<Utc>) -> ByteRange {
+    let Some(range) = headers.get(header::RANGE).and_then(|v| v.to_str().ok()) else {
+        return ByteRange::Whole;
+    };
+    if let Some(if_range) = headers.get(header::IF_RANGE)
+        && !if_range_matches(if_range, etag, last_modified)
+    {
+        return ByteRange::Whole;
+    }
+    let Some((start, end)) = range
+        .trim()
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .and_then(|spec| spec.split_once('-'))
+    else {
+        return ByteRange::Whole;
+    };
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<usize>() {
+            Ok(suffix) => (len.saturating_sub(suffix), len),
+            Err(_) => return ByteRange::Whole,
+        },
+        (start, "") => match start.parse() {
+            Ok(start) => (start, len),
+            Err(_) => return ByteRange::Whole,
+        },
+        (start, end) => match (start.parse::<usize>(), end.parse::<usize>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.saturating_add(1).min(len)),
+            _ => return ByteRange::Whole,
+        },
+    };
+    if start < end {
+        ByteRange::Part(start..end)
+    } else {
+        ByteRange::Unsatisfiable
+    }
+}
+
+/// `If-Range` is the entry's strong ETag, or exactly its `Last-Modified` date; weak ETags never match, since a
+/// range of a weakly matching entry could be spliced onto bytes from another version
+fn if_range_matches(if_range: &HeaderValue, etag: &HeaderValue, last_modified: DateTime<Utc>) -> bool {
+    let Ok(if_range) = if_range.to_str() else {
+        return false;
+    };
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') {
+        if_range.as_bytes() == etag.as_bytes()
+    } else if if_range.starts_with("W/") {
+        false
+    } else {
+        DateTime::parse_from_rfc2822(if_range).is_ok_and(|date| date.timestamp() == last_modified.timestamp())
+    }
+}
+
+/// RFC 8594, warns clients that an entry is about to expire
+const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// IMF-fixdate, the preferred HTTP date format
+fn http_date(dt: DateTime<Utc>) -> HeaderValue {
+    let formatted = dt.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    HeaderValue::from_str(&formatted).expect("formatted date is a valid header value")
+}
+
+/// Strong ETag derived from the hex SHA-256 of the content
+pub fn etag(sha256: &str) -> String {
+    format!("\"{sha256}\"")
+}
+
+/// Weak ETag for entries too large to hash on every get, `updated_at` changes on every store so it identifies
+/// the version, the size is included in case of clock skew between stores
+fn weak_etag(updated_at: DateTime<Utc>, size: usize) -> String {
+    format!("W/\"{:x}-{size:x}\"", updated_at.timestamp_micros())
+}
+
+/// `If-None-Match` includes `etag`, or is `*`, using the weak comparison RFC 9110 requires for `If-None-Match`
+fn etag_matches(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag.to_str().unwrap_or_default());
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+pub fn validate_mime_type(mime_type: String) -> Result<String> {
+    if mime_type.parse::<mime::Mime>().is_ok() && HeaderValue::from_str(&mime_type).is_ok() {
+        Ok(mime_type)
+    } else {
+        Err(AppError::Validation(format!("invalid mime type: {mime_type:?}")))
+    }
+}
+
+/// With `STRICT_PROJECT_CHECK` listing an unknown project is a 404 rather than an empty list
+pub async fn check_project_exists(pool: &Pool, db: &mut DbTimer, config: &Config, project: Uuid) -> Result<()> {
+    if !config.strict_project_check {
+        return Ok(());
+    }
+
+    let mut conn = pool.acquire().await?;
+    if project_exists(conn.as_mut(), db, project).await? {
+        Ok(())
+    } else {
+        Err(AppError::ProjectNotFound(project))
+    }
+}
+
+pub async fn project_exists(conn: &mut PgConnection, db: &mut DbTimer, project: Uuid) -> Result<bool> {
+    let exists = db
+        .time(
+            sqlx::query_scalar!(
+                r#"SELECT EXISTS(SELECT 1 FROM projects WHERE id = $1) AS "exists!""#,
+                project
+            )
+            .fetch_one(&mut *conn),
+        )
+        .await?;
+    Ok(exists)
 }
 
 pub async fn list_entries(
     State(pool): State<Pool>,
-    Path((project, prefix)): Path<(Uuid, String)>,
-) -> Result<Json<Vec<KeyInfo>>> {
-    // Escape SQL LIKE wildcards
-    let pattern = format!(
-        "{}%",
-        prefix.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
-    );
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path(PrefixPath {
+        project,
+        namespace,
+        prefix,
+    }): Path<PrefixPath>,
+    Query(query): Query<ListQuery>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let prefix = config.normalize_key(prefix);
+    telemetry::record_prefix(project, &prefix);
+    telemetry::record_namespace(&namespace);
+    let streamed = accepts(&headers, NDJSON);
+    let limit = list_limit(&config, query.limit, streamed)?;
+    let mut db = DbTimer::default();
+    check_project_exists(&pool, &mut db, &config, project).await?;
 
-    let entries: Vec<KeyInfo> = sqlx::query_as!(
-        KeyInfo,
-        r#"
-        SELECT key, mime_type
-        FROM entries
-        WHERE project_id = $1 AND key LIKE $2
-        ORDER BY key
-        "#,
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    let last_modified = project_last_modified(&mut conn, &mut db, project).await?;
+    if let Some(last_modified) = last_modified
+        && not_modified_since(&headers, last_modified)
+    {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(header::LAST_MODIFIED, http_date(last_modified))],
+        )
+            .into_response());
+    }
+
+    let mut headers = HeaderMap::new();
+    if let Some(limit) = limit {
+        headers.insert(LIST_LIMIT, limit.into());
+    }
+    if let Some(last_modified) = last_modified {
+        headers.insert(header::LAST_MODIFIED, http_date(last_modified));
+    }
+
+    if streamed {
+        if query.delimiter.is_some() || query.depth.is_some() {
+            return Err(AppError::Validation(
+                "delimiter and depth aren't supported when streaming".to_string(),
+            ));
+        }
+        let mime_pattern = list_mime_pattern(&query)?;
+        let body = stream_entries(conn, repository, project, namespace, prefix, query, mime_pattern, limit);
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(NDJSON));
+        return Ok((headers, body).into_response());
+    }
+
+    // one extra row tells us whether the list was truncated
+    let mut entries = query_entries(
+        &mut conn,
+        &mut db,
+        &*repository,
         project,
-        pattern
+        &namespace,
+        &prefix,
+        &query,
+        limit.map(|limit| i64::from(limit) + 1),
     )
-    .fetch_all(&*pool)
     .await?;
+    let truncated = limit.is_some_and(|limit| entries.len() > limit as usize);
+    if let Some(limit) = limit
+        && truncated
+    {
+        entries.truncate(limit as usize);
+        if let Some(last) = entries.last() {
+            let cursor = utf8_percent_encode(&last.key, KEY_ENCODE_SET).to_string();
+            headers.insert(
+                NEXT_CURSOR,
+                HeaderValue::from_str(&cursor).expect("percent-encoded key is ASCII"),
+            );
+        }
+    }
+    headers.insert(
+        RESULT_TRUNCATED,
+        HeaderValue::from_static(if truncated { "true" } else { "false" }),
+    );
+    let response = match query.delimiter.as_deref() {
+        Some(delimiter) => {
+            let depth = query.depth.unwrap_or(1);
+            if delimiter.is_empty() || depth == 0 {
+                return Err(AppError::Validation(
+                    "delimiter must not be empty and depth must be at least 1".to_string(),
+                ));
+            }
+            collapse_common_prefixes(entries, &prefix, delimiter, depth)
+        }
+        None if query.depth.is_some() => return Err(AppError::Validation("depth requires a delimiter".to_string())),
+        None => ListResponse::Keys(entries),
+    };
+    Ok((headers, Json(response)).into_response())
+}
+
+/// `HEAD` on a list: the number of matching keys as `X-Total-Count` and their latest `updated_at` as
+/// `Last-Modified`, so sync clients can tell whether a full list is worth fetching without it being built.
+///
+/// The filters are those of `GET`, except that there's no limit. Unlike `GET`'s `Last-Modified`, which is the
+/// project's, this is only of the matching keys, so deleting one of them shows in the count rather than the date.
+pub async fn head_list_entries(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Path(PrefixPath {
+        project,
+        namespace,
+        prefix,
+    }): Path<PrefixPath>,
+    Query(query): Query<ListQuery>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let prefix = config.normalize_key(prefix);
+    telemetry::record_prefix(project, &prefix);
+    telemetry::record_namespace(&namespace);
+    let mime_pattern = list_mime_pattern(&query)?;
+    let pattern = escape_like_prefix(&prefix);
+    let mut db = DbTimer::default();
+    check_project_exists(&pool, &mut db, &config, project).await?;
+
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    let summary = if config.list_collate_c {
+        db.time(
+            sqlx::query_as!(
+                ListSummary,
+                r#"
+        SELECT count(*) AS "count!", max(updated_at) AS last_modified, NOW() AS "now!"
+        FROM entries
+        WHERE project_id = $1
+            AND namespace = $2
+            AND key COLLATE "C" LIKE $3
+            AND (expires_at IS NULL OR expires_at > NOW())
+            AND ($4::bigint IS NULL OR octet_length(content) >= $4)
+            AND ($5::bigint IS NULL OR octet_length(content) <= $5)
+            AND ($6::text[] IS NULL OR lower(trim(split_part(mime_type, ';', 1))) LIKE ANY($6))
+            AND ($7::text IS NULL OR key COLLATE "C" > $7)
+            AND ($8::text IS NULL OR key COLLATE "C" >= $8)
+            AND ($9::text IS NULL OR key COLLATE "C" < $9)
+        "#,
+                project,
+                &namespace,
+                &pattern,
+                query.min_size,
+                query.max_size,
+                mime_pattern.as_deref(),
+                query.after.as_deref(),
+                query.start.as_deref(),
+                query.end.as_deref(),
+            )
+            .fetch_one(&mut conn),
+        )
+        .await?
+    } else {
+        db.time(
+            sqlx::query_as!(
+                ListSummary,
+                r#"
+        SELECT count(*) AS "count!", max(updated_at) AS last_modified, NOW() AS "now!"
+        FROM entries
+        WHERE project_id = $1
+            AND namespace = $2
+            AND key LIKE $3
+            AND (expires_at IS NULL OR expires_at > NOW())
+            AND ($4::bigint IS NULL OR octet_length(content) >= $4)
+            AND ($5::bigint IS NULL OR octet_length(content) <= $5)
+            AND ($6::text[] IS NULL OR lower(trim(split_part(mime_type, ';', 1))) LIKE ANY($6))
+            AND ($7::text IS NULL OR key > $7)
+            AND ($8::text IS NULL OR key >= $8)
+            AND ($9::text IS NULL OR key < $9)
+        "#,
+                project,
+                &namespace,
+                &pattern,
+                query.min_size,
+                query.max_size,
+                mime_pattern.as_deref(),
+                query.after.as_deref(),
+                query.start.as_deref(),
+                query.end.as_deref(),
+            )
+            .fetch_one(&mut conn),
+        )
+        .await?
+    };
 
-    Ok(Json(entries))
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(TOTAL_COUNT, summary.count.into());
+    // as with the project's, a change within the current second couldn't be told apart from a later one
+    let last_modified = summary
+        .last_modified
+        .filter(|last_modified| last_modified.timestamp() < summary.now.timestamp());
+    if let Some(last_modified) = last_modified {
+        response_headers.insert(header::LAST_MODIFIED, http_date(last_modified));
+        if not_modified_since(&headers, last_modified) {
+            return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+        }
+    }
+    Ok((StatusCode::OK, response_headers).into_response())
 }
 
-pub async fn store_entry(
+struct ListSummary {
+    count: i64,
+    last_modified: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+}
+
+/// When the project's listings last changed, either because an entry was written or deleted or because one
+/// expired. `None` if the project doesn't exist, or if it changed within the current second, since HTTP dates
+/// only have second precision and a later write in the same second would be indistinguishable.
+async fn project_last_modified(
+    conn: &mut PoolConnection,
+    db: &mut DbTimer,
+    project: Uuid,
+) -> Result<Option<DateTime<Utc>>> {
+    let row = db
+        .time(
+            sqlx::query!(
+                r#"
+        SELECT
+            GREATEST(
+                entries_modified_at,
+                (SELECT max(expires_at) FROM entries WHERE project_id = $1 AND expires_at <= NOW())
+            ) AS "last_modified!",
+            NOW() AS "now!"
+        FROM projects
+        WHERE id = $1
+        "#,
+                project
+            )
+            .fetch_optional(&mut *conn),
+        )
+        .await?;
+    Ok(row
+        .filter(|row| row.last_modified.timestamp() < row.now.timestamp())
+        .map(|row| row.last_modified))
+}
+
+/// `If-Modified-Since` is at or after `last_modified`, HTTP dates only have second precision so that's what's
+/// compared; an unparseable date is ignored, as RFC 9110 requires
+fn not_modified_since(headers: &HeaderMap, last_modified: DateTime<Utc>) -> bool {
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+        .is_some_and(|since| last_modified.timestamp() <= since.timestamp())
+}
+
+/// Echoes the limit a list was run with, after clamping to `MAX_LIST_LIMIT`
+const LIST_LIMIT: HeaderName = HeaderName::from_static("x-list-limit");
+
+/// Whether more keys matched than the list returned, on buffered lists
+const RESULT_TRUNCATED: HeaderName = HeaderName::from_static("x-result-truncated");
+
+/// Number of keys a list matches, on `HEAD`
+const TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+
+/// Last key of a truncated list, percent-encoded, pass it as `?after=` to get the next page
+const NEXT_CURSOR: HeaderName = HeaderName::from_static("x-next-cursor");
+
+/// Newline delimited JSON, with which lists are streamed one key per line
+pub const NDJSON: &str = "application/x-ndjson";
+
+/// `?limit=` clamped to `MAX_LIST_LIMIT`, rather than rejected, so clients can ask for "as many as possible".
+///
+/// Streamed lists aren't clamped, the cap is there to bound the memory used buffering a list, which streaming
+/// already does; so they're unlimited by default.
+fn list_limit(config: &Config, requested: Option<u32>, streamed: bool) -> Result<Option<u32>> {
+    let max = config.max_list_limit.get();
+    match requested {
+        Some(0) => Err(AppError::Validation("limit must be at least 1".to_string())),
+        Some(limit) if streamed => Ok(Some(limit)),
+        Some(limit) => Ok(Some(limit.min(max))),
+        None if streamed => Ok(None),
+        None => Ok(Some(max)),
+    }
+}
+
+/// Split keys under `prefix` into those at most `depth` levels down, and the distinct prefixes of deeper keys
+/// truncated after `depth` delimiters
+fn collapse_common_prefixes(entries: Vec<KeyInfo>, prefix: &str, delimiter: &str, depth: usize) -> ListResponse {
+    let mut keys = Vec::new();
+    let mut common_prefixes: Vec<String> = Vec::new();
+    for entry in entries {
+        let rest = &entry.key[prefix.len()..];
+        match rest.match_indices(delimiter).nth(depth - 1) {
+            Some((index, _)) => {
+                let common_prefix = &entry.key[..prefix.len() + index + delimiter.len()];
+                // entries are ordered by key, so duplicates are always adjacent
+                if common_prefixes.last().is_none_or(|last| last != common_prefix) {
+                    common_prefixes.push(common_prefix.to_string());
+                }
+            }
+            None => keys.push(entry),
+        }
+    }
+    ListResponse::Delimited { keys, common_prefixes }
+}
+
+/// Live entries whose key starts with `prefix`, filtered by the list query params
+#[allow(clippy::too_many_arguments)]
+async fn query_entries(
+    conn: &mut PoolConnection,
+    db: &mut DbTimer,
+    repository: &dyn EntryRepository,
+    project: Uuid,
+    namespace: &str,
+    prefix: &str,
+    query: &ListQuery,
+    limit: Option<i64>,
+) -> Result<Vec<KeyInfo>> {
+    let mime_pattern = list_mime_pattern(query)?;
+    let entries = db
+        .time(
+            repository
+                .list_prefix(
+                    conn.as_mut(),
+                    project,
+                    namespace,
+                    prefix,
+                    query,
+                    mime_pattern.as_deref(),
+                    limit,
+                )
+                .try_collect(),
+        )
+        .await?;
+    Ok(entries)
+}
+
+/// Stream matching entries as ND-JSON from a task which owns the connection, so only a channel's worth of keys
+/// is held in memory however many match. A failure midway aborts the response, there's no way to report it
+/// once the status has been sent.
+#[allow(clippy::too_many_arguments)]
+fn stream_entries(
+    mut conn: PoolConnection,
+    repository: Repository,
+    project: Uuid,
+    namespace: String,
+    prefix: String,
+    query: ListQuery,
+    mime_pattern: Option<Vec<String>>,
+    limit: Option<u32>,
+) -> Body {
+    let (tx, rx) = mpsc::channel::<std::result::Result<Bytes, sqlx::Error>>(64);
+    let task = async move {
+        let mut rows = repository.list_prefix(
+            conn.as_mut(),
+            project,
+            &namespace,
+            &prefix,
+            &query,
+            mime_pattern.as_deref(),
+            limit.map(i64::from),
+        );
+        let mut count: usize = 0;
+        while let Some(row) = rows.next().await {
+            let line = row.map(|entry| {
+                let mut line = serde_json::to_vec(&entry).expect("KeyInfo is always serializable");
+                line.push(b'\n');
+                Bytes::from(line)
+            });
+            let failed = line.is_err();
+            // a send error means the client has gone away
+            if tx.send(line).await.is_err() || failed {
+                break;
+            }
+            count += 1;
+        }
+        logfire::info!(
+            "streamed entries project={project} count={count}",
+            project = project.to_string(),
+            count = count,
+        );
+    };
+    tokio::spawn(task.instrument(tracing::Span::current()));
+    Body::from_stream(stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (line, rx))
+    }))
+}
+
+/// Check the size and key bounds are consistent, and build the `?mime=` pattern
+fn list_mime_pattern(query: &ListQuery) -> Result<Option<Vec<String>>> {
+    if let (Some(min_size), Some(max_size)) = (query.min_size, query.max_size)
+        && min_size > max_size
+    {
+        return Err(AppError::Validation(
+            "min_size must not be greater than max_size".to_string(),
+        ));
+    }
+    if let (Some(start), Some(end)) = (&query.start, &query.end)
+        && start > end
+    {
+        return Err(AppError::Validation("start must not be after end".to_string()));
+    }
+    query.mime.as_deref().map(mime_patterns).transpose()
+}
+
+/// LIKE pattern for a `?mime=` filter, either an exact type (`image/png`) or any subtype (`image/*`),
+/// parameters on stored mime types are ignored when matching
+fn mime_pattern(mime: &str) -> Result<String> {
+    let mime = mime.trim().to_lowercase();
+    let Some((type_, subtype)) = mime.split_once('/') else {
+        return Err(AppError::Validation(format!("invalid mime filter: {mime:?}")));
+    };
+    if subtype == "*" {
+        Ok(format!("{}/%", like::escape_like(type_)))
+    } else {
+        Ok(like::escape_like(&mime))
+    }
+}
+
+/// LIKE patterns for a `?mime=` filter of one type or a comma-separated set, e.g. `image/png,image/webp,text/*`,
+/// an entry matches if its type matches any of them
+pub fn mime_patterns(mime: &str) -> Result<Vec<String>> {
+    mime.split(',').map(mime_pattern).collect()
+}
+
+/// Set a TTL from `X-TTL-Seconds` on every live, mutable entry under a prefix, `?dry_run=true` lists the keys which
+/// would be expired instead
+pub async fn expire_entries(
+    State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path((project, prefix)): Path<(Uuid, String)>,
+    Query(query): Query<DryRunQuery>,
+    headers: HeaderMap,
+) -> Result<Json<AffectedRows>> {
+    let prefix = config.normalize_key(prefix);
+    telemetry::record_prefix(project, &prefix);
+    let ttl_seconds = required_ttl(&headers)?;
+    let actor = audit::actor(&headers)?;
+
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    let mut db = DbTimer::default();
+    let mut tx = db.time(audit::begin(&mut conn, actor.as_deref())).await?;
+    let affected = db
+        .time(repository.expire_prefix(&mut tx, project, &prefix, ttl_seconds, query.dry_run))
+        .await?;
+    db.time(tx.commit()).await?;
+
+    logfire::info!(
+        "expired entries project={project} prefix={prefix} ttl_seconds={ttl_seconds} count={count} \
+         dry_run={dry_run} db_ms={db_ms}",
+        project = project.to_string(),
+        prefix = &prefix,
+        ttl_seconds = ttl_seconds,
+        count = affected.count,
+        dry_run = query.dry_run,
+        db_ms = db.ms(),
+    );
+
+    Ok(Json(affected))
+}
+
+/// Expiring with an empty prefix would hit the whole project, so it's always rejected
+pub async fn expire_entries_all() -> Result<Json<AffectedRows>> {
+    Err(AppError::Validation("prefix must not be empty".to_string()))
+}
+
+/// Set the mime type of every live, mutable entry under a prefix, e.g. to fix entries imported as
+/// `application/octet-stream`, `?dry_run=true` lists the keys which would be changed instead
+pub async fn retag_entries(
+    State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path((project, prefix)): Path<(Uuid, String)>,
+    Query(query): Query<DryRunQuery>,
+    headers: HeaderMap,
+    Json(request): Json<RetagRequest>,
+) -> Result<Json<AffectedRows>> {
+    let prefix = config.normalize_key(prefix);
+    telemetry::record_prefix(project, &prefix);
+    let mime_type = validate_mime_type(request.mime_type)?;
+    check_mime_type_allowed(&config, &mime_type)?;
+    let actor = audit::actor(&headers)?;
+
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    let mut db = DbTimer::default();
+    let mut tx = db.time(audit::begin(&mut conn, actor.as_deref())).await?;
+    let affected = db
+        .time(repository.retag_prefix(&mut tx, project, &prefix, &mime_type, actor.as_deref(), query.dry_run))
+        .await?;
+    db.time(tx.commit()).await?;
+
+    logfire::info!(
+        "retagged entries project={project} prefix={prefix} mime_type={mime_type} count={count} \
+         dry_run={dry_run} actor={actor:?} db_ms={db_ms}",
+        project = project.to_string(),
+        prefix = &prefix,
+        mime_type = &mime_type,
+        count = affected.count,
+        dry_run = query.dry_run,
+        actor = actor,
+        db_ms = db.ms(),
+    );
+
+    Ok(Json(affected))
+}
+
+/// As with expiry, retagging the whole project with an empty prefix is always rejected
+pub async fn retag_entries_all() -> Result<Json<AffectedRows>> {
+    Err(AppError::Validation("prefix must not be empty".to_string()))
+}
+
+/// Compare-and-swap on content: replace a live, mutable entry's content with `new_base64` only if it's currently
+/// exactly `expected_base64`, `409` if it isn't and `404` if there's no entry. The entry keeps its mime type,
+/// expiry and headers, its variants are dropped as on any overwrite.
+pub async fn swap_entry_value(
     State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
     Path((project, key)): Path<(Uuid, String)>,
     headers: HeaderMap,
-    body: Bytes,
-) -> Result<StatusCode> {
-    // Extract Content-Type, default to application/octet-stream
-    let mime_type = headers
-        .get(header::CONTENT_TYPE)
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("application/octet-stream")
-        .to_string();
+    Json(request): Json<SwapValueRequest>,
+) -> Result<Json<StoredEntry>> {
+    let key = config.normalize_key(key);
+    telemetry::record_key(project, &key);
+    let decode = |name: &str, value: &str| {
+        BASE64_STANDARD
+            .decode(value)
+            .map_err(|e| AppError::Validation(format!("invalid {name}: {e}")))
+    };
+    let expected = decode("expected_base64", &request.expected_base64)?;
+    let content = decode("new_base64", &request.new_base64)?;
+    let actor = audit::actor(&headers)?;
+
+    let _permit = uploads::acquire(&config, project)?;
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    let mut db = DbTimer::default();
+    let mut tx = db.time(audit::begin(&mut conn, actor.as_deref())).await?;
+    let swapped = db
+        .time(repository.swap_value(&mut tx, project, &key, &expected, &content, actor.as_deref()))
+        .await?;
+    let Some(Swapped {
+        mime_type,
+        immutable,
+        matches,
+    }) = swapped
+    else {
+        return Err(AppError::KeyNotFound { project, key });
+    };
+    if immutable {
+        return Err(AppError::Conflict(format!("entry {key:?} is immutable")));
+    }
+    if !matches {
+        return Err(AppError::Conflict(format!(
+            "entry {key:?} doesn't have the expected content"
+        )));
+    }
+    // returning before the commit rolls the update back
+    check_entry_size(&config, &mime_type, content.len())?;
+    check_json(&config, &mime_type, &content)?;
+    check_quota(&mut tx, &mut db, &config, project).await?;
+    db.time(tx.commit()).await?;
+    telemetry::record_entry_size(SizeOperation::Store, content.len());
+
+    logfire::info!(
+        "swapped entry value project={project} key={key} size={size} actor={actor:?} db_ms={db_ms}",
+        project = project.to_string(),
+        key = &key,
+        size = content.len(),
+        actor = actor,
+        db_ms = db.ms(),
+    );
+    let sha256 = hex::encode(Sha256::digest(&content));
+    Ok(Json(StoredEntry {
+        key,
+        size: content.len(),
+        mime_type,
+        etag: etag(&sha256),
+        sha256,
+    }))
+}
 
-    // Create project if it doesn't exist
-    sqlx::query("INSERT INTO projects (id) VALUES ($1) ON CONFLICT (id) DO NOTHING")
-        .bind(project)
-        .execute(&*pool)
+/// Set the TTL of every live entry to `X-TTL-Seconds` from now, optionally only those under `?prefix=`, e.g. to
+/// keep a cache warm after a deploy. Entries without an expiry get one too unless `?only_expiring=true`, immutable
+/// entries are left alone.
+pub async fn touch_all_entries(
+    State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path(project): Path<Uuid>,
+    Query(query): Query<TouchAllQuery>,
+    headers: HeaderMap,
+) -> Result<Json<AffectedRows>> {
+    let prefix = config.normalize_key(query.prefix.unwrap_or_default());
+    telemetry::record_prefix(project, &prefix);
+    let ttl_seconds = required_ttl(&headers)?;
+    let actor = audit::actor(&headers)?;
+
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    let mut db = DbTimer::default();
+    let mut tx = db.time(audit::begin(&mut conn, actor.as_deref())).await?;
+    let count = db
+        .time(repository.touch_prefix(&mut tx, project, &prefix, ttl_seconds, query.only_expiring))
         .await?;
+    db.time(tx.commit()).await?;
+
+    logfire::info!(
+        "touched entries project={project} prefix={prefix} ttl_seconds={ttl_seconds} count={count} db_ms={db_ms}",
+        project = project.to_string(),
+        prefix = &prefix,
+        ttl_seconds = ttl_seconds,
+        count = count,
+        db_ms = db.ms(),
+    );
+
+    Ok(Json(AffectedRows { count, keys: None }))
+}
 
-    // Upsert entry
-    sqlx::query(
-        r#"
-        INSERT INTO entries (project_id, key, mime_type, content, updated_at)
-        VALUES ($1, $2, $3, $4, NOW())
-        ON CONFLICT (project_id, key)
-        DO UPDATE SET
-            mime_type = EXCLUDED.mime_type,
-            content = EXCLUDED.content,
-            updated_at = NOW()
+/// `X-TTL-Seconds` for the bulk expiry endpoints, where it's required and `none` isn't allowed
+fn required_ttl(headers: &HeaderMap) -> Result<i64> {
+    headers
+        .get("x-ttl-seconds")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .filter(|ttl| *ttl >= 0)
+        .ok_or_else(|| AppError::Validation("X-TTL-Seconds header must be a non-negative integer".to_string()))
+}
+
+pub async fn store_entry(
+    State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path(EntryPath {
+        project,
+        namespace,
+        key,
+    }): Path<EntryPath>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    if namespace == CAS_NAMESPACE {
+        return Err(AppError::Forbidden(format!(
+            "the {CAS_NAMESPACE:?} namespace can only be written via the /cas route"
+        )));
+    }
+    let key = config.normalize_key(key);
+    let mime_type = body_mime_type(&config, &headers)?;
+    check_mime_type_allowed(&config, &mime_type)?;
+    let immutable = bool_header(&headers, "X-Immutable")?;
+    let variant = bool_header(&headers, "X-Variant")?;
+    let create_only = create_only(&headers)?;
+    let requirement = key_requirement(&config, &headers)?;
+
+    let ttl = store_ttl(&headers)?;
+    let response_headers = capture_response_headers(&headers)?;
+    let (created_at, updated_at) = client_timestamps(&config, &headers)?;
+    if variant
+        && (headers.contains_key("x-immutable")
+            || headers.contains_key("x-ttl-seconds")
+            || !response_headers.is_empty()
+            || created_at.is_some()
+            || updated_at.is_some())
+    {
+        return Err(AppError::Validation(
+            "variants share the entry's immutability, expiry, headers and timestamps, they can't be set with \
+             X-Variant"
+                .to_string(),
+        ));
+    }
+    if variant && create_only {
+        return Err(AppError::Validation(
+            "variants are of an existing entry, If-None-Match: * can't be used with X-Variant".to_string(),
+        ));
+    }
+    let body = decode_body(&headers, body, config.entry_size_limit(&mime_type))?;
+    check_entry_size(&config, &mime_type, body.len())?;
+
+    let new_entry = NewEntry {
+        namespace: &namespace,
+        key: &key,
+        mime_type: &mime_type,
+        content: &body,
+        immutable,
+        ttl,
+        response_headers,
+        actor: audit::actor(&headers)?,
+        created_at,
+        updated_at,
+        create_only,
+    };
+    let (inserted, quota_used) = if variant {
+        store_variant(&pool, &config, project, &new_entry, requirement.as_ref()).await?
+    } else {
+        upsert_entry(&pool, &*repository, &config, project, &new_entry, requirement.as_ref()).await?
+    };
+
+    Ok(store_response(
+        &config, &headers, project, inserted, quota_used, &new_entry,
+    ))
+}
+
+/// `created_at` and `updated_at`
+type ClientTimestamps = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+/// `X-Created-At` and `X-Updated-At` (RFC 3339) to keep an imported entry's original timestamps, only accepted with
+/// `CLIENT_TIMESTAMPS` so clients can't otherwise backdate entries
+fn client_timestamps(config: &Config, headers: &HeaderMap) -> Result<ClientTimestamps> {
+    let timestamp = |name: &str| {
+        headers
+            .get(name)
+            .map(|v| {
+                v.to_str()
+                    .ok()
+                    .and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+                    .map(|dt| dt.with_timezone(&Utc))
+                    .ok_or_else(|| AppError::Validation(format!("{name} header must be an RFC 3339 timestamp")))
+            })
+            .transpose()
+    };
+    let created_at = timestamp("X-Created-At")?;
+    let updated_at = timestamp("X-Updated-At")?;
+    if !config.client_timestamps && (created_at.is_some() || updated_at.is_some()) {
+        return Err(AppError::Forbidden(
+            "X-Created-At and X-Updated-At are only accepted with CLIENT_TIMESTAMPS".to_string(),
+        ));
+    }
+    if let (Some(created_at), Some(updated_at)) = (created_at, updated_at)
+        && created_at > updated_at
+    {
+        return Err(AppError::Validation(
+            "X-Created-At must not be after X-Updated-At".to_string(),
+        ));
+    }
+    Ok((created_at, updated_at))
+}
+
+/// `If-None-Match: *`, making a store create-only; checking against particular ETags isn't supported on store
+fn create_only(headers: &HeaderMap) -> Result<bool> {
+    match headers.get(header::IF_NONE_MATCH).map(HeaderValue::to_str) {
+        None => Ok(false),
+        Some(Ok(v)) if v.trim() == "*" => Ok(true),
+        Some(_) => Err(AppError::Validation(
+            "only If-None-Match: * is supported when storing".to_string(),
+        )),
+    }
+}
+
+/// Another key in the same namespace which must, or mustn't, have a live entry for a store to go ahead
+enum KeyRequirement {
+    Exists(String),
+    Absent(String),
+}
+
+/// `X-Require-Exists` or `X-Require-Absent`, naming the key a store depends on
+fn key_requirement(config: &Config, headers: &HeaderMap) -> Result<Option<KeyRequirement>> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .map(|v| {
+                v.to_str()
+                    .map(|key| config.normalize_key(key.to_string()))
+                    .map_err(|_| AppError::Validation(format!("{name} header must be visible ASCII")))
+            })
+            .transpose()
+    };
+    match (header("X-Require-Exists")?, header("X-Require-Absent")?) {
+        (None, None) => Ok(None),
+        (Some(key), None) => Ok(Some(KeyRequirement::Exists(key))),
+        (None, Some(key)) => Ok(Some(KeyRequirement::Absent(key))),
+        (Some(_), Some(_)) => Err(AppError::Validation(
+            "only one of X-Require-Exists and X-Require-Absent can be given".to_string(),
+        )),
+    }
+}
+
+/// Check a store's `KeyRequirement` in its transaction, `412` if it isn't met.
+///
+/// A required entry is locked with `FOR SHARE`, so it can't be deleted or overwritten until the store commits; an
+/// absent key has no row to lock, so a concurrent store of it isn't held off.
+async fn check_requirement(
+    conn: &mut PgConnection,
+    db: &mut DbTimer,
+    project: Uuid,
+    namespace: &str,
+    requirement: Option<&KeyRequirement>,
+) -> Result<()> {
+    let Some(requirement) = requirement else {
+        return Ok(());
+    };
+    let (key, must_exist) = match requirement {
+        KeyRequirement::Exists(key) => (key, true),
+        KeyRequirement::Absent(key) => (key, false),
+    };
+    let exists = db
+        .time(
+            sqlx::query_scalar!(
+                r#"
+        SELECT 1 AS "one!"
+        FROM entries
+        WHERE project_id = $1 AND namespace = $2 AND key = $3 AND (expires_at IS NULL OR expires_at > NOW())
+        FOR SHARE
         "#,
-    )
-    .bind(project)
-    .bind(&key)
-    .bind(&mime_type)
-    .bind(body.as_ref())
-    .execute(&*pool)
-    .await?;
+                project,
+                namespace,
+                key,
+            )
+            .fetch_optional(&mut *conn),
+        )
+        .await?
+        .is_some();
+    match (must_exist, exists) {
+        (true, false) => Err(AppError::PreconditionFailed(format!("entry {key:?} doesn't exist"))),
+        (false, true) => Err(AppError::PreconditionFailed(format!("entry {key:?} exists"))),
+        _ => Ok(()),
+    }
+}
 
-    Ok(StatusCode::CREATED)
+/// A `"true"` or `"false"` header, `false` when it's absent
+fn bool_header(headers: &HeaderMap, name: &str) -> Result<bool> {
+    match headers.get(name).map(HeaderValue::to_str) {
+        None | Some(Ok("false")) => Ok(false),
+        Some(Ok("true")) => Ok(true),
+        Some(_) => Err(AppError::Validation(format!(
+            "{name} header must be \"true\" or \"false\""
+        ))),
+    }
 }
 
-pub async fn delete_entry(State(pool): State<Pool>, Path((project, key)): Path<(Uuid, String)>) -> Result<StatusCode> {
-    let result = sqlx::query("DELETE FROM entries WHERE project_id = $1 AND key = $2")
-        .bind(project)
-        .bind(&key)
-        .execute(&*pool)
+/// Add or replace a variant of an existing live entry, returns `true` if the entry didn't already have a variant
+/// with this mime type, and the project's quota usage if it calls for a warning
+async fn store_variant(
+    pool: &Pool,
+    config: &Config,
+    project: Uuid,
+    variant: &NewEntry<'_>,
+    requirement: Option<&KeyRequirement>,
+) -> Result<(bool, Option<i64>)> {
+    telemetry::record_key(project, variant.key);
+    telemetry::record_namespace(variant.namespace);
+    telemetry::record_content(variant.mime_type, variant.content.len());
+    check_json(config, variant.mime_type, variant.content)?;
+
+    let _permit = uploads::acquire(config, project)?;
+    // `Create` so a tenant schema created before variants existed gets the variants table
+    let mut conn = tenant::connection(pool, config, project, Access::Create).await?;
+    let mut db = DbTimer::default();
+    let mut tx = db.time(audit::begin(&mut conn, variant.actor.as_deref())).await?;
+    check_requirement(&mut tx, &mut db, project, variant.namespace, requirement).await?;
+    let entry = db
+        .time(
+            sqlx::query!(
+                r#"
+        SELECT id, mime_type, immutable
+        FROM entries
+        WHERE project_id = $1 AND namespace = $2 AND key = $3 AND (expires_at IS NULL OR expires_at > NOW())
+        FOR UPDATE
+        "#,
+                project,
+                variant.namespace,
+                variant.key,
+            )
+            .fetch_optional(&mut *tx),
+        )
+        .await?
+        .ok_or_else(|| AppError::KeyNotFound {
+            project,
+            key: variant.key.to_string(),
+        })?;
+    if entry.immutable {
+        return Err(AppError::Conflict(format!("entry {:?} is immutable", variant.key)));
+    }
+    if config::mime_essence(&entry.mime_type) == config::mime_essence(variant.mime_type) {
+        return Err(AppError::Validation(format!(
+            "the entry itself is {}, store it without X-Variant to replace it",
+            entry.mime_type
+        )));
+    }
+    let inserted: bool = db
+        .time(
+            sqlx::query_scalar(
+                r#"
+        INSERT INTO entry_variants (entry_id, mime_type, content) VALUES ($1, $2, $3)
+        ON CONFLICT (entry_id, mime_type) DO UPDATE SET content = EXCLUDED.content, updated_at = NOW()
+        RETURNING (xmax = 0) AS inserted
+        "#,
+            )
+            .bind(entry.id)
+            .bind(variant.mime_type)
+            .bind(variant.content)
+            .fetch_one(&mut *tx),
+        )
         .await?;
+    let quota_used = check_quota(&mut tx, &mut db, config, project).await?;
+    db.time(tx.commit()).await?;
+    telemetry::record_entry_size(SizeOperation::Store, variant.content.len());
+
+    logfire::info!(
+        "stored variant project={project} key={key} mime_type={mime_type} size={size} inserted={inserted} \
+         actor={actor:?} db_ms={db_ms}",
+        project = project.to_string(),
+        key = variant.key.to_string(),
+        mime_type = variant.mime_type.to_string(),
+        size = variant.content.len(),
+        inserted = inserted,
+        actor = variant.actor.clone(),
+        db_ms = db.ms(),
+    );
+    Ok((inserted, quota_used))
+}
+
+/// JSON alternative to `store_entry` for clients where sending a raw body with a custom content type is awkward
+pub async fn store_entry_json(
+    State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path(project): Path<Uuid>,
+    headers: HeaderMap,
+    Json(request): Json<StoreEntryRequest>,
+) -> Result<Response> {
+    let immutable = request.immutable;
+    let (key, mime_type, content) = decode_store_request(&config, request)?;
+    let new_entry = NewEntry {
+        namespace: DEFAULT_NAMESPACE,
+        key: &key,
+        mime_type: &mime_type,
+        content: &content,
+        immutable,
+        ttl: Ttl::ProjectDefault,
+        response_headers: BTreeMap::new(),
+        actor: audit::actor(&headers)?,
+        created_at: None,
+        updated_at: None,
+        create_only: create_only(&headers)?,
+    };
+    let (inserted, quota_used) = upsert_entry(&pool, &*repository, &config, project, &new_entry, None).await?;
 
-    if result.rows_affected() == 0 {
-        return Err(AppError::KeyNotFound(key));
+    Ok(store_response(
+        &config, &headers, project, inserted, quota_used, &new_entry,
+    ))
+}
+
+/// Validate a JSON store request, returning the normalized key, the mime type and the decoded content
+pub fn decode_store_request(config: &Config, request: StoreEntryRequest) -> Result<(String, String, Vec<u8>)> {
+    let mime_type = validate_mime_type(request.mime_type)?;
+    check_mime_type_allowed(config, &mime_type)?;
+    let content = BASE64_STANDARD
+        .decode(&request.content_base64)
+        .map_err(|e| AppError::Validation(format!("invalid content_base64: {e}")))?;
+    check_entry_size(config, &mime_type, content.len())?;
+    Ok((config.normalize_key(request.key), mime_type, content))
+}
+
+/// `Content-Type` of a store body, defaulting to `application/octet-stream` unless `REQUIRE_CONTENT_TYPE` is set
+pub fn body_mime_type(config: &Config, headers: &HeaderMap) -> Result<String> {
+    match headers.get(header::CONTENT_TYPE) {
+        Some(v) => Ok(v.to_str().unwrap_or("application/octet-stream").to_string()),
+        None if config.require_content_type => Err(AppError::Validation("Content-Type header is required".to_string())),
+        None => Ok("application/octet-stream".to_string()),
+    }
+}
+
+/// Decompress a `Content-Encoding: gzip` or `deflate` store body, the decompressed content is what's stored, and
+/// decompressing stops after `limit` bytes
+pub fn decode_body(headers: &HeaderMap, body: Bytes, limit: usize) -> Result<Bytes> {
+    let Some(encoding) = headers.get(header::CONTENT_ENCODING) else {
+        return Ok(body);
+    };
+    let encoding = encoding.to_str().unwrap_or_default().trim().to_lowercase();
+    let mut decoded = Vec::new();
+    let limit = limit as u64;
+    // read one byte past the limit so we can tell if it was exceeded
+    let result = match encoding.as_str() {
+        "identity" => return Ok(body),
+        "gzip" | "x-gzip" => GzDecoder::new(&body[..]).take(limit + 1).read_to_end(&mut decoded),
+        "deflate" => ZlibDecoder::new(&body[..]).take(limit + 1).read_to_end(&mut decoded),
+        _ => return Err(AppError::UnsupportedMediaType(format!("Content-Encoding {encoding:?}"))),
+    };
+    result.map_err(|e| AppError::Validation(format!("invalid {encoding} body: {e}")))?;
+    if decoded.len() as u64 > limit {
+        return Err(AppError::PayloadTooLarge(format!(
+            "decompressed body exceeds {limit} bytes"
+        )));
+    }
+    Ok(decoded.into())
+}
+
+pub fn check_mime_type_allowed(config: &Config, mime_type: &str) -> Result<()> {
+    if config.is_mime_type_allowed(mime_type) {
+        Ok(())
+    } else {
+        Err(AppError::UnsupportedMediaType(mime_type.to_string()))
+    }
+}
+
+/// Check content against the size limit for its mime type, see [`Config::entry_size_limit`]
+pub fn check_entry_size(config: &Config, mime_type: &str, size: usize) -> Result<()> {
+    let limit = config.entry_size_limit(mime_type);
+    if size > limit {
+        Err(AppError::PayloadTooLarge(format!(
+            "{mime_type} entries are limited to {limit} bytes, got {size}"
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// With `VALIDATE_JSON`, check JSON entries parse, so a producer's bug fails its store rather than a later read
+pub fn check_json(config: &Config, mime_type: &str, content: &[u8]) -> Result<()> {
+    if config.validate_json && config::is_json_mime(mime_type) {
+        serde_json::from_slice::<serde::de::IgnoredAny>(content)
+            .map_err(|e| AppError::Validation(format!("content isn't valid JSON: {e}")))?;
+    }
+    Ok(())
+}
+
+/// `201 Created` for a new key, `200 OK` when an existing entry was overwritten
+pub fn store_status(inserted: bool) -> StatusCode {
+    if inserted { StatusCode::CREATED } else { StatusCode::OK }
+}
+
+/// Percentage of its `quota_bytes` a project is using, on stores which leave it at least `QUOTA_WARNING_PERCENT` full
+const QUOTA_WARNING: HeaderName = HeaderName::from_static("x-quota-warning");
+
+/// Empty body unless the client asks for JSON, in which case the stored entry is described so uploads can be
+/// verified without another request
+pub fn store_response(
+    config: &Config,
+    headers: &HeaderMap,
+    project: Uuid,
+    inserted: bool,
+    quota_used: Option<i64>,
+    entry: &NewEntry<'_>,
+) -> Response {
+    let status = store_status(inserted);
+    let location = [(header::LOCATION, entry_url(config, project, entry.namespace, entry.key))];
+    let mut response = if accepts(headers, "application/json") {
+        let sha256 = hex::encode(Sha256::digest(entry.content));
+        let stored = StoredEntry {
+            key: entry.key.to_string(),
+            size: entry.content.len(),
+            mime_type: entry.mime_type.to_string(),
+            etag: etag(&sha256),
+            sha256,
+        };
+        (status, location, Json(stored)).into_response()
+    } else {
+        (status, location).into_response()
+    };
+    if let Some(percent) = quota_used {
+        response.headers_mut().insert(QUOTA_WARNING, HeaderValue::from(percent));
+    }
+    response
+}
+
+/// Where the entry can be fetched, relative unless `PUBLIC_BASE_URL` is set
+fn entry_url(config: &Config, project: Uuid, namespace: &str, key: &str) -> String {
+    let base = config.public_base_url.as_deref().unwrap_or_default();
+    let key = utf8_percent_encode(key, KEY_ENCODE_SET);
+    if namespace == DEFAULT_NAMESPACE {
+        format!("{base}/project/{project}/get/{key}")
+    } else {
+        let namespace = utf8_percent_encode(namespace, KEY_ENCODE_SET);
+        format!("{base}/project/{project}/ns/{namespace}/get/{key}")
+    }
+}
+
+/// Index of the representation in `mime_types` which `Accept` prefers (by `q`, taking the most specific matching
+/// range for each), ties go to the earlier one; the first, the entry itself, is served when there's no `Accept`
+/// or it matches none of them, as before the entry had variants
+fn negotiate(headers: &HeaderMap, mime_types: &[&str]) -> usize {
+    let ranges: Vec<(String, f32)> = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let media_range = config::mime_essence(parts.next()?);
+            let q = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+            (!media_range.is_empty()).then_some((media_range, q))
+        })
+        .collect();
+    let quality = |mime_type: &str| {
+        let essence = config::mime_essence(mime_type);
+        ranges
+            .iter()
+            .filter(|(range, _)| config::mime_matches(range, &essence))
+            .max_by_key(|(range, _)| match range.as_str() {
+                "*/*" => 0,
+                range if range.ends_with("/*") => 1,
+                _ => 2,
+            })
+            .map_or(0.0, |(_, q)| *q)
+    };
+
+    let mut best = (0, quality(mime_types[0]));
+    for (index, mime_type) in mime_types.iter().enumerate().skip(1) {
+        let q = quality(mime_type);
+        if q > best.1 {
+            best = (index, q);
+        }
+    }
+    best.0
+}
+
+/// `Accept` explicitly includes `mime_type`, wildcards don't count so existing clients keep getting the default
+/// response
+fn accepts(headers: &HeaderMap, mime_type: &str) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|range| range.split(';').next().unwrap_or_default().trim() == mime_type)
+}
+
+/// `X-TTL-Seconds` on store, `none` stores without expiry even when the project has a default TTL
+pub fn store_ttl(headers: &HeaderMap) -> Result<Ttl> {
+    let Some(value) = headers.get("x-ttl-seconds") else {
+        return Ok(Ttl::ProjectDefault);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|v| match v {
+            "none" => Some(Ttl::Never),
+            _ => v.parse().ok().filter(|ttl| *ttl >= 0).map(Ttl::Seconds),
+        })
+        .ok_or_else(|| {
+            AppError::Validation("X-TTL-Seconds header must be a non-negative integer or \"none\"".to_string())
+        })
+}
+
+/// Upsert an entry, returns `true` if a new row was inserted rather than an existing one updated, and the
+/// project's quota usage if it calls for a warning
+async fn upsert_entry(
+    pool: &Pool,
+    repository: &dyn EntryRepository,
+    config: &Config,
+    project: Uuid,
+    entry: &NewEntry<'_>,
+    requirement: Option<&KeyRequirement>,
+) -> Result<(bool, Option<i64>)> {
+    telemetry::record_key(project, entry.key);
+    telemetry::record_namespace(entry.namespace);
+    telemetry::record_content(entry.mime_type, entry.content.len());
+
+    let _permit = uploads::acquire(config, project)?;
+    let mut conn = tenant::connection(pool, config, project, Access::Create).await?;
+    let mut db = DbTimer::default();
+    let mut tx = db.time(audit::begin(&mut conn, entry.actor.as_deref())).await?;
+    check_requirement(&mut tx, &mut db, project, entry.namespace, requirement).await?;
+    let written = write_entry(&mut tx, &mut db, repository, config, project, entry).await?;
+    db.time(tx.commit()).await?;
+
+    logfire::info!(
+        "stored entry project={project} key={key} size={size} inserted={inserted} actor={actor:?} db_ms={db_ms}",
+        project = project.to_string(),
+        key = entry.key.to_string(),
+        size = entry.content.len(),
+        inserted = written.is_some_and(|(inserted, _)| inserted),
+        actor = entry.actor.clone(),
+        db_ms = db.ms(),
+    );
+    written.ok_or_else(|| {
+        if entry.create_only {
+            AppError::PreconditionFailed(format!("entry {:?} already exists", entry.key))
+        } else {
+            AppError::Conflict(format!("entry {:?} is immutable", entry.key))
+        }
+    })
+}
+
+/// `EntryRepository::put` on a connection which is already in a transaction, with the project's checks: creating
+/// the project if needed (and allowed by `AUTO_CREATE_PROJECTS`), its key pattern and its quota. Returns whether
+/// the key had no live entry along with `check_quota`'s warning, or `None` like `put`
+pub async fn write_entry(
+    conn: &mut PgConnection,
+    db: &mut DbTimer,
+    repository: &dyn EntryRepository,
+    config: &Config,
+    project: Uuid,
+    entry: &NewEntry<'_>,
+) -> Result<Option<(bool, Option<i64>)>> {
+    check_json(config, entry.mime_type, entry.content)?;
+    ensure_project(conn, db, config, project).await?;
+    // content-addressed keys aren't chosen by clients
+    if entry.namespace != CAS_NAMESPACE {
+        check_key_pattern(conn, db, project, entry.key).await?;
     }
 
-    Ok(StatusCode::NO_CONTENT)
+    let Some(inserted) = db.time(repository.put(conn, project, entry)).await? else {
+        return Ok(None);
+    };
+    let quota_used = check_quota(conn, db, config, project).await?;
+    telemetry::record_entry_size(SizeOperation::Store, entry.content.len());
+    telemetry::record_entry_write(project, inserted);
+    Ok(Some((inserted, quota_used)))
+}
+
+/// Create the project for a write if it doesn't exist and `AUTO_CREATE_PROJECTS` allows it, otherwise `404`
+pub async fn ensure_project(conn: &mut PgConnection, db: &mut DbTimer, config: &Config, project: Uuid) -> Result<()> {
+    if config.auto_create_projects {
+        db.time(
+            sqlx::query("INSERT INTO projects (id) VALUES ($1) ON CONFLICT (id) DO NOTHING")
+                .bind(project)
+                .execute(&mut *conn),
+        )
+        .await?;
+    } else if !project_exists(conn, db, project).await? {
+        return Err(AppError::ProjectNotFound(project));
+    }
+    Ok(())
+}
+
+/// Compiled `key_pattern`s by project, recompiled when a project's pattern changes
+static KEY_PATTERNS: Mutex<BTreeMap<Uuid, Regex>> = Mutex::new(BTreeMap::new());
+
+/// Fail with `400` if the project has a `key_pattern` which `key` doesn't match
+pub async fn check_key_pattern(conn: &mut PgConnection, db: &mut DbTimer, project: Uuid, key: &str) -> Result<()> {
+    let pattern = db
+        .time(sqlx::query_scalar!("SELECT key_pattern FROM projects WHERE id = $1", project).fetch_optional(&mut *conn))
+        .await?
+        .flatten();
+    let Some(pattern) = pattern else {
+        return Ok(());
+    };
+    let regex = {
+        let mut patterns = KEY_PATTERNS.lock().expect("key patterns poisoned");
+        match patterns.get(&project) {
+            Some(regex) if regex.as_str() == pattern => regex.clone(),
+            _ => {
+                // checked when the pattern was set, but it could have been set with another version of `regex`
+                let regex = Regex::new(&pattern)
+                    .map_err(|e| AppError::Internal(format!("project's key_pattern is invalid: {e}")))?;
+                patterns.insert(project, regex.clone());
+                regex
+            }
+        }
+    };
+    if regex.is_match(key) {
+        Ok(())
+    } else {
+        Err(AppError::InvalidKey(format!(
+            "{key:?} doesn't match the project's key pattern {pattern:?}"
+        )))
+    }
+}
+
+/// Fail if the project's live entries and their variants, including one just written on `conn`, exceed its
+/// `quota_bytes`, rolling back the write with the transaction; otherwise the percentage of the quota they use,
+/// rounded down, if that's at least `QUOTA_WARNING_PERCENT`, for the `X-Quota-Warning` header on store. Concurrent
+/// stores can each pass before seeing the other, so a project can briefly go over by up to one entry per writer.
+pub async fn check_quota(
+    conn: &mut PgConnection,
+    db: &mut DbTimer,
+    config: &Config,
+    project: Uuid,
+) -> Result<Option<i64>> {
+    let usage = db
+        .time(
+            sqlx::query!(
+                r#"
+        SELECT
+            quota_bytes AS "quota_bytes!",
+            (
+                SELECT COALESCE(SUM(size), 0) FROM (
+                    SELECT octet_length(content) AS size FROM entries
+                    WHERE project_id = $1 AND (expires_at IS NULL OR expires_at > NOW())
+                    UNION ALL
+                    SELECT octet_length(v.content) FROM entry_variants v JOIN entries e ON e.id = v.entry_id
+                    WHERE e.project_id = $1 AND (e.expires_at IS NULL OR e.expires_at > NOW())
+                ) sizes
+            ) AS "used!"
+        FROM projects
+        WHERE id = $1 AND quota_bytes IS NOT NULL
+        "#,
+                project
+            )
+            .fetch_optional(&mut *conn),
+        )
+        .await?;
+    let Some(usage) = usage else {
+        return Ok(None);
+    };
+    if usage.used > usage.quota_bytes {
+        return Err(AppError::QuotaExceeded(project));
+    }
+    if config.quota_warning_percent == 0 || usage.quota_bytes == 0 {
+        return Ok(None);
+    }
+    let used_percent = usage.used * 100 / usage.quota_bytes;
+    Ok((used_percent >= i64::from(config.quota_warning_percent)).then_some(used_percent))
+}
+
+pub async fn delete_entry(
+    State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path(EntryPath {
+        project,
+        namespace,
+        key,
+    }): Path<EntryPath>,
+    Query(query): Query<DeleteQuery>,
+    headers: HeaderMap,
+) -> Result<StatusCode> {
+    let actor = audit::actor(&headers)?;
+    let key = config.normalize_key(key);
+    telemetry::record_key(project, &key);
+    telemetry::record_namespace(&namespace);
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    let mut db = DbTimer::default();
+    let mut tx = db.time(audit::begin(&mut conn, actor.as_deref())).await?;
+    let immutable = match &query.variant {
+        Some(variant) => {
+            db.time(repository.delete_variant(&mut tx, project, &namespace, &key, variant))
+                .await?
+        }
+        None => db.time(repository.delete(&mut tx, project, &namespace, &key)).await?,
+    };
+    db.time(tx.commit()).await?;
+
+    logfire::info!(
+        "deleted entry project={project} key={key} variant={variant:?} found={found} actor={actor:?} db_ms={db_ms}",
+        project = project.to_string(),
+        key = &key,
+        variant = query.variant.clone(),
+        found = immutable.is_some(),
+        actor = actor,
+        db_ms = db.ms(),
+    );
+    match immutable {
+        None if query.idempotent => Ok(StatusCode::NO_CONTENT),
+        None => Err(AppError::KeyNotFound { project, key }),
+        Some(true) => Err(AppError::Forbidden(format!("entry {key:?} is immutable"))),
+        Some(false) => Ok(StatusCode::NO_CONTENT),
+    }
+}
+
+/// Store or delete the key spelled out by the path of a fixed route (`audit`, `meta/<key>`, `cas/<hash>`...), as
+/// the method fallback of routes which would otherwise shadow the catch-all store and delete routes for that key
+#[allow(clippy::too_many_arguments)]
+pub async fn store_or_delete_shadowed_key(
+    pool: State<Pool>,
+    repository: State<Repository>,
+    config: State<Arc<Config>>,
+    Path(path): Path<ShadowedPath>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    // everything after `/project/<id>/`, or `/project/<id>/ns/<namespace>/`
+    let skip = if path.namespace.is_some() { 4 } else { 2 };
+    let raw_key = uri
+        .path()
+        .trim_start_matches('/')
+        .splitn(skip + 1, '/')
+        .nth(skip)
+        .unwrap_or_default();
+    let key = percent_decode_str(raw_key)
+        .decode_utf8()
+        .map_err(|_| AppError::Validation("key must be valid UTF-8".to_string()))?
+        .into_owned();
+    let entry_path = EntryPath {
+        project: path.project,
+        namespace: path.namespace.unwrap_or_else(|| DEFAULT_NAMESPACE.to_string()),
+        key,
+    };
+    match method {
+        Method::POST => store_entry(pool, repository, config, Path(entry_path), headers, body).await,
+        Method::DELETE => {
+            let query = Query::try_from_uri(&uri).map_err(|e| AppError::Validation(e.body_text()))?;
+            let status = delete_entry(pool, repository, config, Path(entry_path), query, headers).await?;
+            Ok(status.into_response())
+        }
+        _ => Ok(StatusCode::METHOD_NOT_ALLOWED.into_response()),
+    }
 }
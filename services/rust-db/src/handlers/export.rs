@@ -0,0 +1,402 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use base64::prelude::*;
+use chrono::Utc;
+use futures::{StreamExt, stream};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    audit,
+    config::Config,
+    error::{AppError, Result},
+    extract::Path,
+    handlers::entries,
+    like,
+    models::{
+        CAS_NAMESPACE, DEFAULT_NAMESPACE, DumpEntry, ExportManifest, ExportQuery, ManifestEntry, RestoreError,
+        RestoreReport, StoreEntryRequest,
+    },
+    repository::{EntryRepository, NewEntry, Ttl},
+    state::{Pool, Repository},
+    telemetry::{self, DbTimer},
+    tenant::{self, Access, PoolConnection},
+    uploads,
+};
+
+/// Export a project's live entries as a tar archive, optionally only those matching `?prefix=` and `?mime=`.
+///
+/// Content is stored at `entries/<n>` rather than under the key, since keys aren't necessarily valid paths,
+/// `manifest.json` maps each key to its file and records the filters applied.
+pub async fn export_entries(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Path(project): Path<Uuid>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response> {
+    let prefix = query.prefix.map(|prefix| config.normalize_key(prefix));
+    let pattern = like::escape_like_prefix(prefix.as_deref().unwrap_or_default());
+    telemetry::record_prefix(project, prefix.as_deref().unwrap_or_default());
+    let mime_pattern = query.mime.as_deref().map(entries::mime_patterns).transpose()?;
+
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    let mut db = DbTimer::default();
+    let rows = db
+        .time(
+            sqlx::query!(
+                r#"
+        SELECT key, mime_type, content, updated_at, encode(sha256(content), 'hex') AS "sha256!"
+        FROM entries
+        WHERE project_id = $1
+            AND namespace = $2
+            AND key LIKE $3
+            AND (expires_at IS NULL OR expires_at > NOW())
+            AND ($4::text[] IS NULL OR lower(trim(split_part(mime_type, ';', 1))) LIKE ANY($4))
+        ORDER BY key
+        "#,
+                project,
+                DEFAULT_NAMESPACE,
+                pattern,
+                mime_pattern.as_deref(),
+            )
+            .fetch_all(&mut conn),
+        )
+        .await?;
+
+    let mut archive = tar::Builder::new(Vec::new());
+    let mut manifest = ExportManifest {
+        project,
+        exported_at: Utc::now(),
+        prefix,
+        mime: query.mime,
+        entries: Vec::with_capacity(rows.len()),
+    };
+    for (index, row) in rows.into_iter().enumerate() {
+        let path = format!("entries/{index}");
+        append_file(&mut archive, &path, &row.content, row.updated_at.timestamp())?;
+        manifest.entries.push(ManifestEntry {
+            key: row.key,
+            path,
+            mime_type: row.mime_type,
+            size: row.content.len() as i64,
+            sha256: row.sha256,
+        });
+    }
+    let manifest_json = serde_json::to_vec_pretty(&manifest).map_err(|e| AppError::Internal(e.to_string()))?;
+    append_file(
+        &mut archive,
+        "manifest.json",
+        &manifest_json,
+        manifest.exported_at.timestamp(),
+    )?;
+    let archive = archive
+        .into_inner()
+        .map_err(|e| AppError::Internal(format!("failed to build archive: {e}")))?;
+
+    logfire::info!(
+        "exported entries project={project} count={count} size={size} db_ms={db_ms}",
+        project = project.to_string(),
+        count = manifest.entries.len(),
+        size = archive.len(),
+        db_ms = db.ms(),
+    );
+
+    let disposition = format!("attachment; filename=\"{project}.tar\"");
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        archive,
+    )
+        .into_response())
+}
+
+fn append_file(archive: &mut tar::Builder<Vec<u8>>, path: &str, data: &[u8], mtime: i64) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime.max(0) as u64);
+    archive
+        .append_data(&mut header, path, data)
+        .map_err(|e| AppError::Internal(format!("failed to build archive: {e}")))
+}
+
+/// Stream every live entry in every namespace as ND-JSON, with base64 content, for backups which
+/// `restore_dump` loads back and which can be filtered with `jq`.
+///
+/// Like streamed lists, rows are fetched one at a time by a task which owns the connection, so only a channel's
+/// worth of entries is held in memory however large the project. A failure midway aborts the response.
+pub async fn dump_entries(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Path(project): Path<Uuid>,
+) -> Result<Response> {
+    let mut db = DbTimer::default();
+    entries::check_project_exists(&pool, &mut db, &config, project).await?;
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+
+    let (tx, rx) = mpsc::channel::<std::result::Result<Bytes, sqlx::Error>>(16);
+    let task = async move {
+        let mut rows = sqlx::query!(
+            r#"
+        SELECT
+            namespace,
+            key,
+            mime_type,
+            content,
+            immutable,
+            expires_at,
+            response_headers AS "response_headers: sqlx::types::Json<BTreeMap<String, String>>",
+            created_at,
+            updated_at
+        FROM entries
+        WHERE project_id = $1 AND (expires_at IS NULL OR expires_at > NOW())
+        ORDER BY namespace, key
+        "#,
+            project
+        )
+        .fetch(&mut conn);
+        let mut count: usize = 0;
+        while let Some(row) = rows.next().await {
+            let line = row.map(|row| {
+                let entry = DumpEntry {
+                    namespace: row.namespace,
+                    key: row.key,
+                    mime_type: row.mime_type,
+                    content_base64: BASE64_STANDARD.encode(&row.content),
+                    immutable: row.immutable,
+                    expires_at: row.expires_at,
+                    response_headers: row.response_headers.0,
+                    created_at: Some(row.created_at),
+                    updated_at: Some(row.updated_at),
+                };
+                let mut line = serde_json::to_vec(&entry).expect("DumpEntry is always serializable");
+                line.push(b'\n');
+                Bytes::from(line)
+            });
+            let failed = line.is_err();
+            // a send error means the client has gone away
+            if tx.send(line).await.is_err() || failed {
+                break;
+            }
+            count += 1;
+        }
+        logfire::info!(
+            "dumped entries project={project} count={count}",
+            project = project.to_string(),
+            count = count,
+        );
+    };
+    tokio::spawn(task.instrument(tracing::Span::current()));
+
+    let disposition = format!("attachment; filename=\"{project}.ndjson\"");
+    let body = Body::from_stream(stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (line, rx))
+    }));
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, entries::NDJSON.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Failures past this many are counted but not listed in the report
+const MAX_REPORTED_ERRORS: usize = 100;
+
+/// Restore a `dump_entries` dump, upserting an entry per line as the body arrives so a restore of any size is
+/// never held in memory.
+///
+/// Each entry is written in its own transaction, and a line which can't be restored (malformed, too large, over the
+/// quota, immutable...) is reported by line number without stopping the restore, so a failed restore can be run
+/// again. Entries keep their expiry, already expired ones are skipped, and their timestamps only with
+/// `CLIENT_TIMESTAMPS`.
+pub async fn restore_dump(
+    State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path(project): Path<Uuid>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<RestoreReport>> {
+    let actor = audit::actor(&headers)?;
+    let _permit = uploads::acquire(&config, project)?;
+    let mut conn = tenant::connection(&pool, &config, project, Access::Create).await?;
+    let mut db = DbTimer::default();
+    // base64 makes content a third larger, the rest of a line is small
+    let max_line = config.max_body_size().div_ceil(3) * 4 + 64 * 1024;
+
+    let mut report = RestoreReport {
+        restored: 0,
+        expired: 0,
+        failed: 0,
+        errors: Vec::new(),
+    };
+    let mut line_number: u64 = 0;
+    let mut buffer: Vec<u8> = Vec::new();
+    // how much of `buffer` is known not to contain a newline
+    let mut searched = 0;
+    // set while discarding the rest of a line which was too long
+    let mut discarding = false;
+    let mut chunks = body.into_data_stream();
+    loop {
+        let chunk = chunks
+            .next()
+            .await
+            .transpose()
+            .map_err(|e| AppError::Validation(format!("failed to read the request body: {e}")))?;
+        let finished = chunk.is_none();
+        match chunk {
+            Some(chunk) => buffer.extend_from_slice(&chunk),
+            // the last line needn't end with a newline
+            None if !buffer.is_empty() => buffer.push(b'\n'),
+            None => {}
+        }
+
+        let mut start = 0;
+        while let Some(end) = buffer[searched..].iter().position(|b| *b == b'\n') {
+            let end = searched + end;
+            let line = &buffer[start..end];
+            start = end + 1;
+            searched = start;
+            line_number += 1;
+            if discarding {
+                discarding = false;
+            } else if !line.trim_ascii().is_empty() {
+                let outcome = restore_line(
+                    &mut conn,
+                    &mut db,
+                    &*repository,
+                    &config,
+                    project,
+                    actor.as_deref(),
+                    line,
+                )
+                .await;
+                record(&mut report, line_number, outcome)?;
+            }
+        }
+        buffer.drain(..start);
+        searched = buffer.len();
+        if finished {
+            break;
+        }
+        if buffer.len() > max_line {
+            if !discarding {
+                let error = AppError::PayloadTooLarge(format!("lines are limited to {max_line} bytes"));
+                record(&mut report, line_number + 1, Err(error))?;
+            }
+            discarding = true;
+            buffer.clear();
+            searched = 0;
+        }
+    }
+
+    logfire::info!(
+        "restored dump project={project} restored={restored} expired={expired} failed={failed} actor={actor:?} \
+         db_ms={db_ms}",
+        project = project.to_string(),
+        restored = report.restored,
+        expired = report.expired,
+        failed = report.failed,
+        actor = actor,
+        db_ms = db.ms(),
+    );
+    Ok(Json(report))
+}
+
+/// Whether a line was restored, skipped as expired, or failed; server errors abort the restore
+fn record(report: &mut RestoreReport, line: u64, outcome: Result<bool>) -> Result<()> {
+    match outcome {
+        Ok(true) => report.restored += 1,
+        Ok(false) => report.expired += 1,
+        Err(e) if e.status().is_server_error() => return Err(e),
+        Err(e) => {
+            report.failed += 1;
+            if report.errors.len() < MAX_REPORTED_ERRORS {
+                report.errors.push(RestoreError {
+                    line,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Upsert the entry on one line of a dump, `false` if it has expired since it was dumped
+async fn restore_line(
+    conn: &mut PoolConnection,
+    db: &mut DbTimer,
+    repository: &dyn EntryRepository,
+    config: &Config,
+    project: Uuid,
+    actor: Option<&str>,
+    line: &[u8],
+) -> Result<bool> {
+    let entry: DumpEntry =
+        serde_json::from_slice(line).map_err(|e| AppError::Validation(format!("invalid line: {e}")))?;
+    let request = StoreEntryRequest {
+        key: entry.key,
+        mime_type: entry.mime_type,
+        content_base64: entry.content_base64,
+        immutable: entry.immutable,
+    };
+    let (key, mime_type, content) = entries::decode_store_request(config, request)?;
+    if entry.namespace == CAS_NAMESPACE && key != hex::encode(Sha256::digest(&content)) {
+        return Err(AppError::Validation(format!(
+            "{CAS_NAMESPACE:?} entries must be stored under the SHA-256 of their content"
+        )));
+    }
+    entries::check_response_headers(&entry.response_headers)?;
+    let ttl = match entry.expires_at {
+        None => Ttl::Never,
+        Some(expires_at) => {
+            let remaining_ms = (expires_at - Utc::now()).num_milliseconds();
+            if remaining_ms <= 0 {
+                return Ok(false);
+            }
+            Ttl::Seconds((remaining_ms + 999) / 1000)
+        }
+    };
+    let (created_at, updated_at) = if config.client_timestamps {
+        (entry.created_at, entry.updated_at)
+    } else {
+        (None, None)
+    };
+
+    let new_entry = NewEntry {
+        namespace: &entry.namespace,
+        key: &key,
+        mime_type: &mime_type,
+        content: &content,
+        immutable: entry.immutable,
+        ttl,
+        response_headers: entry.response_headers,
+        actor: actor.map(str::to_string),
+        created_at,
+        updated_at,
+        create_only: false,
+    };
+    let mut tx = db.time(audit::begin(conn, actor)).await?;
+    let written = entries::write_entry(&mut tx, db, repository, config, project, &new_entry).await?;
+    db.time(tx.commit()).await?;
+    match written {
+        Some(_) => Ok(true),
+        None => Err(AppError::Conflict(format!("entry {key:?} is immutable"))),
+    }
+}
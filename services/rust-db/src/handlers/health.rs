@@ -0,0 +1,37 @@
+use axum::{extract::State, http::StatusCode};
+
+use crate::{error::Result, shutdown, state::Pool};
+
+/// Schema version this build expects, must match the `schema_migrations` insert at the end of `schema.sql`
+pub const SCHEMA_VERSION: i32 = 18;
+
+pub async fn health(State(pool): State<Pool>) -> Result<&'static str> {
+    sqlx::query("SELECT 1").execute(&*pool).await?;
+    Ok("OK")
+}
+
+/// Readiness check, unlike `health` this returns `503` until the database schema is at least `SCHEMA_VERSION`,
+/// and again once the service starts shutting down
+pub async fn ready(State(pool): State<Pool>) -> (StatusCode, String) {
+    if shutdown::is_draining() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "shutting down".to_string());
+    }
+    let version = sqlx::query_scalar!("SELECT MAX(version) FROM schema_migrations")
+        .fetch_one(&*pool)
+        .await;
+    match version {
+        Ok(Some(version)) if version >= SCHEMA_VERSION => (StatusCode::OK, "OK".to_string()),
+        Ok(version) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!(
+                "schema version {} is behind expected version {SCHEMA_VERSION}",
+                version.unwrap_or_default()
+            ),
+        ),
+        // most likely `schema_migrations` doesn't exist yet
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("failed to check schema version: {e}"),
+        ),
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+    extract::Path,
+    handlers::entries,
+    like,
+    models::{JsonQuery, JsonQueryPage, KeyInfo},
+    state::Pool,
+    telemetry::{self, DbTimer},
+    tenant::{self, Access},
+};
+
+/// Keys of JSON entries (`application/json` or `+json`) whose content matches a SQL/JSON path, in key order.
+///
+/// A predicate path matches documents where it's true, and any other path documents where it selects something.
+/// Entries whose content isn't JSON are skipped rather than failing the query. Documents are parsed as they're
+/// scanned, there's no index, so a narrow `?prefix=` keeps queries of large projects quick.
+pub async fn query_json(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Path(project): Path<Uuid>,
+    Query(query): Query<JsonQuery>,
+) -> Result<Json<JsonQueryPage>> {
+    let limit = match query.limit {
+        Some(0) => return Err(AppError::Validation("limit must be at least 1".to_string())),
+        Some(limit) => limit.min(config.max_list_limit.get()),
+        None => config.max_list_limit.get(),
+    };
+    let prefix = config.normalize_key(query.prefix);
+    telemetry::record_prefix(project, &prefix);
+    telemetry::record_namespace(&query.namespace);
+    let mut db = DbTimer::default();
+    entries::check_project_exists(&pool, &mut db, &config, project).await?;
+
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    db.time(sqlx::query("SELECT $1::jsonpath").bind(&query.path).execute(&mut conn))
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(e) => AppError::Validation(format!("invalid path: {}", e.message())),
+            e => AppError::Database(e),
+        })?;
+    // one extra row tells us whether there's another page
+    let mut keys = db
+        .time(
+            sqlx::query_as!(
+                KeyInfo,
+                r#"
+        SELECT key, mime_type, octet_length(content)::bigint AS "size!"
+        FROM entries
+        WHERE project_id = $1
+            AND namespace = $2
+            AND key LIKE $3
+            AND (expires_at IS NULL OR expires_at > NOW())
+            AND (
+                lower(trim(split_part(mime_type, ';', 1))) = 'application/json'
+                OR lower(trim(split_part(mime_type, ';', 1))) LIKE '%+json'
+            )
+            AND ($4::text IS NULL OR key > $4)
+            AND COALESCE(
+                jsonb_path_match(try_jsonb(content), $5::text::jsonpath, '{}', true),
+                jsonb_path_exists(try_jsonb(content), $5::text::jsonpath, '{}', true)
+            )
+        ORDER BY key
+        LIMIT $6
+        "#,
+                project,
+                &query.namespace,
+                like::escape_like_prefix(&prefix),
+                query.after.as_deref(),
+                &query.path,
+                i64::from(limit) + 1,
+            )
+            .fetch_all(&mut conn),
+        )
+        .await?;
+
+    let next = if keys.len() > limit as usize {
+        keys.truncate(limit as usize);
+        keys.last().map(|key| key.key.clone())
+    } else {
+        None
+    };
+    logfire::info!(
+        "queried JSON entries project={project} json_path={json_path} count={count} db_ms={db_ms}",
+        project = project.to_string(),
+        json_path = query.path,
+        count = keys.len(),
+        db_ms = db.ms(),
+    );
+    Ok(Json(JsonQueryPage { keys, next }))
+}
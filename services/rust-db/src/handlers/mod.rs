@@ -1 +1,12 @@
+pub mod admin;
+pub mod audit;
+pub mod batch;
+pub mod cas;
+pub mod changes;
 pub mod entries;
+pub mod export;
+pub mod health;
+pub mod jsonquery;
+pub mod resumable;
+pub mod signed;
+pub mod usage;
@@ -0,0 +1,278 @@
+//! Resumable uploads, for large entries over unreliable links: an upload is started with the content's length,
+//! its content is sent in chunks each at the offset it continues from, and the chunk which completes it stores the
+//! entry. After a dropped connection `HEAD` says where to resume.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{
+    audit,
+    config::Config,
+    error::{AppError, Result},
+    extract::Path,
+    handlers::entries,
+    models::{CAS_NAMESPACE, NewUploadRequest, Upload},
+    repository::{NewEntry, Ttl},
+    state::{Pool, Repository},
+    telemetry::DbTimer,
+    tenant::{self, Access},
+    uploads,
+};
+
+/// Bytes of the upload received so far, which is where the next chunk must start
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+
+/// Size of the whole content, as declared when the upload was started
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+
+/// Start an upload of an entry, `201` with the upload's URL in `Location`.
+///
+/// The key, mime type and length are checked now rather than when the last chunk arrives, so an upload which
+/// could never be stored is refused before any of it is sent. Uploads nobody has sent a chunk to for
+/// `UPLOAD_EXPIRY_SECS` are abandoned.
+pub async fn create_upload(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Path(project): Path<Uuid>,
+    Json(request): Json<NewUploadRequest>,
+) -> Result<Response> {
+    if request.namespace == CAS_NAMESPACE {
+        return Err(AppError::Forbidden(format!(
+            "the {CAS_NAMESPACE:?} namespace can only be written via the /cas route"
+        )));
+    }
+    let key = config.normalize_key(request.key);
+    let mime_type = entries::validate_mime_type(request.mime_type)?;
+    entries::check_mime_type_allowed(&config, &mime_type)?;
+    let Ok(length) = usize::try_from(request.length) else {
+        return Err(AppError::Validation("length must not be negative".to_string()));
+    };
+    entries::check_entry_size(&config, &mime_type, length)?;
+
+    let mut conn = tenant::connection(&pool, &config, project, Access::Create).await?;
+    let mut db = DbTimer::default();
+    entries::ensure_project(conn.as_mut(), &mut db, &config, project).await?;
+    entries::check_key_pattern(conn.as_mut(), &mut db, project, &key).await?;
+    db.time(
+        sqlx::query("DELETE FROM uploads WHERE project_id = $1 AND expires_at <= NOW()")
+            .bind(project)
+            .execute(&mut conn),
+    )
+    .await?;
+    let (id, expires_at): (Uuid, DateTime<Utc>) = db
+        .time(
+            sqlx::query_as(
+                r#"
+        INSERT INTO uploads (project_id, namespace, key, mime_type, immutable, length, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, NOW() + $7 * INTERVAL '1 second')
+        RETURNING id, expires_at
+        "#,
+            )
+            .bind(project)
+            .bind(&request.namespace)
+            .bind(&key)
+            .bind(&mime_type)
+            .bind(request.immutable)
+            .bind(request.length)
+            .bind(config.upload_expiry.as_secs() as i64)
+            .fetch_one(&mut conn),
+        )
+        .await?;
+
+    logfire::info!(
+        "started upload project={project} upload_id={upload_id} key={key} length={length} db_ms={db_ms}",
+        project = project.to_string(),
+        upload_id = id.to_string(),
+        key = key.clone(),
+        length = request.length,
+        db_ms = db.ms(),
+    );
+    let base = config.public_base_url.as_deref().unwrap_or_default();
+    let location = format!("{base}/project/{project}/uploads/{id}");
+    let upload = Upload {
+        id,
+        namespace: request.namespace,
+        key,
+        mime_type,
+        length: request.length,
+        offset: 0,
+        expires_at,
+    };
+    Ok((StatusCode::CREATED, [(header::LOCATION, location)], Json(upload)).into_response())
+}
+
+/// How much of an upload has been received, as `Upload-Offset`, so a client can resume after losing a response
+pub async fn upload_status(
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
+    Path((project, id)): Path<(Uuid, Uuid)>,
+) -> Result<Response> {
+    let mut conn = tenant::connection(&pool, &config, project, Access::Existing).await?;
+    let mut db = DbTimer::default();
+    let row = db
+        .time(
+            sqlx::query!(
+                "SELECT received, length FROM uploads WHERE id = $1 AND project_id = $2 AND expires_at > NOW()",
+                id,
+                project,
+            )
+            .fetch_optional(&mut conn),
+        )
+        .await?
+        .ok_or(AppError::UploadNotFound(id))?;
+    Ok((
+        StatusCode::OK,
+        [
+            (UPLOAD_OFFSET, HeaderValue::from(row.received)),
+            (UPLOAD_LENGTH, HeaderValue::from(row.length)),
+            (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+        ],
+    )
+        .into_response())
+}
+
+/// Append a chunk at `Upload-Offset`, which must be the number of bytes received so far, otherwise `409`; `204`
+/// with the new `Upload-Offset`, or once the upload is complete the response of a store of the whole content.
+///
+/// The chunk and the store of a completed upload share a transaction, so if the store fails (the entry is
+/// immutable, say, or the project is over its quota) the chunk isn't kept either and can be sent again.
+pub async fn append_upload(
+    State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path((project, id)): Path<(Uuid, Uuid)>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    let offset: i64 = headers
+        .get(&UPLOAD_OFFSET)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| AppError::Validation("Upload-Offset header must be a number of bytes".to_string()))?;
+    let actor = audit::actor(&headers)?;
+    let _permit = uploads::acquire(&config, project)?;
+    let mut conn = tenant::connection(&pool, &config, project, Access::Create).await?;
+    let mut db = DbTimer::default();
+    let mut tx = db.time(audit::begin(&mut conn, actor.as_deref())).await?;
+    let upload = db
+        .time(
+            sqlx::query!(
+                r#"
+        SELECT namespace, key, mime_type, immutable, length, received
+        FROM uploads
+        WHERE id = $1 AND project_id = $2 AND expires_at > NOW()
+        FOR UPDATE
+        "#,
+                id,
+                project,
+            )
+            .fetch_optional(&mut *tx),
+        )
+        .await?
+        .ok_or(AppError::UploadNotFound(id))?;
+    if offset != upload.received {
+        return Err(AppError::Conflict(format!(
+            "upload is at offset {}, not {offset}",
+            upload.received
+        )));
+    }
+    let received = upload.received + body.len() as i64;
+    if received > upload.length {
+        return Err(AppError::PayloadTooLarge(format!(
+            "chunk goes past the upload's length of {} bytes",
+            upload.length
+        )));
+    }
+    if !body.is_empty() {
+        db.time(
+            sqlx::query(r#"INSERT INTO upload_chunks (upload_id, "offset", content) VALUES ($1, $2, $3)"#)
+                .bind(id)
+                .bind(offset)
+                .bind(body.as_ref())
+                .execute(&mut *tx),
+        )
+        .await?;
+    }
+
+    if received < upload.length {
+        db.time(
+            sqlx::query(
+                "UPDATE uploads SET received = $2, expires_at = NOW() + $3 * INTERVAL '1 second' WHERE id = $1",
+            )
+            .bind(id)
+            .bind(received)
+            .bind(config.upload_expiry.as_secs() as i64)
+            .execute(&mut *tx),
+        )
+        .await?;
+        db.time(tx.commit()).await?;
+        logfire::debug!(
+            "received upload chunk project={project} upload_id={upload_id} offset={offset} size={size} db_ms={db_ms}",
+            project = project.to_string(),
+            upload_id = id.to_string(),
+            offset = offset,
+            size = body.len(),
+            db_ms = db.ms(),
+        );
+        return Ok((StatusCode::NO_CONTENT, [(UPLOAD_OFFSET, HeaderValue::from(received))]).into_response());
+    }
+
+    let content: Vec<u8> = db
+        .time(
+            sqlx::query_scalar(
+                r#"SELECT COALESCE(string_agg(content, '' ORDER BY "offset"), '') FROM upload_chunks WHERE upload_id = $1"#,
+            )
+            .bind(id)
+            .fetch_one(&mut *tx),
+        )
+        .await?;
+    let entry = NewEntry {
+        namespace: &upload.namespace,
+        key: &upload.key,
+        mime_type: &upload.mime_type,
+        content: &content,
+        immutable: upload.immutable,
+        ttl: Ttl::ProjectDefault,
+        response_headers: BTreeMap::new(),
+        actor,
+        created_at: None,
+        updated_at: None,
+        create_only: false,
+    };
+    let (inserted, quota_used) = entries::write_entry(&mut tx, &mut db, &*repository, &config, project, &entry)
+        .await?
+        .ok_or_else(|| AppError::Conflict(format!("entry {:?} is immutable", upload.key)))?;
+    db.time(
+        sqlx::query("DELETE FROM uploads WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx),
+    )
+    .await?;
+    db.time(tx.commit()).await?;
+
+    logfire::info!(
+        "completed upload project={project} upload_id={upload_id} key={key} size={size} inserted={inserted} \
+         actor={actor:?} db_ms={db_ms}",
+        project = project.to_string(),
+        upload_id = id.to_string(),
+        key = upload.key.clone(),
+        size = content.len(),
+        inserted = inserted,
+        actor = entry.actor.clone(),
+        db_ms = db.ms(),
+    );
+    let mut response = entries::store_response(&config, &headers, project, inserted, quota_used, &entry);
+    response
+        .headers_mut()
+        .insert(UPLOAD_OFFSET, HeaderValue::from(received));
+    Ok(response)
+}
@@ -0,0 +1,122 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::HeaderMap,
+    response::Response,
+};
+use hmac::{Hmac, Mac};
+use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+    extract::Path,
+    handlers::entries::{self, Rendering},
+    models::{DEFAULT_NAMESPACE, SignQuery, SignedQuery, SignedUrl},
+    state::{Pool, Repository},
+    telemetry,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const DEFAULT_EXPIRES_IN: u64 = 3600;
+const MAX_EXPIRES_IN: u64 = 7 * 24 * 3600;
+
+/// Characters escaped in keys when building URLs, `/` is left alone so nested keys stay readable
+pub const KEY_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Create a time-limited URL granting public read access to a single entry
+pub async fn sign_entry(
+    State(config): State<Arc<Config>>,
+    Path((project, key)): Path<(Uuid, String)>,
+    Query(query): Query<SignQuery>,
+) -> Result<Json<SignedUrl>> {
+    let key = config.normalize_key(key);
+    telemetry::record_key(project, &key);
+    let expires_in = query.expires_in.unwrap_or(DEFAULT_EXPIRES_IN);
+    if expires_in == 0 || expires_in > MAX_EXPIRES_IN {
+        return Err(AppError::Validation(format!(
+            "expires_in must be between 1 and {MAX_EXPIRES_IN} seconds"
+        )));
+    }
+    let expires = unix_now() + expires_in;
+    let signature = hex::encode(entry_mac(&config, project, &key, expires)?.finalize().into_bytes());
+
+    let url = format!(
+        "/signed/{project}/{key}?expires={expires}&signature={signature}",
+        key = utf8_percent_encode(&key, KEY_ENCODE_SET)
+    );
+    Ok(Json(SignedUrl { url, expires }))
+}
+
+/// Serve an entry via a signed URL, checking the signature and expiry first
+pub async fn get_signed_entry(
+    State(pool): State<Pool>,
+    State(repository): State<Repository>,
+    State(config): State<Arc<Config>>,
+    Path((project, key)): Path<(Uuid, String)>,
+    Query(query): Query<SignedQuery>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let key = config.normalize_key(key);
+    // the signature covers the expiry, so check it first, a tampered expiry is reported as an invalid signature
+    let signature = hex::decode(&query.signature).map_err(|_| invalid_signature())?;
+    entry_mac(&config, project, &key, query.expires)?
+        .verify_slice(&signature)
+        .map_err(|_| invalid_signature())?;
+
+    if query.expires <= unix_now() {
+        return Err(AppError::Forbidden("signed URL has expired".to_string()));
+    }
+
+    entries::fetch_entry(
+        &pool,
+        &*repository,
+        &config,
+        &headers,
+        project,
+        DEFAULT_NAMESPACE,
+        key,
+        Rendering::Stored,
+    )
+    .await
+}
+
+fn entry_mac(config: &Config, project: Uuid, key: &str, expires: u64) -> Result<HmacSha256> {
+    let signing_key = config
+        .signing_key
+        .as_ref()
+        .ok_or_else(|| AppError::Forbidden("signed URLs are not enabled".to_string()))?;
+
+    let mut mac = HmacSha256::new_from_slice(signing_key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{project}\n{key}\n{expires}").as_bytes());
+    Ok(mac)
+}
+
+fn invalid_signature() -> AppError {
+    AppError::Forbidden("invalid signature".to_string())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is after the unix epoch")
+        .as_secs()
+}
@@ -0,0 +1,63 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use chrono::{TimeDelta, Utc};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, Result},
+    extract::Path,
+    models::{ProjectUsage, UsageQuery},
+    state::Pool,
+    telemetry::DbTimer,
+    usage,
+};
+
+/// Requests made to a project over the last `?hours=` (default 24), counting the current hour as the first; the
+/// counts include what this instance hasn't flushed yet, but not other instances' unflushed counts
+pub async fn project_usage(
+    State(pool): State<Pool>,
+    Path(project): Path<Uuid>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<ProjectUsage>> {
+    let hours = query.hours.unwrap_or(24);
+    if hours == 0 {
+        return Err(AppError::Validation("hours must be at least 1".to_string()));
+    }
+    let since = usage::bucket(Utc::now()) - TimeDelta::hours(i64::from(hours) - 1);
+
+    let mut db = DbTimer::default();
+    let flushed = db
+        .time(
+            sqlx::query!(
+                r#"
+        SELECT
+            COALESCE(sum(reads), 0)::bigint AS "reads!",
+            COALESCE(sum(writes), 0)::bigint AS "writes!",
+            COALESCE(sum(deletes), 0)::bigint AS "deletes!"
+        FROM project_usage
+        WHERE project_id = $1 AND bucket >= $2
+        "#,
+                project,
+                since,
+            )
+            .fetch_one(&*pool),
+        )
+        .await?;
+    let pending = usage::pending(project, since);
+
+    logfire::info!(
+        "read usage project={project} hours={hours} db_ms={db_ms}",
+        project = project.to_string(),
+        hours = hours,
+        db_ms = db.ms(),
+    );
+    Ok(Json(ProjectUsage {
+        hours,
+        since,
+        reads: flushed.reads + pending.reads,
+        writes: flushed.writes + pending.writes,
+        deletes: flushed.deletes + pending.deletes,
+    }))
+}
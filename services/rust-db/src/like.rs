@@ -0,0 +1,49 @@
+//! Building SQL `LIKE` patterns from user input, every query matching keys by prefix should go through here so
+//! `%`, `_` and `\` in keys are matched literally.
+
+/// `LIKE` pattern matching strings starting with `prefix`
+pub fn escape_like_prefix(prefix: &str) -> String {
+    format!("{}%", escape_like(prefix))
+}
+
+/// Escape `LIKE` wildcards, and the (default) escape character itself
+pub fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_prefix() {
+        assert_eq!(escape_like_prefix("docs/"), "docs/%");
+    }
+
+    #[test]
+    fn empty_prefix_matches_everything() {
+        assert_eq!(escape_like_prefix(""), "%");
+    }
+
+    #[test]
+    fn percent() {
+        assert_eq!(escape_like_prefix("100%/"), "100\\%/%");
+    }
+
+    #[test]
+    fn underscore() {
+        assert_eq!(escape_like_prefix("a_b"), "a\\_b%");
+    }
+
+    #[test]
+    fn backslash() {
+        assert_eq!(escape_like_prefix("a\\b"), "a\\\\b%");
+    }
+
+    #[test]
+    fn backslash_before_wildcard() {
+        // the backslash is escaped first, so it can't turn the wildcard escape back into a wildcard
+        assert_eq!(escape_like_prefix("\\%"), "\\\\\\%%");
+        assert_eq!(escape_like_prefix("\\_"), "\\\\\\_%");
+    }
+}
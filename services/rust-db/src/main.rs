@@ -1,17 +1,35 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
-use opentelemetry::global;
-use opentelemetry::propagation::TextMapCompositePropagator;
+use opentelemetry::{global, propagation::TextMapCompositePropagator};
 use opentelemetry_sdk::propagation::{BaggagePropagator, TraceContextPropagator};
-use sqlx::postgres::PgPoolOptions;
+use sqlx::{Connection, Executor, PgConnection, PgPool, postgres::PgPoolOptions};
 
+mod access_log;
+mod admin_auth;
+mod audit;
+mod body_timeout;
+mod client_ip;
 mod config;
 mod error;
+mod extract;
 mod handlers;
+mod like;
 mod models;
+mod repository;
 mod routes;
+mod server;
+mod shutdown;
+mod state;
+mod telemetry;
+mod tenant;
+mod timings;
+mod trace_sampling;
+mod transcode;
+mod uploads;
+mod usage;
 
 use config::Config;
+use state::AppState;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -29,21 +47,101 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let config = Config::from_env()?;
 
     // Create database pool wrapped with sqlx-tracing for OTEL spans
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&config.database_url)
-        .await?;
+    let pool = connect_with_retry(&config).await?;
     let pool = Arc::new(sqlx_tracing::Pool::from(pool));
 
-    // Build router
-    let app = routes::create_router(pool);
+    // Build router, admin routes are only served alongside data routes when there's no dedicated admin port
+    let config = Arc::new(config);
+    let state = AppState {
+        pool,
+        config: config.clone(),
+        repository: Arc::new(repository::PgEntryRepository::new(&config)),
+    };
+    let app = routes::create_router(state.clone(), config.admin_port.is_none());
+    tokio::spawn(usage::flush_periodically(
+        state.pool.clone(),
+        config.usage_flush_interval,
+    ));
 
     // Start server
     let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
     logfire::info!("Listening on {addr}", addr = addr.to_string());
 
+    // flips to `true` once the drain period after a shutdown signal is over
+    let (shutdown_tx, shutdown) = tokio::sync::watch::channel(false);
+    let drain = config.shutdown_drain;
+    tokio::spawn(async move {
+        shutdown::drained(drain).await;
+        let _ = shutdown_tx.send(true);
+    });
+
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    let server = server::serve(listener, app, &config, shutdown.clone());
+
+    if let Some(admin_port) = config.admin_port {
+        let admin_addr = SocketAddr::from(([0, 0, 0, 0], admin_port));
+        logfire::info!("Admin listening on {addr}", addr = admin_addr.to_string());
 
+        let admin_listener = tokio::net::TcpListener::bind(admin_addr).await?;
+        let admin_app = routes::create_admin_router(state.clone());
+        let admin_server = server::serve(admin_listener, admin_app, &config, shutdown);
+        tokio::try_join!(server, admin_server)?;
+    } else {
+        server.await?;
+    }
+
+    // whatever was counted since the last periodic flush
+    usage::flush(&state.pool).await;
+    logfire::info!("shut down");
     Ok(())
 }
+
+/// Connect to the database, retrying with exponential backoff so the service can start before Postgres is ready
+async fn connect_with_retry(config: &Config) -> Result<PgPool, sqlx::Error> {
+    const MAX_INTERVAL: Duration = Duration::from_secs(30);
+
+    let mut interval = config.db_connect_interval;
+    let mut attempt: u32 = 1;
+    // probe with a single connection, the pool's own connect keeps retrying until its acquire timeout
+    loop {
+        let error = match PgConnection::connect(&config.database_url).await {
+            Ok(conn) => {
+                conn.close().await?;
+                break;
+            }
+            Err(error) => error,
+        };
+        if attempt >= config.db_connect_attempts {
+            logfire::error!(
+                "failed to connect to the database after {attempts} attempts: {error}",
+                attempts = attempt,
+                error = error.to_string(),
+            );
+            return Err(error);
+        }
+        logfire::warn!(
+            "database connection attempt {attempt} of {attempts} failed, retrying in {interval_ms}ms: {error}",
+            attempt = attempt,
+            attempts = config.db_connect_attempts,
+            interval_ms = interval.as_millis() as u64,
+            error = error.to_string(),
+        );
+        tokio::time::sleep(interval).await;
+        interval = (interval * 2).min(MAX_INTERVAL);
+        attempt += 1;
+    }
+
+    let options = PgPoolOptions::new().max_connections(5);
+    let options = if config.tenant_schemas {
+        // tenant connections point `search_path` at a project's schema, undo that before anything else uses them
+        options.after_release(|conn, _| {
+            Box::pin(async move {
+                conn.execute("RESET search_path").await?;
+                Ok(true)
+            })
+        })
+    } else {
+        options
+    };
+    options.connect(&config.database_url).await
+}
@@ -1,13 +1,629 @@
-use serde::Serialize;
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use uuid::Uuid;
+
+/// Namespace of entries stored via routes without an `/ns/{namespace}/` segment
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// Namespace of content-addressed entries, keyed by the SHA-256 of their content; only the `/cas` route writes to
+/// it, so every key is guaranteed to match its content
+pub const CAS_NAMESPACE: &str = "cas";
+
+fn default_namespace() -> String {
+    DEFAULT_NAMESPACE.to_string()
+}
+
+/// Path params of routes addressing a single entry, with or without an `/ns/{namespace}/` segment
+#[derive(Debug, Deserialize)]
+pub struct EntryPath {
+    pub project: Uuid,
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    pub key: String,
+}
+
+/// Path params of a fixed route, stores and deletes of which are of the key its whole path spells out
+#[derive(Debug, Deserialize)]
+pub struct ShadowedPath {
+    pub project: Uuid,
+    pub namespace: Option<String>,
+}
+
+/// Path params of the list routes, the prefix is missing when listing everything
+#[derive(Debug, Deserialize)]
+pub struct PrefixPath {
+    pub project: Uuid,
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    #[serde(default)]
+    pub prefix: String,
+}
 
 #[derive(Debug, Serialize)]
 pub struct KeyInfo {
     pub key: String,
     pub mime_type: String,
+    pub size: i64,
 }
 
 #[derive(Debug)]
 pub struct Entry {
+    pub id: Uuid,
     pub mime_type: String,
     pub content: Vec<u8>,
+    /// Headers captured at store time and replayed on get, keyed by lowercase header name
+    pub response_headers: Json<BTreeMap<String, String>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+    /// Hex encoded SHA-256 of the content, only computed for entries up to `WEAK_ETAG_THRESHOLD`
+    pub sha256: Option<String>,
+    /// Mime types of the entry's other variants
+    pub variants: Vec<String>,
+}
+
+/// Another representation of an entry, see `entries::negotiate`
+#[derive(Debug)]
+pub struct Variant {
+    pub content: Vec<u8>,
+    pub updated_at: DateTime<Utc>,
+    pub sha256: Option<String>,
+}
+
+/// Everything about an entry except its content
+#[derive(Debug, Serialize)]
+pub struct EntryMeta {
+    pub key: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub etag: String,
+    /// Hex encoded SHA-256 of the content
+    pub sha256: String,
+    /// `X-Actor` of the first and latest stores
+    pub created_by: Option<String>,
+    pub updated_by: Option<String>,
+    /// Mime types of other variants get can serve instead, depending on `Accept`
+    pub variants: Vec<String>,
+}
+
+/// Store response body, for clients sending `Accept: application/json`, describes the entry as stored, i.e. after
+/// key normalization and body decoding
+#[derive(Debug, Serialize)]
+pub struct StoredEntry {
+    pub key: String,
+    pub size: usize,
+    pub mime_type: String,
+    pub etag: String,
+    /// Hex encoded SHA-256 of the stored content
+    pub sha256: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetEntryQuery {
+    /// Overrides the stored mime type in the response's `Content-Type`, the stored entry is unchanged
+    pub content_type: Option<String>,
+    /// Convert the content to another format in the response, the stored entry is unchanged
+    #[serde(rename = "as")]
+    pub as_format: Option<TranscodeFormat>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscodeFormat {
+    /// From CSV, YAML or TOML
+    Json,
+}
+
+/// Filters for the list endpoints, all optional and combined with the key prefix
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    /// Minimum content size in bytes, inclusive
+    pub min_size: Option<i64>,
+    /// Maximum content size in bytes, inclusive
+    pub max_size: Option<i64>,
+    /// Exact mime type like `image/png`, or `image/*` for any subtype, or a comma-separated set of them
+    pub mime: Option<String>,
+    /// Collapse keys more than `depth` delimiters below the prefix into `common_prefixes`, like S3
+    pub delimiter: Option<String>,
+    /// Levels below the prefix to return with `delimiter`, defaults to 1, i.e. immediate children
+    pub depth: Option<usize>,
+    /// Most keys to return, clamped to `MAX_LIST_LIMIT` which is also the default; with `delimiter` this limits
+    /// the keys scanned before grouping
+    pub limit: Option<u32>,
+    /// Only keys after this one, i.e. `X-Next-Cursor` from the previous page
+    pub after: Option<String>,
+    /// Only keys from this one onwards, inclusive, so workers can each list a slice of the keys
+    pub start: Option<String>,
+    /// Only keys before this one, exclusive
+    pub end: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum ListResponse {
+    Keys(Vec<KeyInfo>),
+    /// With `?delimiter=`, keys directly under the prefix and the distinct "directories" below them
+    Delimited {
+        keys: Vec<KeyInfo>,
+        common_prefixes: Vec<String>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteQuery {
+    /// Return `204` when the key doesn't exist rather than `404`
+    #[serde(default)]
+    pub idempotent: bool,
+    /// Only delete the entry's variant with this mime type
+    pub variant: Option<String>,
+}
+
+/// Filters for the export endpoint, only matching entries are included in the archive
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    /// Key prefix, matched literally
+    pub prefix: Option<String>,
+    /// Exact mime type like `image/png`, or `image/*` for any subtype, or a comma-separated set of them
+    pub mime: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TouchAllQuery {
+    /// Only touch keys starting with this prefix, matched literally
+    pub prefix: Option<String>,
+    /// Leave entries without an expiry alone, rather than giving them the TTL too
+    #[serde(default)]
+    pub only_expiring: bool,
+}
+
+/// `manifest.json` written at the end of an export archive
+#[derive(Debug, Serialize)]
+pub struct ExportManifest {
+    pub project: Uuid,
+    pub exported_at: DateTime<Utc>,
+    /// Prefix filter applied, if any
+    pub prefix: Option<String>,
+    /// Mime filter applied, if any
+    pub mime: Option<String>,
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// A line of a project dump, one per entry
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DumpEntry {
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    pub key: String,
+    pub mime_type: String,
+    pub content_base64: String,
+    #[serde(default)]
+    pub immutable: bool,
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub response_headers: BTreeMap<String, String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Lines of a restored dump which couldn't be restored are reported rather than aborting the restore
+#[derive(Debug, Serialize)]
+pub struct RestoreReport {
+    pub restored: u64,
+    /// Entries which had expired since they were dumped
+    pub expired: u64,
+    pub failed: u64,
+    /// The first failures, by line number from 1
+    pub errors: Vec<RestoreError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreError {
+    pub line: u64,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    pub key: String,
+    /// Path of the entry's content within the archive
+    pub path: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StoreEntryRequest {
+    pub key: String,
+    #[serde(default = "default_mime_type")]
+    pub mime_type: String,
+    pub content_base64: String,
+    #[serde(default)]
+    pub immutable: bool,
+}
+
+/// Start a resumable upload, whose content is then sent in chunks
+#[derive(Debug, Deserialize)]
+pub struct NewUploadRequest {
+    pub key: String,
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    #[serde(default = "default_mime_type")]
+    pub mime_type: String,
+    #[serde(default)]
+    pub immutable: bool,
+    /// Size of the whole content in bytes, the upload is stored once this much has been received
+    pub length: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Upload {
+    pub id: Uuid,
+    pub namespace: String,
+    pub key: String,
+    pub mime_type: String,
+    pub length: i64,
+    /// Bytes received so far, where the next chunk starts
+    pub offset: i64,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    /// Kept as raw JSON so a malformed operation fails on its own rather than rejecting the whole batch
+    pub operations: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    /// As the JSON store endpoint
+    Store(StoreEntryRequest),
+    Delete {
+        key: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchQuery {
+    /// Apply every operation or none of them
+    #[serde(default)]
+    pub atomic: bool,
+    /// Report what the deletes would do without deleting anything, stores are refused
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Outcome of one operation, in the order they were sent
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    pub index: usize,
+    pub key: Option<String>,
+    /// The status the equivalent single request would have returned, or `424` for operations reverted by
+    /// `?atomic=true`
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResponse {
+    pub results: Vec<BatchResult>,
+}
+
+fn default_mime_type() -> String {
+    "application/octet-stream".to_string()
+}
+
+/// Per-project settings, managed via the admin API; fields left out are cleared, so the same request always leaves
+/// the project in the same state
+#[derive(Debug, Deserialize)]
+pub struct ProjectSettings {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// TTL applied to entries stored without `X-TTL-Seconds`, `None` means entries don't expire by default
+    pub default_ttl_secs: Option<i64>,
+    /// Most bytes of live entry content the project may hold, stores beyond it get `507`
+    pub quota_bytes: Option<i64>,
+    /// Appended to keys ending in `/` which aren't found, e.g. `index.html`, for serving a static site
+    pub index_key: Option<String>,
+    /// Served in place of keys which aren't found, e.g. `404.html`
+    pub fallback_key: Option<String>,
+    /// Status `fallback_key` is served with, defaults to `404`
+    pub fallback_status: Option<u16>,
+    /// Regular expression stored keys must match, e.g. `^[a-z0-9/_-]+$`, stores of other keys get `400`
+    pub key_pattern: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Project {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub default_ttl_secs: Option<i64>,
+    pub quota_bytes: Option<i64>,
+    pub index_key: Option<String>,
+    pub fallback_key: Option<String>,
+    pub fallback_status: Option<u16>,
+    pub key_pattern: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Keys which would collide if key handling changed, see `admin::key_collisions`
+#[derive(Debug, Serialize)]
+pub struct KeyCollisions {
+    /// Keys differing only by case
+    pub case_folding: Vec<CollisionGroup>,
+    /// Keys which `NORMALIZE_KEYS` would map to the same key
+    pub slash_normalization: Vec<CollisionGroup>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollisionGroup {
+    pub namespace: String,
+    /// What every key in the group maps to
+    pub normalized: String,
+    pub keys: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FindKeyQuery {
+    pub key: String,
+    /// `next` from the previous page
+    pub after: Option<Uuid>,
+    /// Most matches to return, clamped to `MAX_LIST_LIMIT` which is also the default
+    pub limit: Option<u32>,
+}
+
+/// A live entry with the key being searched for, see `admin::find_key`
+#[derive(Debug, Serialize)]
+pub struct KeyLocation {
+    /// Entry id, only used as the page cursor
+    #[serde(skip)]
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub namespace: String,
+    pub key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyLocations {
+    pub matches: Vec<KeyLocation>,
+    /// Pass as `?after=` to get the next page, `null` on the last page
+    pub next: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MultiListRequest {
+    pub projects: Vec<Uuid>,
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    #[serde(default)]
+    pub prefix: String,
+    /// Most keys to return per project, clamped to `MAX_LIST_LIMIT` which is also the default
+    pub limit: Option<u32>,
+}
+
+/// What `admin::merge_projects` does with a key which exists in both projects
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeConflict {
+    /// Refuse the whole merge with `409`
+    #[default]
+    Fail,
+    /// Keep the destination's entry, leaving the source's in the source project
+    Skip,
+    /// Replace the destination's entry with the source's
+    Overwrite,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeRequest {
+    pub from_project: Uuid,
+    pub to_project: Uuid,
+    #[serde(default)]
+    pub on_conflict: MergeConflict,
+}
+
+/// Entries moved by a merge; `overwritten` counts destination entries replaced, which are included in `moved`
+#[derive(Debug, Serialize)]
+pub struct MergeReport {
+    pub moved: u64,
+    pub overwritten: u64,
+    pub skipped: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MaintenanceQuery {
+    /// Also rebuild the entries indexes
+    #[serde(default)]
+    pub reindex: bool,
+}
+
+/// How long each maintenance step took, `reindex_ms` is `None` when reindexing wasn't requested
+#[derive(Debug, Serialize)]
+pub struct MaintenanceReport {
+    pub vacuum_ms: f64,
+    pub reindex_ms: Option<f64>,
+}
+
+/// Number of entries changed by a bulk operation
+#[derive(Debug, Serialize)]
+pub struct AffectedRows {
+    pub count: u64,
+    /// With `?dry_run=true`, the keys which would have been changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RetagRequest {
+    pub mime_type: String,
+}
+
+/// Replace an entry's content only if it's currently `expected_base64`, see `entries::swap_entry_value`
+#[derive(Debug, Deserialize)]
+pub struct SwapValueRequest {
+    pub expected_base64: String,
+    pub new_base64: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DryRunQuery {
+    /// Report what would be changed without changing anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Filters for the audit log, `from` is inclusive and `to` exclusive
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// `next` from the previous page
+    pub after: Option<i64>,
+    /// Most events to return, clamped to `MAX_LIST_LIMIT` which is also the default
+    pub limit: Option<u32>,
+}
+
+/// One change to an entry or one of its variants
+#[derive(Debug, Serialize)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub namespace: String,
+    pub key: String,
+    /// `insert`, `update` or `delete`
+    pub op: String,
+    pub actor: Option<String>,
+    /// Content size after the change, or before a delete
+    pub size: i64,
+    /// Mime type of the variant changed, absent for a change to the entry itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditPage {
+    /// Oldest first
+    pub events: Vec<AuditEvent>,
+    /// Pass as `?after=` to get the next page, `null` on the last page
+    pub next: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    /// Length of the window in hours, including the current one
+    pub hours: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangesQuery {
+    /// Only changes after this time, usually `now` from the previous response
+    pub since: DateTime<Utc>,
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    /// Also changes at exactly `since` to keys after this one, from `next`
+    pub after: Option<String>,
+    /// Most changes to return, clamped to `MAX_LIST_LIMIT` which is also the default
+    pub limit: Option<u32>,
+}
+
+/// Query JSON entries with a SQL/JSON path, see `jsonquery::query_json`
+#[derive(Debug, Deserialize)]
+pub struct JsonQuery {
+    /// A predicate like `$.status == "active"`, or a path like `$.tags ? (@ == "urgent")` which matches documents
+    /// where it selects anything
+    pub path: String,
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    #[serde(default)]
+    pub prefix: String,
+    /// Most keys to return, clamped to `MAX_LIST_LIMIT` which is also the default
+    pub limit: Option<u32>,
+    /// Only keys after this one, `next` from the previous page
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonQueryPage {
+    pub keys: Vec<KeyInfo>,
+    /// Pass as `?after=` for the next page, `None` on the last
+    pub next: Option<String>,
+}
+
+/// A key which was stored, deleted or expired, see `changes::list_changes`
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub key: String,
+    /// `None` when the key was deleted
+    pub mime_type: Option<String>,
+    pub size: Option<i64>,
+    /// When it was stored, deleted or expired
+    pub updated_at: DateTime<Utc>,
+    pub deleted: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangesPage {
+    /// Database time of the query, pass as `since` to get the changes after these
+    pub now: DateTime<Utc>,
+    pub changes: Vec<Change>,
+    /// When more changes matched than `limit`, pass as `since` and `after` for the rest, rather than `now`
+    pub next: Option<ChangesCursor>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangesCursor {
+    pub since: DateTime<Utc>,
+    pub after: String,
+}
+
+/// Latencies of one route since startup, see `timings`
+#[derive(Debug, Serialize)]
+pub struct RouteTiming {
+    pub method: String,
+    pub route: String,
+    pub count: u64,
+    /// `5xx` responses
+    pub errors: u64,
+    pub error_rate: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Request counts for a project, `GET`s are reads, `POST`s and `PUT`s writes
+#[derive(Debug, Serialize)]
+pub struct ProjectUsage {
+    pub hours: u32,
+    /// Start of the first hour counted
+    pub since: DateTime<Utc>,
+    pub reads: i64,
+    pub writes: i64,
+    pub deletes: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignQuery {
+    /// Seconds until the signed URL expires
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SignedUrl {
+    pub url: String,
+    /// Unix timestamp after which the URL is rejected
+    pub expires: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignedQuery {
+    pub expires: u64,
+    pub signature: String,
 }
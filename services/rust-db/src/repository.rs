@@ -0,0 +1,650 @@
+//! Reading and writing entries, behind `EntryRepository` so handlers don't embed the SQL themselves.
+//!
+//! Every method runs on a connection the caller passes in rather than one of its own, so a write joins whatever
+//! transaction the caller has open: `audit::begin`'s for the actor, a batch's savepoint, or CAS and restores
+//! which commit a write together with their own statements. Project-level checks (existence, key patterns,
+//! quotas) stay with the handlers, which run them on the same connection.
+//!
+//! `memory::MemoryEntryRepository` implements the trait on maps for unit tests.
+
+#[cfg(test)]
+mod memory;
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use futures::{FutureExt, future::BoxFuture, stream::BoxStream};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    like::escape_like_prefix,
+    models::{AffectedRows, DEFAULT_NAMESPACE, Entry, KeyInfo, ListQuery, Variant},
+};
+
+pub struct NewEntry<'a> {
+    pub namespace: &'a str,
+    pub key: &'a str,
+    pub mime_type: &'a str,
+    pub content: &'a [u8],
+    /// Once stored, immutable entries can't be overwritten or deleted
+    pub immutable: bool,
+    pub ttl: Ttl,
+    pub response_headers: BTreeMap<String, String>,
+    /// Who's storing the entry, from `X-Actor`
+    pub actor: Option<String>,
+    /// Original timestamps of an imported entry, `NOW()` when unset; overwriting a live entry keeps its `created_at`
+    /// unless one is given
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Only store the entry if the key has no live entry, from `If-None-Match: *`
+    pub create_only: bool,
+}
+
+/// Expiry of a stored entry
+pub enum Ttl {
+    /// The project's `default_ttl_secs`, which may itself be no expiry
+    ProjectDefault,
+    Never,
+    Seconds(i64),
+}
+
+/// A live entry's state when `swap_value` compared it
+pub struct Swapped {
+    pub mime_type: String,
+    pub immutable: bool,
+    /// Whether the content was the expected one, and so was replaced unless the entry is immutable
+    pub matches: bool,
+}
+
+/// Entries and their variants; expired entries are never returned, the bulk operations only change live,
+/// mutable entries in the default namespace.
+///
+/// Generic over the connection `C` each call runs on, a Postgres connection (with whatever transaction the caller
+/// has open) for `PgEntryRepository`, so implementations which don't need one can take anything.
+pub trait EntryRepository<C: ?Sized + Send = PgConnection>: Send + Sync {
+    /// A live entry, `sha256` only set if its content is at most `WEAK_ETAG_THRESHOLD` bytes
+    fn get<'a>(
+        &'a self,
+        conn: &'a mut C,
+        project: Uuid,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, sqlx::Result<Option<Entry>>>;
+
+    /// One of an entry's variants, by the `id` from `get`
+    fn get_variant<'a>(
+        &'a self,
+        conn: &'a mut C,
+        entry_id: Uuid,
+        mime_type: &'a str,
+    ) -> BoxFuture<'a, sqlx::Result<Option<Variant>>>;
+
+    /// Insert or update an entry, dropping the variants of the content it replaces; `Some(true)` if the key had no
+    /// live entry, so overwriting an expired row counts as an insert (and resets its `created_at` and `created_by`),
+    /// and `None` if the existing live entry is immutable, or exists at all with `create_only`.
+    ///
+    /// A concurrent store of the same key waits on the row lock, then takes the conflict path rather than failing
+    /// with a unique violation, so of two racing create-only stores exactly one inserts.
+    fn put<'a>(
+        &'a self,
+        conn: &'a mut C,
+        project: Uuid,
+        entry: &'a NewEntry<'a>,
+    ) -> BoxFuture<'a, sqlx::Result<Option<bool>>>;
+
+    /// Live entries whose key matches the LIKE `pattern`, filtered by the list query params and ordered by key, a
+    /// `None` limit is no limit
+    #[allow(clippy::too_many_arguments)]
+    fn list<'a>(
+        &'a self,
+        conn: &'a mut C,
+        project: Uuid,
+        namespace: &'a str,
+        pattern: String,
+        query: &'a ListQuery,
+        mime_pattern: Option<&'a [String]>,
+        limit: Option<i64>,
+    ) -> BoxStream<'a, sqlx::Result<KeyInfo>>;
+
+    /// As `list`, of the keys starting with `prefix`
+    #[allow(clippy::too_many_arguments)]
+    fn list_prefix<'a>(
+        &'a self,
+        conn: &'a mut C,
+        project: Uuid,
+        namespace: &'a str,
+        prefix: &str,
+        query: &'a ListQuery,
+        mime_pattern: Option<&'a [String]>,
+        limit: Option<i64>,
+    ) -> BoxStream<'a, sqlx::Result<KeyInfo>> {
+        self.list(
+            conn,
+            project,
+            namespace,
+            escape_like_prefix(prefix),
+            query,
+            mime_pattern,
+            limit,
+        )
+    }
+
+    /// Delete an entry unless it's immutable; returns whether it was immutable, or `None` if it doesn't exist
+    fn delete<'a>(
+        &'a self,
+        conn: &'a mut C,
+        project: Uuid,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, sqlx::Result<Option<bool>>>;
+
+    /// Delete one variant of an entry unless the entry is immutable, as `delete` returns whether it was immutable,
+    /// or `None` if the entry doesn't have the variant
+    fn delete_variant<'a>(
+        &'a self,
+        conn: &'a mut C,
+        project: Uuid,
+        namespace: &'a str,
+        key: &'a str,
+        mime_type: &'a str,
+    ) -> BoxFuture<'a, sqlx::Result<Option<bool>>>;
+
+    /// Set a TTL on every entry under `prefix`, with `dry_run` only count them and list their keys
+    fn expire_prefix<'a>(
+        &'a self,
+        conn: &'a mut C,
+        project: Uuid,
+        prefix: &'a str,
+        ttl_seconds: i64,
+        dry_run: bool,
+    ) -> BoxFuture<'a, sqlx::Result<AffectedRows>>;
+
+    /// Set the mime type of every entry under `prefix`, with `dry_run` only count them and list their keys
+    fn retag_prefix<'a>(
+        &'a self,
+        conn: &'a mut C,
+        project: Uuid,
+        prefix: &'a str,
+        mime_type: &'a str,
+        actor: Option<&'a str>,
+        dry_run: bool,
+    ) -> BoxFuture<'a, sqlx::Result<AffectedRows>>;
+
+    /// Set the TTL of every entry under `prefix` to `ttl_seconds` from now, including those without an expiry
+    /// unless `only_expiring`; returns how many were changed
+    fn touch_prefix<'a>(
+        &'a self,
+        conn: &'a mut C,
+        project: Uuid,
+        prefix: &'a str,
+        ttl_seconds: i64,
+        only_expiring: bool,
+    ) -> BoxFuture<'a, sqlx::Result<u64>>;
+
+    /// Replace an entry's content with `new` if it's currently exactly `expected`, dropping its variants; `None` if
+    /// there's no live entry. The row is locked from the comparison to the update.
+    fn swap_value<'a>(
+        &'a self,
+        conn: &'a mut C,
+        project: Uuid,
+        key: &'a str,
+        expected: &'a [u8],
+        new: &'a [u8],
+        actor: Option<&'a str>,
+    ) -> BoxFuture<'a, sqlx::Result<Option<Swapped>>>;
+}
+
+/// `EntryRepository` on the project's `entries` and `entry_variants` tables, whichever schema the connection's
+/// `search_path` finds them in
+pub struct PgEntryRepository {
+    /// `WEAK_ETAG_THRESHOLD`
+    weak_etag_threshold: i64,
+    /// `LIST_COLLATE_C`
+    collate_c: bool,
+    /// `UUID_V7_IDS`
+    uuid_v7_ids: bool,
+}
+
+impl PgEntryRepository {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            weak_etag_threshold: config.weak_etag_threshold,
+            collate_c: config.list_collate_c,
+            uuid_v7_ids: config.uuid_v7_ids,
+        }
+    }
+}
+
+impl EntryRepository for PgEntryRepository {
+    fn get<'a>(
+        &'a self,
+        conn: &'a mut PgConnection,
+        project: Uuid,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, sqlx::Result<Option<Entry>>> {
+        sqlx::query_as!(
+            Entry,
+            r#"
+        SELECT
+            id,
+            mime_type,
+            content,
+            response_headers AS "response_headers: sqlx::types::Json<BTreeMap<String, String>>",
+            expires_at,
+            updated_at,
+            CASE WHEN octet_length(content)::bigint <= $4 THEN encode(sha256(content), 'hex') END AS sha256,
+            ARRAY(
+                SELECT mime_type FROM entry_variants WHERE entry_id = entries.id ORDER BY mime_type
+            ) AS "variants!"
+        FROM entries
+        WHERE project_id = $1 AND namespace = $2 AND key = $3 AND (expires_at IS NULL OR expires_at > NOW())
+        "#,
+            project,
+            namespace,
+            key,
+            self.weak_etag_threshold,
+        )
+        .fetch_optional(conn)
+        .boxed()
+    }
+
+    fn get_variant<'a>(
+        &'a self,
+        conn: &'a mut PgConnection,
+        entry_id: Uuid,
+        mime_type: &'a str,
+    ) -> BoxFuture<'a, sqlx::Result<Option<Variant>>> {
+        sqlx::query_as!(
+            Variant,
+            r#"
+        SELECT
+            content,
+            updated_at,
+            CASE WHEN octet_length(content)::bigint <= $3 THEN encode(sha256(content), 'hex') END AS sha256
+        FROM entry_variants
+        WHERE entry_id = $1 AND mime_type = $2
+        "#,
+            entry_id,
+            mime_type,
+            self.weak_etag_threshold,
+        )
+        .fetch_optional(conn)
+        .boxed()
+    }
+
+    fn put<'a>(
+        &'a self,
+        conn: &'a mut PgConnection,
+        project: Uuid,
+        entry: &'a NewEntry<'a>,
+    ) -> BoxFuture<'a, sqlx::Result<Option<bool>>> {
+        let (use_project_default, ttl_seconds) = match entry.ttl {
+            Ttl::ProjectDefault => (true, None),
+            Ttl::Never => (false, None),
+            Ttl::Seconds(seconds) => (false, Some(seconds)),
+        };
+        async move {
+            // xmax is only zero for a freshly inserted row, `previous` (from the statement's snapshot) tells whether
+            // an updated row had expired; no row is returned when the existing entry is immutable (and still live)
+            let upserted: Option<(bool, bool, Uuid)> = sqlx::query_as(
+                r#"
+        WITH previous AS (
+            SELECT expires_at <= NOW() AS expired FROM entries WHERE project_id = $1 AND namespace = $2 AND key = $3
+        )
+        INSERT INTO entries (
+            id, project_id, namespace, key, mime_type, content, immutable, response_headers, created_at, updated_at,
+            expires_at, created_by, updated_by
+        )
+        VALUES (
+            COALESCE($11, gen_random_uuid()), $1, $2, $3, $4, $5, $6, $7, COALESCE($12, NOW()), COALESCE($13, NOW()),
+            NOW() + CASE
+                WHEN $8 THEN (SELECT default_ttl_secs FROM projects WHERE id = $1)
+                ELSE $9
+            END * INTERVAL '1 second',
+            $10, $10
+        )
+        ON CONFLICT (project_id, namespace, key)
+        DO UPDATE SET
+            mime_type = EXCLUDED.mime_type,
+            content = EXCLUDED.content,
+            immutable = EXCLUDED.immutable,
+            response_headers = EXCLUDED.response_headers,
+            created_at = CASE
+                WHEN entries.expires_at <= NOW() THEN EXCLUDED.created_at
+                ELSE COALESCE($12, entries.created_at)
+            END,
+            updated_at = EXCLUDED.updated_at,
+            expires_at = EXCLUDED.expires_at,
+            created_by = CASE WHEN entries.expires_at <= NOW() THEN EXCLUDED.created_by ELSE entries.created_by END,
+            updated_by = EXCLUDED.updated_by
+        WHERE (NOT entries.immutable AND NOT $14) OR entries.expires_at <= NOW()
+        RETURNING (xmax = 0) AS fresh, (xmax = 0 OR COALESCE((SELECT expired FROM previous), FALSE)) AS inserted, id
+        "#,
+            )
+            .bind(project)
+            .bind(entry.namespace)
+            .bind(entry.key)
+            .bind(entry.mime_type)
+            .bind(entry.content)
+            .bind(entry.immutable)
+            .bind(sqlx::types::Json(&entry.response_headers))
+            .bind(use_project_default)
+            .bind(ttl_seconds)
+            .bind(entry.actor.as_deref())
+            // only used for a new row, an overwrite keeps the existing id
+            .bind(self.uuid_v7_ids.then(Uuid::now_v7))
+            .bind(entry.created_at)
+            .bind(entry.updated_at)
+            .bind(entry.create_only)
+            .fetch_optional(&mut *conn)
+            .await?;
+            // the old variants were of the old content
+            if let Some((false, _, id)) = upserted {
+                sqlx::query("DELETE FROM entry_variants WHERE entry_id = $1")
+                    .bind(id)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            Ok(upserted.map(|(_, inserted, _)| inserted))
+        }
+        .boxed()
+    }
+
+    // with `LIST_COLLATE_C` keys are compared as bytes, so the order doesn't depend on the database's locale and
+    // matches `idx_entries_key_c`; collations can't be bound as parameters, hence the two queries
+    fn list<'a>(
+        &'a self,
+        conn: &'a mut PgConnection,
+        project: Uuid,
+        namespace: &'a str,
+        pattern: String,
+        query: &'a ListQuery,
+        mime_pattern: Option<&'a [String]>,
+        limit: Option<i64>,
+    ) -> BoxStream<'a, sqlx::Result<KeyInfo>> {
+        if self.collate_c {
+            sqlx::query_as!(
+                KeyInfo,
+                r#"
+        SELECT key, mime_type, octet_length(content)::bigint AS "size!"
+        FROM entries
+        WHERE project_id = $1
+            AND namespace = $2
+            AND key COLLATE "C" LIKE $3
+            AND (expires_at IS NULL OR expires_at > NOW())
+            AND ($4::bigint IS NULL OR octet_length(content) >= $4)
+            AND ($5::bigint IS NULL OR octet_length(content) <= $5)
+            AND ($6::text[] IS NULL OR lower(trim(split_part(mime_type, ';', 1))) LIKE ANY($6))
+            AND ($8::text IS NULL OR key COLLATE "C" > $8)
+            AND ($9::text IS NULL OR key COLLATE "C" >= $9)
+            AND ($10::text IS NULL OR key COLLATE "C" < $10)
+        ORDER BY key COLLATE "C"
+        LIMIT $7
+        "#,
+                project,
+                namespace,
+                pattern,
+                query.min_size,
+                query.max_size,
+                mime_pattern,
+                limit,
+                query.after.as_deref(),
+                query.start.as_deref(),
+                query.end.as_deref(),
+            )
+            .fetch(conn)
+        } else {
+            sqlx::query_as!(
+                KeyInfo,
+                r#"
+        SELECT key, mime_type, octet_length(content)::bigint AS "size!"
+        FROM entries
+        WHERE project_id = $1
+            AND namespace = $2
+            AND key LIKE $3
+            AND (expires_at IS NULL OR expires_at > NOW())
+            AND ($4::bigint IS NULL OR octet_length(content) >= $4)
+            AND ($5::bigint IS NULL OR octet_length(content) <= $5)
+            AND ($6::text[] IS NULL OR lower(trim(split_part(mime_type, ';', 1))) LIKE ANY($6))
+            AND ($8::text IS NULL OR key > $8)
+            AND ($9::text IS NULL OR key >= $9)
+            AND ($10::text IS NULL OR key < $10)
+        ORDER BY key
+        LIMIT $7
+        "#,
+                project,
+                namespace,
+                pattern,
+                query.min_size,
+                query.max_size,
+                mime_pattern,
+                limit,
+                query.after.as_deref(),
+                query.start.as_deref(),
+                query.end.as_deref(),
+            )
+            .fetch(conn)
+        }
+    }
+
+    fn delete<'a>(
+        &'a self,
+        conn: &'a mut PgConnection,
+        project: Uuid,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, sqlx::Result<Option<bool>>> {
+        sqlx::query_scalar(
+            r#"
+        WITH target AS (
+            SELECT id, immutable FROM entries WHERE project_id = $1 AND namespace = $2 AND key = $3
+        ), deleted AS (
+            DELETE FROM entries WHERE id IN (SELECT id FROM target WHERE NOT immutable)
+        )
+        SELECT immutable FROM target
+        "#,
+        )
+        .bind(project)
+        .bind(namespace)
+        .bind(key)
+        .fetch_optional(conn)
+        .boxed()
+    }
+
+    fn delete_variant<'a>(
+        &'a self,
+        conn: &'a mut PgConnection,
+        project: Uuid,
+        namespace: &'a str,
+        key: &'a str,
+        mime_type: &'a str,
+    ) -> BoxFuture<'a, sqlx::Result<Option<bool>>> {
+        sqlx::query_scalar(
+            r#"
+        WITH target AS (
+            SELECT v.entry_id, e.immutable
+            FROM entry_variants v JOIN entries e ON e.id = v.entry_id
+            WHERE e.project_id = $1 AND e.namespace = $2 AND e.key = $3 AND v.mime_type = $4
+        ), deleted AS (
+            DELETE FROM entry_variants
+            WHERE entry_id IN (SELECT entry_id FROM target WHERE NOT immutable) AND mime_type = $4
+        )
+        SELECT immutable FROM target
+        "#,
+        )
+        .bind(project)
+        .bind(namespace)
+        .bind(key)
+        .bind(mime_type)
+        .fetch_optional(conn)
+        .boxed()
+    }
+
+    fn expire_prefix<'a>(
+        &'a self,
+        conn: &'a mut PgConnection,
+        project: Uuid,
+        prefix: &'a str,
+        ttl_seconds: i64,
+        dry_run: bool,
+    ) -> BoxFuture<'a, sqlx::Result<AffectedRows>> {
+        // one statement for both modes, so a dry run matches exactly the entries a real run would expire
+        let affected = sqlx::query_as(
+            r#"
+        WITH target AS (
+            SELECT id, key
+            FROM entries
+            WHERE project_id = $1
+                AND namespace = $2
+                AND key LIKE $3
+                AND (expires_at IS NULL OR expires_at > NOW())
+                AND NOT immutable
+        ), updated AS (
+            UPDATE entries
+            SET expires_at = NOW() + $4 * INTERVAL '1 second'
+            WHERE id IN (SELECT id FROM target) AND NOT $5
+        )
+        SELECT count(*), array_agg(key ORDER BY key) FILTER (WHERE $5)
+        FROM target
+        "#,
+        )
+        .bind(project)
+        .bind(DEFAULT_NAMESPACE)
+        .bind(escape_like_prefix(prefix))
+        .bind(ttl_seconds)
+        .bind(dry_run)
+        .fetch_one(conn);
+        async move { affected.await.map(|row| affected_rows(row, dry_run)) }.boxed()
+    }
+
+    fn retag_prefix<'a>(
+        &'a self,
+        conn: &'a mut PgConnection,
+        project: Uuid,
+        prefix: &'a str,
+        mime_type: &'a str,
+        actor: Option<&'a str>,
+        dry_run: bool,
+    ) -> BoxFuture<'a, sqlx::Result<AffectedRows>> {
+        // as `expire_prefix`, one statement for both modes
+        let affected = sqlx::query_as(
+            r#"
+        WITH target AS (
+            SELECT id, key
+            FROM entries
+            WHERE project_id = $1
+                AND namespace = $2
+                AND key LIKE $3
+                AND (expires_at IS NULL OR expires_at > NOW())
+                AND NOT immutable
+        ), updated AS (
+            UPDATE entries
+            SET mime_type = $4, updated_at = NOW(), updated_by = $6
+            WHERE id IN (SELECT id FROM target) AND NOT $5
+        )
+        SELECT count(*), array_agg(key ORDER BY key) FILTER (WHERE $5)
+        FROM target
+        "#,
+        )
+        .bind(project)
+        .bind(DEFAULT_NAMESPACE)
+        .bind(escape_like_prefix(prefix))
+        .bind(mime_type)
+        .bind(dry_run)
+        .bind(actor)
+        .fetch_one(conn);
+        async move { affected.await.map(|row| affected_rows(row, dry_run)) }.boxed()
+    }
+
+    fn touch_prefix<'a>(
+        &'a self,
+        conn: &'a mut PgConnection,
+        project: Uuid,
+        prefix: &'a str,
+        ttl_seconds: i64,
+        only_expiring: bool,
+    ) -> BoxFuture<'a, sqlx::Result<u64>> {
+        let result = sqlx::query(
+            r#"
+        UPDATE entries
+        SET expires_at = NOW() + $4 * INTERVAL '1 second'
+        WHERE project_id = $1
+            AND namespace = $2
+            AND key LIKE $3
+            AND (expires_at > NOW() OR (expires_at IS NULL AND NOT $5))
+            AND NOT immutable
+        "#,
+        )
+        .bind(project)
+        .bind(DEFAULT_NAMESPACE)
+        .bind(escape_like_prefix(prefix))
+        .bind(ttl_seconds)
+        .bind(only_expiring)
+        .execute(conn);
+        async move { result.await.map(|result| result.rows_affected()) }.boxed()
+    }
+
+    fn swap_value<'a>(
+        &'a self,
+        conn: &'a mut PgConnection,
+        project: Uuid,
+        key: &'a str,
+        expected: &'a [u8],
+        new: &'a [u8],
+        actor: Option<&'a str>,
+    ) -> BoxFuture<'a, sqlx::Result<Option<Swapped>>> {
+        async move {
+            // the row lock taken by `FOR UPDATE` means the content can't change between the comparison and the
+            // update
+            let current: Option<(Uuid, String, bool, bool)> = sqlx::query_as(
+                r#"
+        WITH current AS (
+            SELECT id, mime_type, immutable, content = $4 AS matches
+            FROM entries
+            WHERE project_id = $1 AND namespace = $2 AND key = $3 AND (expires_at IS NULL OR expires_at > NOW())
+            FOR UPDATE
+        ), updated AS (
+            UPDATE entries
+            SET content = $5, updated_at = NOW(), updated_by = $6
+            WHERE id IN (SELECT id FROM current WHERE matches AND NOT immutable)
+        )
+        SELECT id, mime_type, immutable, matches FROM current
+        "#,
+            )
+            .bind(project)
+            .bind(DEFAULT_NAMESPACE)
+            .bind(key)
+            .bind(expected)
+            .bind(new)
+            .bind(actor)
+            .fetch_optional(&mut *conn)
+            .await?;
+            let Some((id, mime_type, immutable, matches)) = current else {
+                return Ok(None);
+            };
+            if matches && !immutable {
+                sqlx::query("DELETE FROM entry_variants WHERE entry_id = $1")
+                    .bind(id)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            Ok(Some(Swapped {
+                mime_type,
+                immutable,
+                matches,
+            }))
+        }
+        .boxed()
+    }
+}
+
+/// The count and keys of a bulk operation, keys are only returned by a dry run
+fn affected_rows((count, keys): (i64, Option<Vec<String>>), dry_run: bool) -> AffectedRows {
+    AffectedRows {
+        count: count as u64,
+        keys: dry_run.then(|| keys.unwrap_or_default()),
+    }
+}
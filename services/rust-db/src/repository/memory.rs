@@ -0,0 +1,739 @@
+//! `EntryRepository` on maps in memory, for tests which exercise code written against the trait without a
+//! database.
+//!
+//! It follows `PgEntryRepository`'s semantics: expired entries stay until overwritten or deleted but are never
+//! returned, immutable and create-only stores, variants dropped with the content they were of, and keys ordered
+//! as bytes like `LIST_COLLATE_C`. There are no projects, so `Ttl::ProjectDefault` never expires.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use futures::{
+    FutureExt, StreamExt,
+    future::{self, BoxFuture},
+    stream::{self, BoxStream},
+};
+use sha2::{Digest, Sha256};
+use sqlx::types::Json;
+use uuid::Uuid;
+
+use super::{EntryRepository, NewEntry, Swapped, Ttl};
+use crate::models::{AffectedRows, DEFAULT_NAMESPACE, Entry, KeyInfo, ListQuery, Variant};
+
+struct StoredEntry {
+    id: Uuid,
+    mime_type: String,
+    content: Vec<u8>,
+    immutable: bool,
+    response_headers: BTreeMap<String, String>,
+    expires_at: Option<DateTime<Utc>>,
+    updated_at: DateTime<Utc>,
+    /// By mime type, so `variants` comes out ordered like the SQL's `ORDER BY mime_type`
+    variants: BTreeMap<String, (Vec<u8>, DateTime<Utc>)>,
+}
+
+impl StoredEntry {
+    fn is_live(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+/// Project, namespace and key, the same uniqueness as `entries`' constraint
+type EntryKey = (Uuid, String, String);
+
+pub struct MemoryEntryRepository {
+    /// `WEAK_ETAG_THRESHOLD`
+    weak_etag_threshold: i64,
+    entries: Mutex<BTreeMap<EntryKey, StoredEntry>>,
+}
+
+impl MemoryEntryRepository {
+    pub fn new(weak_etag_threshold: i64) -> Self {
+        Self {
+            weak_etag_threshold,
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, BTreeMap<EntryKey, StoredEntry>> {
+        self.entries.lock().expect("entries poisoned")
+    }
+
+    fn sha256(&self, content: &[u8]) -> Option<String> {
+        (content.len() as i64 <= self.weak_etag_threshold).then(|| hex::encode(Sha256::digest(content)))
+    }
+
+    /// Live, mutable entries in the default namespace under `prefix`, the bulk operations' targets
+    fn bulk_targets<'e>(
+        entries: &'e mut BTreeMap<EntryKey, StoredEntry>,
+        project: Uuid,
+        prefix: &str,
+        now: DateTime<Utc>,
+    ) -> impl Iterator<Item = (&'e EntryKey, &'e mut StoredEntry)> {
+        entries
+            .iter_mut()
+            .filter(move |((entry_project, namespace, key), entry)| {
+                *entry_project == project
+                    && namespace == DEFAULT_NAMESPACE
+                    && key.starts_with(prefix)
+                    && entry.is_live(now)
+                    && !entry.immutable
+            })
+    }
+}
+
+/// Every method works on the maps, whatever connection it's given
+impl<C: ?Sized + Send> EntryRepository<C> for MemoryEntryRepository {
+    fn get<'a>(
+        &'a self,
+        _conn: &'a mut C,
+        project: Uuid,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, sqlx::Result<Option<Entry>>> {
+        let entries = self.entries();
+        let entry = entries
+            .get(&(project, namespace.to_string(), key.to_string()))
+            .filter(|entry| entry.is_live(Utc::now()))
+            .map(|entry| Entry {
+                id: entry.id,
+                mime_type: entry.mime_type.clone(),
+                content: entry.content.clone(),
+                response_headers: Json(entry.response_headers.clone()),
+                expires_at: entry.expires_at,
+                updated_at: entry.updated_at,
+                sha256: self.sha256(&entry.content),
+                variants: entry.variants.keys().cloned().collect(),
+            });
+        future::ready(Ok(entry)).boxed()
+    }
+
+    fn get_variant<'a>(
+        &'a self,
+        _conn: &'a mut C,
+        entry_id: Uuid,
+        mime_type: &'a str,
+    ) -> BoxFuture<'a, sqlx::Result<Option<Variant>>> {
+        let entries = self.entries();
+        let variant = entries
+            .values()
+            .find(|entry| entry.id == entry_id)
+            .and_then(|entry| entry.variants.get(mime_type))
+            .map(|(content, updated_at)| Variant {
+                content: content.clone(),
+                updated_at: *updated_at,
+                sha256: self.sha256(content),
+            });
+        future::ready(Ok(variant)).boxed()
+    }
+
+    fn put<'a>(
+        &'a self,
+        _conn: &'a mut C,
+        project: Uuid,
+        entry: &'a NewEntry<'a>,
+    ) -> BoxFuture<'a, sqlx::Result<Option<bool>>> {
+        let now = Utc::now();
+        let expires_at = match entry.ttl {
+            Ttl::ProjectDefault | Ttl::Never => None,
+            Ttl::Seconds(seconds) => Some(now + Duration::seconds(seconds)),
+        };
+        let stored = |id| StoredEntry {
+            id,
+            mime_type: entry.mime_type.to_string(),
+            content: entry.content.to_vec(),
+            immutable: entry.immutable,
+            response_headers: entry.response_headers.clone(),
+            expires_at,
+            updated_at: entry.updated_at.unwrap_or(now),
+            variants: BTreeMap::new(),
+        };
+        let mut entries = self.entries();
+        let key = (project, entry.namespace.to_string(), entry.key.to_string());
+        let inserted = match entries.get_mut(&key) {
+            // an overwrite keeps the row's id, and drops the variants of the old content
+            Some(existing) if existing.is_live(now) => {
+                if existing.immutable || entry.create_only {
+                    None
+                } else {
+                    *existing = stored(existing.id);
+                    Some(false)
+                }
+            }
+            Some(expired) => {
+                *expired = stored(expired.id);
+                Some(true)
+            }
+            None => {
+                entries.insert(key, stored(Uuid::new_v4()));
+                Some(true)
+            }
+        };
+        future::ready(Ok(inserted)).boxed()
+    }
+
+    fn list<'a>(
+        &'a self,
+        _conn: &'a mut C,
+        project: Uuid,
+        namespace: &'a str,
+        pattern: String,
+        query: &'a ListQuery,
+        mime_pattern: Option<&'a [String]>,
+        limit: Option<i64>,
+    ) -> BoxStream<'a, sqlx::Result<KeyInfo>> {
+        let now = Utc::now();
+        let entries = self.entries();
+        let keys: Vec<KeyInfo> = entries
+            .iter()
+            .filter(|((entry_project, entry_namespace, key), entry)| {
+                let size = entry.content.len() as i64;
+                let essence = entry
+                    .mime_type
+                    .split(';')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_lowercase();
+                *entry_project == project
+                    && entry_namespace == namespace
+                    && matches_like(&pattern, key)
+                    && entry.is_live(now)
+                    && query.min_size.is_none_or(|min_size| size >= min_size)
+                    && query.max_size.is_none_or(|max_size| size <= max_size)
+                    && mime_pattern.is_none_or(|patterns| patterns.iter().any(|p| matches_like(p, &essence)))
+                    && query.after.as_deref().is_none_or(|after| key.as_str() > after)
+                    && query.start.as_deref().is_none_or(|start| key.as_str() >= start)
+                    && query.end.as_deref().is_none_or(|end| key.as_str() < end)
+            })
+            .take(limit.map_or(usize::MAX, |limit| limit as usize))
+            .map(|((_, _, key), entry)| KeyInfo {
+                key: key.clone(),
+                mime_type: entry.mime_type.clone(),
+                size: entry.content.len() as i64,
+            })
+            .collect();
+        stream::iter(keys.into_iter().map(Ok)).boxed()
+    }
+
+    fn delete<'a>(
+        &'a self,
+        _conn: &'a mut C,
+        project: Uuid,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, sqlx::Result<Option<bool>>> {
+        // like the SQL, an expired entry is still found and deleted
+        let mut entries = self.entries();
+        let key = (project, namespace.to_string(), key.to_string());
+        let immutable = entries.get(&key).map(|entry| entry.immutable);
+        if immutable == Some(false) {
+            entries.remove(&key);
+        }
+        future::ready(Ok(immutable)).boxed()
+    }
+
+    fn delete_variant<'a>(
+        &'a self,
+        _conn: &'a mut C,
+        project: Uuid,
+        namespace: &'a str,
+        key: &'a str,
+        mime_type: &'a str,
+    ) -> BoxFuture<'a, sqlx::Result<Option<bool>>> {
+        let mut entries = self.entries();
+        let immutable = entries
+            .get_mut(&(project, namespace.to_string(), key.to_string()))
+            .filter(|entry| entry.variants.contains_key(mime_type))
+            .map(|entry| {
+                if !entry.immutable {
+                    entry.variants.remove(mime_type);
+                }
+                entry.immutable
+            });
+        future::ready(Ok(immutable)).boxed()
+    }
+
+    fn expire_prefix<'a>(
+        &'a self,
+        _conn: &'a mut C,
+        project: Uuid,
+        prefix: &'a str,
+        ttl_seconds: i64,
+        dry_run: bool,
+    ) -> BoxFuture<'a, sqlx::Result<AffectedRows>> {
+        let now = Utc::now();
+        let mut entries = self.entries();
+        let mut keys = Vec::new();
+        for ((_, _, key), entry) in Self::bulk_targets(&mut entries, project, prefix, now) {
+            if !dry_run {
+                entry.expires_at = Some(now + Duration::seconds(ttl_seconds));
+            }
+            keys.push(key.clone());
+        }
+        future::ready(Ok(affected_rows(keys, dry_run))).boxed()
+    }
+
+    fn retag_prefix<'a>(
+        &'a self,
+        _conn: &'a mut C,
+        project: Uuid,
+        prefix: &'a str,
+        mime_type: &'a str,
+        _actor: Option<&'a str>,
+        dry_run: bool,
+    ) -> BoxFuture<'a, sqlx::Result<AffectedRows>> {
+        let now = Utc::now();
+        let mut entries = self.entries();
+        let mut keys = Vec::new();
+        for ((_, _, key), entry) in Self::bulk_targets(&mut entries, project, prefix, now) {
+            if !dry_run {
+                entry.mime_type = mime_type.to_string();
+                entry.updated_at = now;
+            }
+            keys.push(key.clone());
+        }
+        future::ready(Ok(affected_rows(keys, dry_run))).boxed()
+    }
+
+    fn touch_prefix<'a>(
+        &'a self,
+        _conn: &'a mut C,
+        project: Uuid,
+        prefix: &'a str,
+        ttl_seconds: i64,
+        only_expiring: bool,
+    ) -> BoxFuture<'a, sqlx::Result<u64>> {
+        let now = Utc::now();
+        let mut entries = self.entries();
+        let mut touched = 0;
+        for (_, entry) in Self::bulk_targets(&mut entries, project, prefix, now) {
+            if entry.expires_at.is_some() || !only_expiring {
+                entry.expires_at = Some(now + Duration::seconds(ttl_seconds));
+                touched += 1;
+            }
+        }
+        future::ready(Ok(touched)).boxed()
+    }
+
+    fn swap_value<'a>(
+        &'a self,
+        _conn: &'a mut C,
+        project: Uuid,
+        key: &'a str,
+        expected: &'a [u8],
+        new: &'a [u8],
+        _actor: Option<&'a str>,
+    ) -> BoxFuture<'a, sqlx::Result<Option<Swapped>>> {
+        let now = Utc::now();
+        let mut entries = self.entries();
+        let swapped = entries
+            .get_mut(&(project, DEFAULT_NAMESPACE.to_string(), key.to_string()))
+            .filter(|entry| entry.is_live(now))
+            .map(|entry| {
+                let matches = entry.content == expected;
+                if matches && !entry.immutable {
+                    entry.content = new.to_vec();
+                    entry.updated_at = now;
+                    entry.variants.clear();
+                }
+                Swapped {
+                    mime_type: entry.mime_type.clone(),
+                    immutable: entry.immutable,
+                    matches,
+                }
+            });
+        future::ready(Ok(swapped)).boxed()
+    }
+}
+
+/// As `super::affected_rows`, the keys come out of the map already in order
+fn affected_rows(keys: Vec<String>, dry_run: bool) -> AffectedRows {
+    AffectedRows {
+        count: keys.len() as u64,
+        keys: dry_run.then_some(keys),
+    }
+}
+
+/// Whether `text` matches the SQL `LIKE` `pattern`, with `\` escaping the wildcards as `like::escape_like` does
+fn matches_like(pattern: &str, text: &str) -> bool {
+    enum Token {
+        Any,
+        One,
+        Char(char),
+    }
+
+    fn matches(tokens: &[Token], text: &[char]) -> bool {
+        match tokens.split_first() {
+            None => text.is_empty(),
+            Some((Token::Any, rest)) => (0..=text.len()).any(|skip| matches(rest, &text[skip..])),
+            Some((Token::One, rest)) => !text.is_empty() && matches(rest, &text[1..]),
+            Some((Token::Char(c), rest)) => text.first() == Some(c) && matches(rest, &text[1..]),
+        }
+    }
+
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '%' => Token::Any,
+            '_' => Token::One,
+            '\\' => Token::Char(chars.next().unwrap_or('\\')),
+            c => Token::Char(c),
+        });
+    }
+    matches(&tokens, &text.chars().collect::<Vec<_>>())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::like::escape_like_prefix;
+
+    const PROJECT: Uuid = Uuid::from_u128(1);
+
+    fn new_entry<'a>(key: &'a str, content: &'a [u8]) -> NewEntry<'a> {
+        NewEntry {
+            namespace: DEFAULT_NAMESPACE,
+            key,
+            mime_type: "text/plain",
+            content,
+            immutable: false,
+            ttl: Ttl::Never,
+            response_headers: BTreeMap::new(),
+            actor: None,
+            created_at: None,
+            updated_at: None,
+            create_only: false,
+        }
+    }
+
+    fn list_query(query: serde_json::Value) -> ListQuery {
+        serde_json::from_value(query).unwrap()
+    }
+
+    /// Through the trait object, as handlers hold the repository
+    fn repository() -> Box<dyn EntryRepository<()>> {
+        Box::new(MemoryEntryRepository::new(4))
+    }
+
+    async fn keys(repository: &dyn EntryRepository<()>, prefix: &str, query: &ListQuery) -> Vec<String> {
+        repository
+            .list_prefix(&mut (), PROJECT, DEFAULT_NAMESPACE, prefix, query, None, None)
+            .map_ok(|info| info.key)
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn put_then_get() {
+        let repository = repository();
+        assert_eq!(
+            repository.put(&mut (), PROJECT, &new_entry("a", b"one")).await.unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            repository
+                .put(&mut (), PROJECT, &new_entry("a", b"three"))
+                .await
+                .unwrap(),
+            Some(false)
+        );
+
+        let entry = repository
+            .get(&mut (), PROJECT, DEFAULT_NAMESPACE, "a")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.content, b"three");
+        // over `weak_etag_threshold`, so not hashed
+        assert_eq!(entry.sha256, None);
+        assert!(repository.get(&mut (), PROJECT, "other", "a").await.unwrap().is_none());
+        assert!(
+            repository
+                .get(&mut (), Uuid::from_u128(2), DEFAULT_NAMESPACE, "a")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn immutable_and_create_only() {
+        let repository = repository();
+        let immutable = NewEntry {
+            immutable: true,
+            ..new_entry("locked", b"x")
+        };
+        repository.put(&mut (), PROJECT, &immutable).await.unwrap();
+        assert_eq!(
+            repository
+                .put(&mut (), PROJECT, &new_entry("locked", b"y"))
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            repository
+                .delete(&mut (), PROJECT, DEFAULT_NAMESPACE, "locked")
+                .await
+                .unwrap(),
+            Some(true)
+        );
+
+        repository
+            .put(&mut (), PROJECT, &new_entry("taken", b"x"))
+            .await
+            .unwrap();
+        let create_only = NewEntry {
+            create_only: true,
+            ..new_entry("taken", b"y")
+        };
+        assert_eq!(repository.put(&mut (), PROJECT, &create_only).await.unwrap(), None);
+        assert_eq!(
+            repository
+                .delete(&mut (), PROJECT, DEFAULT_NAMESPACE, "taken")
+                .await
+                .unwrap(),
+            Some(false)
+        );
+        assert_eq!(
+            repository
+                .delete(&mut (), PROJECT, DEFAULT_NAMESPACE, "taken")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_entries() {
+        let repository = repository();
+        let expired = NewEntry {
+            immutable: true,
+            ttl: Ttl::Seconds(0),
+            ..new_entry("gone", b"x")
+        };
+        repository.put(&mut (), PROJECT, &expired).await.unwrap();
+        assert!(
+            repository
+                .get(&mut (), PROJECT, DEFAULT_NAMESPACE, "gone")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            keys(&*repository, "", &list_query(serde_json::json!({})))
+                .await
+                .is_empty()
+        );
+
+        // an expired entry doesn't hold the key, even immutable or against a create-only store
+        let create_only = NewEntry {
+            create_only: true,
+            ..new_entry("gone", b"y")
+        };
+        assert_eq!(
+            repository.put(&mut (), PROJECT, &create_only).await.unwrap(),
+            Some(true)
+        );
+    }
+
+    #[tokio::test]
+    async fn list_filters() {
+        let repository = repository();
+        for key in ["a/1", "a/2", "a/3", "a_b", "ab", "b"] {
+            repository
+                .put(&mut (), PROJECT, &new_entry(key, key.as_bytes()))
+                .await
+                .unwrap();
+        }
+        let image = NewEntry {
+            mime_type: "Image/PNG; q=1",
+            ..new_entry("a/png", b"png")
+        };
+        repository.put(&mut (), PROJECT, &image).await.unwrap();
+
+        let all = list_query(serde_json::json!({}));
+        assert_eq!(keys(&*repository, "a/", &all).await, ["a/1", "a/2", "a/3", "a/png"]);
+        // the prefix's `_` is literal
+        assert_eq!(keys(&*repository, "a_", &all).await, ["a_b"]);
+
+        let page = list_query(serde_json::json!({"after": "a/1", "end": "a/3"}));
+        assert_eq!(keys(&*repository, "a/", &page).await, ["a/2"]);
+        let sized = list_query(serde_json::json!({"min_size": 3, "max_size": 3}));
+        assert_eq!(
+            keys(&*repository, "", &sized).await,
+            ["a/1", "a/2", "a/3", "a/png", "a_b"]
+        );
+
+        let images: Vec<String> = repository
+            .list(
+                &mut (),
+                PROJECT,
+                DEFAULT_NAMESPACE,
+                escape_like_prefix(""),
+                &all,
+                Some(&["image/%".to_string()]),
+                Some(10),
+            )
+            .map_ok(|info| info.key)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(images, ["a/png"]);
+    }
+
+    #[tokio::test]
+    async fn variants_go_with_old_content() {
+        let repository = MemoryEntryRepository::new(4);
+        EntryRepository::<()>::put(&repository, &mut (), PROJECT, &new_entry("logo", b"jpeg"))
+            .await
+            .unwrap();
+        let key = (PROJECT, DEFAULT_NAMESPACE.to_string(), "logo".to_string());
+        let id = {
+            let mut entries = repository.entries();
+            let entry = entries.get_mut(&key).unwrap();
+            entry
+                .variants
+                .insert("image/webp".to_string(), (b"webp".to_vec(), Utc::now()));
+            entry.id
+        };
+        let repository: &dyn EntryRepository<()> = &repository;
+
+        let variant = repository
+            .get_variant(&mut (), id, "image/webp")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(variant.content, b"webp");
+        assert_eq!(variant.sha256, Some(hex::encode(Sha256::digest(b"webp"))));
+
+        assert_eq!(
+            repository
+                .put(&mut (), PROJECT, &new_entry("logo", b"png"))
+                .await
+                .unwrap(),
+            Some(false)
+        );
+        let entry = repository
+            .get(&mut (), PROJECT, DEFAULT_NAMESPACE, "logo")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.id, id);
+        assert!(entry.variants.is_empty());
+        assert_eq!(
+            repository
+                .delete_variant(&mut (), PROJECT, DEFAULT_NAMESPACE, "logo", "image/webp")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn bulk_operations() {
+        let repository = repository();
+        for key in ["tmp/a", "tmp/b", "keep"] {
+            repository.put(&mut (), PROJECT, &new_entry(key, b"x")).await.unwrap();
+        }
+
+        let preview = repository
+            .expire_prefix(&mut (), PROJECT, "tmp/", 0, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            (preview.count, preview.keys),
+            (2, Some(vec!["tmp/a".to_string(), "tmp/b".to_string()]))
+        );
+        assert_eq!(
+            keys(&*repository, "", &list_query(serde_json::json!({}))).await,
+            ["keep", "tmp/a", "tmp/b"]
+        );
+
+        let retagged = repository
+            .retag_prefix(&mut (), PROJECT, "tmp/", "text/csv", None, false)
+            .await
+            .unwrap();
+        assert_eq!((retagged.count, retagged.keys), (2, None));
+        let entry = repository
+            .get(&mut (), PROJECT, DEFAULT_NAMESPACE, "tmp/a")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.mime_type, "text/csv");
+
+        // only entries which already expire, and there are none
+        assert_eq!(
+            repository.touch_prefix(&mut (), PROJECT, "", 60, true).await.unwrap(),
+            0
+        );
+        assert_eq!(
+            repository.touch_prefix(&mut (), PROJECT, "", 60, false).await.unwrap(),
+            3
+        );
+
+        repository
+            .expire_prefix(&mut (), PROJECT, "tmp/", 0, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            keys(&*repository, "", &list_query(serde_json::json!({}))).await,
+            ["keep"]
+        );
+    }
+
+    #[tokio::test]
+    async fn swap_value() {
+        let repository = repository();
+        repository
+            .put(&mut (), PROJECT, &new_entry("counter", b"1"))
+            .await
+            .unwrap();
+
+        let swapped = repository
+            .swap_value(&mut (), PROJECT, "counter", b"0", b"2", None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!swapped.matches);
+        let swapped = repository
+            .swap_value(&mut (), PROJECT, "counter", b"1", b"2", None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(swapped.matches);
+        let entry = repository
+            .get(&mut (), PROJECT, DEFAULT_NAMESPACE, "counter")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.content, b"2");
+        assert!(
+            repository
+                .swap_value(&mut (), PROJECT, "missing", b"", b"", None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn like_patterns() {
+        assert!(matches_like("a/%", "a/b/c"));
+        assert!(matches_like("%", ""));
+        assert!(matches_like("a_c", "abc"));
+        assert!(!matches_like("a_c", "ac"));
+        assert!(matches_like("a\\_c", "a_c"));
+        assert!(!matches_like("a\\_c", "abc"));
+        assert!(matches_like("100\\%%", "100%/x"));
+        assert!(!matches_like("a", "ab"));
+    }
+}
@@ -1,21 +1,373 @@
+use std::{sync::Arc, time::Duration};
+
 use axum::{
-    Router,
-    routing::{delete, get, post},
+    BoxError, Router,
+    error_handling::HandleErrorLayer,
+    extract::{DefaultBodyLimit, Request},
+    handler::Handler,
+    http::{Method, Uri},
+    middleware,
+    routing::{MethodRouter, delete, get, patch, post, put},
 };
 use axum_tracing_opentelemetry::middleware::{OtelAxumLayer, OtelInResponseLayer};
+use tower::{ServiceBuilder, limit::GlobalConcurrencyLimitLayer, load_shed::LoadShedLayer, timeout::TimeoutLayer};
 
-use crate::handlers::entries;
+use crate::{
+    access_log, admin_auth, body_timeout, client_ip,
+    config::Config,
+    error::{self, AppError},
+    handlers::{admin, audit, batch, cas, changes, entries, export, health, jsonquery, resumable, signed, usage},
+    shutdown,
+    state::AppState,
+    telemetry, timings, trace_sampling,
+};
 
-pub fn create_router(pool: std::sync::Arc<sqlx_tracing::Pool<sqlx::Postgres>>) -> Router {
-    Router::new()
+/// Router for the public data API, `include_admin` also mounts the admin routes when there's no dedicated admin port
+pub fn create_router(state: AppState, include_admin: bool) -> Router {
+    let router = data_routes(&state.config);
+    // health checks are merged after the limit, so probes still succeed when the data API is shedding load
+    let router = match state.config.max_concurrent_requests {
+        Some(max) => with_concurrency_limit(router, max),
+        None => router,
+    };
+    let router = router.layer(middleware::from_fn(shutdown::refuse_while_draining));
+    let router = if include_admin {
+        router.merge(admin_routes(&state))
+    } else {
+        router
+    };
+    finish(router, state)
+}
+
+/// Router for the dedicated admin port, serving only the admin/health routes
+pub fn create_admin_router(state: AppState) -> Router {
+    finish(admin_routes(&state), state)
+}
+
+fn data_routes(config: &Arc<Config>) -> Router<AppState> {
+    let router = Router::new()
         // Entry operations - more specific routes first
-        .route("/project/{project}/get/{*key}", get(entries::get_entry))
-        .route("/project/{project}/list/", get(entries::list_entries_all))
-        .route("/project/{project}/list/{*prefix}", get(entries::list_entries))
-        // Catch-all routes for store and delete
-        .route("/project/{project}/{*key}", post(entries::store_entry))
-        .route("/project/{project}/{*key}", delete(entries::delete_entry))
-        .with_state(pool)
+        .route("/project/{project}/get/{*key}", shadowing(get(entries::get_entry)))
+        .route(
+            "/project/{project}/meta/{*key}",
+            shadowing(get(entries::get_entry_meta)),
+        )
+        .route(
+            "/project/{project}/list/",
+            shadowing(get(entries::list_entries).head(entries::head_list_entries)),
+        )
+        .route(
+            "/project/{project}/list/{*prefix}",
+            shadowing(get(entries::list_entries).head(entries::head_list_entries)),
+        )
+        .route("/project/{project}/sign/{*key}", shadowing(get(signed::sign_entry)))
+        .route("/signed/{project}/{*key}", get(signed::get_signed_entry))
+        .route(
+            "/project/{project}/expire/",
+            shadowing(post(entries::expire_entries_all)),
+        )
+        .route(
+            "/project/{project}/expire/{*prefix}",
+            shadowing(post(entries::expire_entries)),
+        )
+        .route("/project/{project}/retag/", shadowing(post(entries::retag_entries_all)))
+        .route(
+            "/project/{project}/retag/{*prefix}",
+            shadowing(post(entries::retag_entries)),
+        )
+        .route(
+            "/project/{project}/cas-value/{*key}",
+            shadowing(post(entries::swap_entry_value)),
+        )
+        .route(
+            "/project/{project}/touch-all",
+            shadowing(post(entries::touch_all_entries)),
+        )
+        .route("/project/{project}/cas/{hash}", shadowing(get(cas::get_cas)))
+        .route("/project/{project}/batch", shadowing(post(batch::batch)))
+        .route("/project/{project}/audit", shadowing(get(audit::audit_log)))
+        .route("/project/{project}/changes", shadowing(get(changes::list_changes)))
+        .route("/project/{project}/jsonquery", shadowing(get(jsonquery::query_json)))
+        // The same operations within a namespace, routes above use the default namespace
+        .route(
+            "/project/{project}/ns/{namespace}/get/{*key}",
+            shadowing(get(entries::get_entry)),
+        )
+        .route(
+            "/project/{project}/ns/{namespace}/meta/{*key}",
+            shadowing(get(entries::get_entry_meta)),
+        )
+        .route(
+            "/project/{project}/ns/{namespace}/list/",
+            shadowing(get(entries::list_entries).head(entries::head_list_entries)),
+        )
+        .route(
+            "/project/{project}/ns/{namespace}/list/{*prefix}",
+            shadowing(get(entries::list_entries).head(entries::head_list_entries)),
+        )
+        // Catch-all route for delete, stores to the same paths are added below
+        .route(
+            "/project/{project}/ns/{namespace}/{*key}",
+            delete(entries::delete_entry),
+        )
+        .route("/project/{project}/{*key}", delete(entries::delete_entry));
+    let router = match config.request_timeout {
+        Some(timeout) => with_timeout(router, timeout),
+        None => router,
+    };
+    // downloads and uploads of whole projects or entries are exempt from `REQUEST_TIMEOUT_MS`, the time they take
+    // depends on their size and the client's link; reading an upload's body is still limited by
+    // `BODY_READ_TIMEOUT_SECS`
+    let router = router
+        .route("/project/{project}/export", shadowing(get(export::export_entries)))
+        .route("/project/{project}/dump", shadowing(get(export::dump_entries)))
+        .route("/project/{project}/restore-dump", shadowing(post(export::restore_dump)))
+        .route("/project/{project}/cas", shadowing(post(cas::store_cas)))
+        .route("/project/{project}/uploads", shadowing(post(resumable::create_upload)))
+        .route(
+            "/project/{project}/uploads/{upload}",
+            shadowing(patch(resumable::append_upload).head(resumable::upload_status)),
+        )
+        // Structured JSON store, takes priority over the catch-all store route
+        .route("/project/{project}/entry", shadowing(post(entries::store_entry_json)))
+        .route("/project/{project}/ns/{namespace}/{*key}", post(entries::store_entry))
+        // Catch-all route for store
+        .route("/project/{project}/{*key}", post(entries::store_entry));
+    // added after the counting layer, so checking usage doesn't count as a read, stores and deletes of the key
+    // `usage` are counted as usual
+    let counted_fallback = entries::store_or_delete_shadowed_key.layer(middleware::from_fn(crate::usage::count_usage));
+    let router = router
+        .route_layer(middleware::from_fn(crate::usage::count_usage))
+        .route(
+            "/project/{project}/usage",
+            get(usage::project_usage).fallback(counted_fallback),
+        );
+    // raised to the largest `MIME_SIZE_LIMITS` rule, store then checks the limit for the entry's own mime type
+    router
+        .layer(DefaultBodyLimit::max(config.max_body_size()))
+        .layer(middleware::from_fn_with_state(
+            config.clone(),
+            body_timeout::limit_body_read_time,
+        ))
+}
+
+/// A fixed route under `/project/{project}/` shadows the catch-all store and delete routes for the key its path
+/// spells out, so the methods it doesn't handle itself store and delete that key instead
+fn shadowing(route: MethodRouter<AppState>) -> MethodRouter<AppState> {
+    route.fallback(entries::store_or_delete_shadowed_key)
+}
+
+/// Time out requests with a `504` and our usual error body
+fn with_timeout(router: Router<AppState>, timeout: Duration) -> Router<AppState> {
+    router.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(move |_: BoxError| async move {
+                AppError::Timeout(timeout.as_millis())
+            }))
+            .layer(TimeoutLayer::new(timeout)),
+    )
+}
+
+/// Health checks stay open so probes don't need the admin token, and keep answering while draining
+fn admin_routes(state: &AppState) -> Router<AppState> {
+    let admin = Router::new()
+        .route("/admin/maintenance", post(admin::run_maintenance))
+        // not under `/admin/project/{project}/`, where it would shadow force deleting the key `collisions`
+        .route("/admin/collisions/{project}", get(admin::key_collisions))
+        .route("/admin/find-key", get(admin::find_key))
+        .route("/admin/list", post(admin::list_projects))
+        .route("/admin/merge", post(admin::merge_projects))
+        .route("/admin/timings", get(admin::timings))
+        .route("/admin/project", post(admin::create_project))
+        // the project itself rather than an admin action on it, but still admin (and behind `ADMIN_TOKEN`) since
+        // it sets the project's quota; the data routes have nothing at the bare project path
+        .route("/project/{project}", put(admin::set_project_settings))
+        .route("/admin/project/{project}/{*key}", delete(admin::force_delete_entry))
+        .route_layer(middleware::from_fn_with_state(
+            state.config.clone(),
+            admin_auth::require_admin_token,
+        ))
+        .layer(middleware::from_fn(shutdown::refuse_while_draining));
+    Router::new()
+        .route("/health", get(health::health))
+        .route("/ready", get(health::ready))
+        .merge(admin)
+}
+
+/// Reject requests beyond `max` in flight with `503` straight away, rather than queueing them for the pool; the
+/// global layer shares one semaphore across routes, `ConcurrencyLimitLayer` would limit each route separately
+fn with_concurrency_limit(router: Router<AppState>, max: usize) -> Router<AppState> {
+    router
+        .layer(middleware::from_fn(telemetry::count_active_requests))
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(|_: BoxError| async { AppError::Overloaded }))
+                .layer(LoadShedLayer::new())
+                .layer(GlobalConcurrencyLimitLayer::new(max)),
+        )
+}
+
+fn finish(router: Router<AppState>, state: AppState) -> Router {
+    let router = match state.config.slow_query {
+        // a route layer, so the matched route and path params are available
+        Some(_) => router.route_layer(middleware::from_fn_with_state(
+            state.config.clone(),
+            telemetry::log_slow_queries,
+        )),
+        None => router,
+    };
+    let router = router
+        .route_layer(middleware::from_fn(timings::record_timing))
+        .layer(middleware::from_fn_with_state(
+            state.config.clone(),
+            error::negotiate_error_body,
+        ));
+    let router = access_log::add_json_layer(router, &state.config).layer(middleware::from_fn_with_state(
+        state.clone(),
+        client_ip::resolve_client_ip,
+    ));
+    // sampling is decided before `OtelAxumLayer` starts the request's span
+    let config = state.config.clone();
+    let router = access_log::add_layers(router, &state.config)
+        .with_state(state)
         .layer(OtelAxumLayer::default())
-        .layer(OtelInResponseLayer)
+        .layer(middleware::from_fn_with_state(config, trace_sampling::sample_trace))
+        .layer(OtelInResponseLayer);
+    // middleware on a router only runs once it has routed the request, so the path is fixed up by an outer router
+    // whose only route is the real one
+    Router::new()
+        .fallback_service(router)
+        .layer(middleware::map_request(normalize_trailing_slash))
+}
+
+/// Make a trailing slash optional on `GET` and `HEAD` routes: it's trimmed, except that listing routes (where
+/// `list/` with an empty prefix lists everything) get one added.
+///
+/// Paths to a key or prefix (`get/`, `meta/`, `sign/`, `list/` and signed URLs) are left alone, since `foo/` and
+/// `foo` are different keys, as are paths for other methods, which are all stores and deletes of keys.
+async fn normalize_trailing_slash(mut request: Request) -> Request {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return request;
+    }
+    let Some(path) = canonical_path(request.uri().path()) else {
+        return request;
+    };
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path,
+    };
+    let mut parts = request.uri().clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *request.uri_mut() = uri;
+    }
+    request
+}
+
+/// Where `path` should be routed instead, `None` when it's fine as it is
+fn canonical_path(path: &str) -> Option<String> {
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    let route = match segments.as_slice() {
+        ["signed", ..] => return None,
+        ["project", _, "ns", _, route @ ..] | ["project", _, route @ ..] => route,
+        _ => &[],
+    };
+    match route {
+        ["get" | "meta" | "sign" | "list", _, ..] => None,
+        ["list"] => Some(format!("{path}/")),
+        _ if path.len() > 1 && path.ends_with('/') => Some(format!("/{}", path.trim_matches('/'))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::{
+        body::{Body, Bytes},
+        http::{StatusCode, header},
+    };
+    use futures::{StreamExt, stream};
+    use sqlx::postgres::PgPoolOptions;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::repository::PgEntryRepository;
+
+    /// The default config
+    fn config() -> Arc<Config> {
+        // SAFETY: no other test reads or writes the environment
+        unsafe { std::env::set_var("DATABASE_URL", "postgres://localhost:1/unused") };
+        Arc::new(Config::from_env().unwrap())
+    }
+
+    /// The router with the default config, on a pool which never connects
+    fn router() -> Router {
+        let config = config();
+        let pool = PgPoolOptions::new().connect_lazy(&config.database_url).unwrap();
+        let state = AppState {
+            pool: Arc::new(sqlx_tracing::Pool::from(pool)),
+            repository: Arc::new(PgEntryRepository::new(&config)),
+            config,
+        };
+        create_router(state, false)
+    }
+
+    /// One byte of the ten promised, then nothing
+    fn trickle() -> Body {
+        let first = stream::once(async { Ok::<_, Infallible>(Bytes::from_static(b"x")) });
+        Body::from_stream(first.chain(stream::pending()))
+    }
+
+    /// Uploads are exempt from `REQUEST_TIMEOUT_MS`, so with the defaults a trickling body gets `408` after
+    /// `BODY_READ_TIMEOUT_SECS` rather than `504` after the shorter request timeout
+    #[tokio::test(start_paused = true)]
+    async fn trickling_uploads_get_body_read_timeout() {
+        let project = "0198a8a4-5c1e-7000-8000-000000000000";
+        let uploads = [
+            (Method::POST, format!("/project/{project}/slow.txt")),
+            (Method::POST, format!("/project/{project}/ns/docs/slow.txt")),
+            (Method::POST, format!("/project/{project}/cas")),
+            (
+                Method::PATCH,
+                format!("/project/{project}/uploads/0198a8a4-5c1e-7000-8000-000000000001"),
+            ),
+        ];
+        for (method, uri) in uploads {
+            let request = Request::builder()
+                .method(method)
+                .uri(&uri)
+                .header("content-length", "10")
+                .header("upload-offset", "0")
+                .body(trickle())
+                .unwrap();
+            let started = tokio::time::Instant::now();
+            let response = router().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT, "{uri}");
+            assert_eq!(started.elapsed(), Duration::from_secs(300), "{uri}");
+        }
+    }
+
+    /// Re-rendering an error's body mustn't drop the headers set along with it
+    #[tokio::test]
+    async fn negotiated_errors_keep_their_headers() {
+        let router = Router::new()
+            .route(
+                "/",
+                get(|| async { ([(header::CONNECTION, "close")], AppError::ShuttingDown) }),
+            )
+            .layer(middleware::from_fn_with_state(config(), error::negotiate_error_body));
+        let request = Request::builder()
+            .uri("/")
+            .header(header::ACCEPT, "text/plain")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::CONNECTION], "close");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, AppError::ShuttingDown.to_string());
+    }
 }
@@ -0,0 +1,94 @@
+//! Accept loop built on hyper-util's connection builder, `axum::serve` doesn't expose HTTP/2 or keep-alive
+//! settings.
+
+use std::{io, net::SocketAddr, pin::pin, time::Duration};
+
+use axum::Router;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, watch},
+};
+use tower::Service;
+
+use crate::config::Config;
+
+/// Serve until `shutdown` changes, then stop accepting and wait for open connections to finish their in-flight
+/// requests
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    config: &Config,
+    mut shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let builder = connection_builder(config);
+    // connect info gives the socket peer address, used to resolve the client IP
+    let mut make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+    // every connection task holds a sender, so `recv` returns `None` once they've all finished
+    let (open_tx, mut open_rx) = mpsc::channel::<()>(1);
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.changed() => break,
+        };
+        let (stream, peer) = match accepted {
+            Ok(conn) => conn,
+            Err(e) => {
+                // e.g. too many open files, back off rather than spinning
+                logfire::warn!("failed to accept connection: {error}", error = e.to_string());
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let service = make_service.call(peer).await.unwrap_or_else(|err| match err {});
+        let service = TowerToHyperService::new(service);
+        let builder = builder.clone();
+        let open = open_tx.clone();
+        let mut shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let _open = open;
+            // not `serve_connection_with_upgrades`, which ignores `http1_only`
+            let conn = builder.serve_connection(TokioIo::new(stream), service);
+            let mut conn = pin!(conn);
+            let result = tokio::select! {
+                result = conn.as_mut() => result,
+                _ = shutdown.changed() => {
+                    // finishes the in-flight request then closes, idle connections close straight away
+                    conn.as_mut().graceful_shutdown();
+                    conn.await
+                }
+            };
+            if let Err(e) = result {
+                tracing::debug!("failed to serve connection: {e:#}");
+            }
+        });
+    }
+
+    drop(open_tx);
+    open_rx.recv().await;
+    Ok(())
+}
+
+/// HTTP/1.1 only unless `HTTP2` is set, in which case clients may also use HTTP/2 with prior knowledge
+fn connection_builder(config: &Config) -> Builder<TokioExecutor> {
+    let mut builder = Builder::new(TokioExecutor::new());
+    builder.http1().keep_alive(config.keep_alive);
+    if let Some(idle_timeout) = config.idle_timeout {
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(idle_timeout);
+    }
+    if !config.http2 {
+        return builder.http1_only();
+    }
+    if let Some(interval) = config.http2_keep_alive_interval {
+        builder.http2().timer(TokioTimer::new()).keep_alive_interval(interval);
+    }
+    builder
+}
@@ -0,0 +1,57 @@
+//! Graceful shutdown: on SIGTERM or Ctrl-C the service starts draining, refusing new requests with `503` and
+//! reporting not ready for `SHUTDOWN_DRAIN_SECS` so load balancers stop routing to it, then it stops accepting
+//! connections and exits once in-flight requests have finished.
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use axum::{
+    extract::Request,
+    http::{HeaderValue, Version, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::signal::unix::{SignalKind, signal};
+
+use crate::error::AppError;
+
+/// Set once a shutdown signal has been received
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::Relaxed)
+}
+
+/// Wait for a shutdown signal, then drain for `drain` before returning, at which point servers should stop
+/// accepting connections
+pub async fn drained(drain: Duration) {
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+    DRAINING.store(true, Ordering::Relaxed);
+    logfire::info!(
+        "shutting down, draining for {drain_secs}s",
+        drain_secs = drain.as_secs()
+    );
+    tokio::time::sleep(drain).await;
+}
+
+/// Refuse requests with `503` while draining, closing HTTP/1.1 connections so clients reconnect elsewhere rather
+/// than reusing this one
+pub async fn refuse_while_draining(request: Request, next: Next) -> Response {
+    if !is_draining() {
+        return next.run(request).await;
+    }
+    let mut response = AppError::ShuttingDown.into_response();
+    // connection-specific headers aren't allowed in HTTP/2
+    if request.version() < Version::HTTP_2 {
+        response
+            .headers_mut()
+            .insert(header::CONNECTION, HeaderValue::from_static("close"));
+    }
+    response
+}
@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+
+use crate::{config::Config, repository::EntryRepository};
+
+pub type Pool = Arc<sqlx_tracing::Pool<sqlx::Postgres>>;
+
+pub type Repository = Arc<dyn EntryRepository>;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: Pool,
+    pub config: Arc<Config>,
+    pub repository: Repository,
+}
+
+impl FromRef<AppState> for Pool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for Repository {
+    fn from_ref(state: &AppState) -> Self {
+        state.repository.clone()
+    }
+}
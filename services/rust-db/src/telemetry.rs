@@ -0,0 +1,175 @@
+//! Structured attributes on the current request span, so traces can be filtered by project, key, mime type etc.
+
+use std::{
+    cell::Cell,
+    future::IntoFuture,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, RawPathParams, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::{
+    KeyValue,
+    metrics::{Counter, Histogram, UpDownCounter},
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+use crate::config::Config;
+
+/// Data API requests currently being handled, those shed by `MAX_CONCURRENT_REQUESTS` aren't counted
+static ACTIVE_REQUESTS: LazyLock<UpDownCounter<i64>> = LazyLock::new(|| {
+    logfire::i64_up_down_counter("http.server.active_requests")
+        .with_description("Data API requests currently in flight")
+        .with_unit("{request}")
+        .build()
+});
+
+pub async fn count_active_requests(request: Request, next: Next) -> Response {
+    /// Decrements on drop, so requests cancelled mid-flight (e.g. on timeout) are still uncounted
+    struct Active;
+    impl Drop for Active {
+        fn drop(&mut self) {
+            ACTIVE_REQUESTS.add(-1, &[]);
+        }
+    }
+
+    ACTIVE_REQUESTS.add(1, &[]);
+    let _active = Active;
+    next.run(request).await
+}
+
+/// Sizes of entries stored and served, bucketed in powers of four from 64 bytes to 256 MiB
+static ENTRY_SIZE: LazyLock<Histogram<u64>> = LazyLock::new(|| {
+    logfire::u64_histogram("forgettable.entry.size")
+        .with_description("Size of entry content stored or served")
+        .with_unit("By")
+        .with_boundaries((3..=14).map(|exp| 4_f64.powi(exp)).collect())
+        .build()
+});
+
+/// Whether an entry's content was written or read, the `operation` attribute of `forgettable.entry.size`
+#[derive(Debug, Clone, Copy)]
+pub enum SizeOperation {
+    Store,
+    Get,
+}
+
+pub fn record_entry_size(operation: SizeOperation, size: usize) {
+    let operation = match operation {
+        SizeOperation::Store => "store",
+        SizeOperation::Get => "get",
+    };
+    ENTRY_SIZE.record(size as u64, &[KeyValue::new("operation", operation)]);
+}
+
+/// Writes which stored a new key, and those which overwrote an existing entry, by project, so how much of the
+/// write load is churn of existing keys can be told apart from growth
+static ENTRIES_CREATED: LazyLock<Counter<u64>> = LazyLock::new(|| {
+    logfire::u64_counter("forgettable.entries.created")
+        .with_description("Entries stored under a key which had no live entry")
+        .with_unit("{entry}")
+        .build()
+});
+
+static ENTRIES_UPDATED: LazyLock<Counter<u64>> = LazyLock::new(|| {
+    logfire::u64_counter("forgettable.entries.updated")
+        .with_description("Entries stored over an existing live entry")
+        .with_unit("{entry}")
+        .build()
+});
+
+/// Count a write as a new key or an overwrite, from whether `EntryRepository::put` reported an insert, which a
+/// store over an expired entry is
+pub fn record_entry_write(project: Uuid, inserted: bool) {
+    let counter = if inserted { &ENTRIES_CREATED } else { &ENTRIES_UPDATED };
+    counter.add(1, &[KeyValue::new("project_id", project.to_string())]);
+}
+
+pub fn record_key(project: Uuid, key: &str) {
+    let span = tracing::Span::current();
+    span.set_attribute("project_id", project.to_string());
+    span.set_attribute("key", key.to_string());
+}
+
+pub fn record_prefix(project: Uuid, prefix: &str) {
+    let span = tracing::Span::current();
+    span.set_attribute("project_id", project.to_string());
+    span.set_attribute("prefix", prefix.to_string());
+}
+
+pub fn record_content(mime_type: &str, size: usize) {
+    let span = tracing::Span::current();
+    span.set_attribute("mime_type", mime_type.to_string());
+    span.set_attribute("size", size as i64);
+}
+
+pub fn record_namespace(namespace: &str) {
+    tracing::Span::current().set_attribute("namespace", namespace.to_string());
+}
+
+/// Time spent in SQL queries by a handler, separate from the total request time, so slow requests can be
+/// attributed to Postgres or not
+#[derive(Debug, Default)]
+pub struct DbTimer {
+    elapsed: Duration,
+}
+
+impl DbTimer {
+    /// Await a query, adding its duration to the total which is recorded as `db_ms` on the request span
+    pub async fn time<F: IntoFuture>(&mut self, query: F) -> F::Output {
+        let start = Instant::now();
+        let output = query.await;
+        let elapsed = start.elapsed();
+        self.elapsed += elapsed;
+        // outside `log_slow_queries`, e.g. in the task streaming a list, there's nothing to add to
+        let _ = REQUEST_DB_TIME.try_with(|total| total.set(total.get() + elapsed));
+        tracing::Span::current().set_attribute("db_ms", self.ms());
+        output
+    }
+
+    /// Total milliseconds so far, for handler log lines
+    pub fn ms(&self) -> f64 {
+        self.elapsed.as_secs_f64() * 1000.0
+    }
+}
+
+tokio::task_local! {
+    /// SQL time of the whole request, summed over every `DbTimer` its handler uses
+    static REQUEST_DB_TIME: Cell<Duration>;
+}
+
+/// Warn about requests whose SQL queries took longer than `SLOW_QUERY_MS` in total, with the route and the
+/// project and key (or prefix) from the path; requests under the threshold log nothing extra
+pub async fn log_slow_queries(
+    State(config): State<Arc<Config>>,
+    route: Option<MatchedPath>,
+    params: RawPathParams,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(threshold) = config.slow_query else {
+        return next.run(request).await;
+    };
+    let (response, db_time) = REQUEST_DB_TIME
+        .scope(Cell::new(Duration::ZERO), async {
+            let response = next.run(request).await;
+            (response, REQUEST_DB_TIME.with(Cell::get))
+        })
+        .await;
+    if db_time > threshold {
+        let param = |name: &str| params.iter().find(|(key, _)| *key == name).map(|(_, v)| v.to_string());
+        logfire::warn!(
+            "slow queries route={route} project={project:?} key={key:?} db_ms={db_ms}",
+            route = route.as_ref().map_or("", MatchedPath::as_str).to_string(),
+            project = param("project"),
+            key = param("key").or_else(|| param("prefix")),
+            db_ms = db_time.as_secs_f64() * 1000.0,
+        );
+    }
+    response
+}
@@ -0,0 +1,193 @@
+//! With `TENANT_SCHEMAS`, each project's entries live in their own Postgres schema rather than the shared
+//! `public.entries` table.
+//!
+//! Queries are written against unqualified `entries`, [`connection`] selects the table per request by pointing
+//! the connection's `search_path` at the project's schema, with `public` after it for `projects`, `audit_log` and
+//! the trigger functions. The pool resets `search_path` when connections are released.
+
+use std::{collections::BTreeSet, sync::Mutex};
+
+use sqlx::{Connection, PgConnection, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+    state::Pool,
+};
+
+pub type PoolConnection = sqlx_tracing::PoolConnection<Postgres>;
+
+/// Whether the request may create the project's schema, only stores do, so reads (or deletes) of unknown
+/// projects can't be used to create schemas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Existing,
+    Create,
+}
+
+/// Projects whose schema this process has already created, so the DDL only runs on a project's first write
+static CREATED_SCHEMAS: Mutex<BTreeSet<Uuid>> = Mutex::new(BTreeSet::new());
+
+/// Connection on which unqualified `entries` is the project's table, a plain pool connection unless
+/// `TENANT_SCHEMAS` is set.
+///
+/// Reads of a project whose schema doesn't exist yet fall through to `public.entries`, the project's first store
+/// then moves entries stored before `TENANT_SCHEMAS` was enabled into the new schema.
+pub async fn connection(pool: &Pool, config: &Config, project: Uuid, access: Access) -> Result<PoolConnection> {
+    let mut conn = pool.acquire().await?;
+    if !config.tenant_schemas {
+        return Ok(conn);
+    }
+
+    let schema = schema_name(project);
+    if access == Access::Create && !is_created(project) {
+        // an unknown project's store is about to be refused, it mustn't leave a schema behind
+        if !config.auto_create_projects {
+            let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM projects WHERE id = $1)")
+                .bind(project)
+                .fetch_one(&mut conn)
+                .await?;
+            if !exists {
+                return Err(AppError::ProjectNotFound(project));
+            }
+        }
+        create_schema(&mut conn, project, &schema).await?;
+        CREATED_SCHEMAS.lock().expect("schema cache poisoned").insert(project);
+    }
+    sqlx::query(&format!("SET search_path TO {schema}, public"))
+        .persistent(false)
+        .execute(&mut conn)
+        .await?;
+    Ok(conn)
+}
+
+/// Every entries table to read for a query across projects: `public.entries`, and with `TENANT_SCHEMAS` each
+/// project schema's, for building a `UNION ALL` since they can't be a query parameter
+pub async fn entries_tables(conn: &mut PgConnection, config: &Config) -> Result<Vec<String>> {
+    let mut tables = vec!["public.entries".to_string()];
+    if config.tenant_schemas {
+        // only names `schema_name` could have made, so they're safe to interpolate
+        let schemas: Vec<String> = sqlx::query_scalar(
+            r#"
+        SELECT nspname::text FROM pg_namespace JOIN pg_class ON pg_class.relnamespace = pg_namespace.oid
+        WHERE relname = 'entries' AND relkind = 'r' AND nspname ~ '^project_[0-9a-f]{32}$'
+        ORDER BY nspname
+        "#,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        tables.extend(schemas.into_iter().map(|schema| format!("{schema}.entries")));
+    }
+    Ok(tables)
+}
+
+/// Safe to interpolate into SQL, since it's only ever `project_` and hex digits
+pub fn schema_name(project: Uuid) -> String {
+    format!("project_{}", project.simple())
+}
+
+fn is_created(project: Uuid) -> bool {
+    CREATED_SCHEMAS
+        .lock()
+        .expect("schema cache poisoned")
+        .contains(&project)
+}
+
+/// Create the project's schema and entries table, modelled on `public.entries`, and move the project's entries
+/// (with their variants) out of the shared table into it, in one transaction so no read sees them in neither or
+/// both. The advisory lock stops concurrent first writes (from any instance) racing on `CREATE SCHEMA IF NOT EXISTS`.
+async fn create_schema(conn: &mut PoolConnection, project: Uuid, schema: &str) -> Result<()> {
+    let mut tx = conn.as_mut().begin().await?;
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(schema)
+        .execute(&mut *tx)
+        .await?;
+    let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+        .bind(format!("{schema}.entries"))
+        .fetch_one(&mut *tx)
+        .await?;
+    if exists {
+        return Ok(());
+    }
+    execute_ddl(
+        &mut tx,
+        [
+            format!("CREATE SCHEMA IF NOT EXISTS {schema}"),
+            format!("CREATE TABLE {schema}.entries (LIKE public.entries INCLUDING ALL)"),
+            // `LIKE` doesn't copy foreign keys, so the variants table is spelt out to reference the schema's entries
+            format!(
+                "CREATE TABLE {schema}.entry_variants (\
+                 entry_id UUID NOT NULL REFERENCES {schema}.entries(id) ON DELETE CASCADE, \
+                 mime_type TEXT NOT NULL, content BYTEA NOT NULL, updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(), \
+                 PRIMARY KEY (entry_id, mime_type))"
+            ),
+        ],
+    )
+    .await?;
+
+    // copied before the schema's triggers exist, the entries and variants aren't changing so aren't audited
+    sqlx::query(&format!(
+        "INSERT INTO {schema}.entries SELECT * FROM public.entries WHERE project_id = $1"
+    ))
+    .persistent(false)
+    .bind(project)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(&format!(
+        "INSERT INTO {schema}.entry_variants (entry_id, mime_type, content, updated_at) \
+         SELECT entry_id, variants.mime_type, variants.content, variants.updated_at \
+         FROM public.entry_variants variants JOIN public.entries ON entries.id = variants.entry_id \
+         WHERE entries.project_id = $1"
+    ))
+    .persistent(false)
+    .bind(project)
+    .execute(&mut *tx)
+    .await?;
+    // variants go with their entries, `forgettable.moving` stops `audit_entry` recording the entries as deleted
+    sqlx::query("SELECT set_config('forgettable.moving', 'on', true)")
+        .execute(&mut *tx)
+        .await?;
+    let moved = sqlx::query("DELETE FROM public.entries WHERE project_id = $1")
+        .bind(project)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    sqlx::query("SELECT set_config('forgettable.moving', '', true)")
+        .execute(&mut *tx)
+        .await?;
+
+    execute_ddl(
+        &mut tx,
+        [
+            format!(
+                "CREATE TRIGGER entries_touch_project AFTER INSERT OR UPDATE OR DELETE ON {schema}.entries \
+                 FOR EACH ROW EXECUTE FUNCTION public.touch_project()"
+            ),
+            format!(
+                "CREATE TRIGGER entries_audit AFTER INSERT OR UPDATE OR DELETE ON {schema}.entries \
+                 FOR EACH ROW EXECUTE FUNCTION public.audit_entry()"
+            ),
+            format!(
+                "CREATE TRIGGER entry_variants_audit AFTER INSERT OR UPDATE OR DELETE ON {schema}.entry_variants \
+                 FOR EACH ROW EXECUTE FUNCTION public.audit_variant()"
+            ),
+        ],
+    )
+    .await?;
+    tx.commit().await?;
+    logfire::info!(
+        "created tenant schema {schema} moved={moved}",
+        schema = schema.to_string(),
+        moved = moved,
+    );
+    Ok(())
+}
+
+/// DDL can't take parameters, so isn't prepared
+async fn execute_ddl(tx: &mut Transaction<'_, Postgres>, statements: impl IntoIterator<Item = String>) -> Result<()> {
+    for statement in statements {
+        sqlx::query(&statement).persistent(false).execute(&mut **tx).await?;
+    }
+    Ok(())
+}
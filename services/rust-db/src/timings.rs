@@ -0,0 +1,123 @@
+//! In-memory request latencies per route since startup, for diagnosing a deployment without a metrics stack.
+//!
+//! Each route keeps a histogram with buckets a quarter power of two apart, so percentiles are within 19% of the
+//! true value while recording stays a lock and an increment.
+
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::models::RouteTiming;
+
+/// Buckets per doubling of latency
+const BUCKETS_PER_DOUBLING: f64 = 4.0;
+/// Enough buckets for 2^40µs (about 12 days), slower requests all land in the last one
+const BUCKETS: usize = 160;
+
+struct Histogram {
+    count: u64,
+    errors: u64,
+    max: Duration,
+    buckets: Vec<u64>,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            errors: 0,
+            max: Duration::ZERO,
+            buckets: vec![0; BUCKETS],
+        }
+    }
+
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn record(&mut self, elapsed: Duration, error: bool) {
+        let micros = elapsed.as_micros().max(1) as f64;
+        let index = ((micros.log2() * BUCKETS_PER_DOUBLING) as usize).min(BUCKETS - 1);
+        self.buckets[index] += 1;
+        self.count += 1;
+        self.errors += u64::from(error);
+        self.max = self.max.max(elapsed);
+    }
+
+    /// Upper bound of the bucket holding the `quantile` request, capped at the slowest request seen
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn quantile_ms(&self, quantile: f64) -> f64 {
+        let rank = ((self.count as f64 * quantile).ceil() as u64).max(1);
+        let mut seen = 0;
+        let index = self
+            .buckets
+            .iter()
+            .position(|count| {
+                seen += count;
+                seen >= rank
+            })
+            .unwrap_or(BUCKETS - 1);
+        let upper_micros = ((index + 1) as f64 / BUCKETS_PER_DOUBLING).exp2();
+        (upper_micros / 1000.0).min(ms(self.max))
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Histograms by route and method
+static TIMINGS: Mutex<BTreeMap<(String, String), Histogram>> = Mutex::new(BTreeMap::new());
+
+/// Time the request against its matched route, `5xx` responses count as errors
+pub async fn record_timing(route: Option<MatchedPath>, request: Request, next: Next) -> Response {
+    let Some(route) = route else {
+        return next.run(request).await;
+    };
+    let method = request.method().to_string();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    TIMINGS
+        .lock()
+        .expect("timings poisoned")
+        .entry((route.as_str().to_string(), method))
+        .or_insert_with(Histogram::new)
+        .record(start.elapsed(), response.status().is_server_error());
+    response
+}
+
+/// Every route requested since startup, slowest `p99_ms` first
+#[allow(clippy::cast_precision_loss)]
+pub fn summary() -> Vec<RouteTiming> {
+    let mut timings: Vec<RouteTiming> = TIMINGS
+        .lock()
+        .expect("timings poisoned")
+        .iter()
+        .map(|((route, method), histogram)| RouteTiming {
+            method: method.clone(),
+            route: route.clone(),
+            count: histogram.count,
+            errors: histogram.errors,
+            error_rate: histogram.errors as f64 / histogram.count as f64,
+            p50_ms: histogram.quantile_ms(0.5),
+            p95_ms: histogram.quantile_ms(0.95),
+            p99_ms: histogram.quantile_ms(0.99),
+            max_ms: ms(histogram.max),
+        })
+        .collect();
+    timings.sort_by(|a, b| b.p99_ms.total_cmp(&a.p99_ms));
+    timings
+}
@@ -0,0 +1,82 @@
+//! `TRACE_SAMPLE_RATIO`: trace only a fraction of requests, so tracing costs less under heavy load.
+//!
+//! logfire builds the tracer provider itself, with the SDK's default `ParentBased(AlwaysOn)` sampler, so instead
+//! of replacing the sampler the decision is made here, before `OtelAxumLayer` starts the request's span: a request
+//! which isn't sampled gets a `traceparent` with the sampled flag cleared, and the parent based sampler drops its
+//! spans. Requests which already carry a `traceparent` keep the caller's decision, so distributed traces stay
+//! whole.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::{SpanId, TraceContextExt, TraceId},
+};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::config::Config;
+
+const TRACEPARENT: &str = "traceparent";
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(axum::http::HeaderName::as_str).collect()
+    }
+}
+
+/// Decide whether a request without a propagated sampling decision is traced, server errors in requests which
+/// aren't traced are still logged
+pub async fn sample_trace(State(config): State<Arc<Config>>, mut request: Request, next: Next) -> Response {
+    let Some(ratio) = config.trace_sample_ratio else {
+        return next.run(request).await;
+    };
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(request.headers())));
+    let parent = parent.span().span_context().clone();
+    let sampled = if parent.is_valid() {
+        parent.is_sampled()
+    } else {
+        let trace_id = Uuid::new_v4().as_u128();
+        // the low 62 bits of a v4 uuid are random
+        let random = trace_id as u64 & ((1 << 62) - 1);
+        let sampled = random < (ratio * (1u64 << 62) as f64) as u64;
+        if !sampled {
+            let span_id = (Uuid::new_v4().as_u128() as u64).max(1);
+            let traceparent = format!("00-{}-{}-00", TraceId::from(trace_id), SpanId::from(span_id));
+            let value = HeaderValue::from_str(&traceparent).expect("traceparent is a valid header value");
+            request.headers_mut().insert(TRACEPARENT, value);
+        }
+        sampled
+    };
+    if sampled {
+        return next.run(request).await;
+    }
+
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    if response.status().is_server_error() {
+        logfire::error!(
+            "untraced request failed {method} {path} status={status} duration_ms={duration_ms}",
+            method = method,
+            path = path,
+            status = response.status().as_u16(),
+            duration_ms = start.elapsed().as_secs_f64() * 1000.0,
+        );
+    }
+    response
+}
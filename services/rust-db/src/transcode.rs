@@ -0,0 +1,80 @@
+//! `?as=json` on get: CSV, YAML and TOML entries converted to JSON in the response, the stored bytes are
+//! unchanged.
+
+use serde_json::{Map, Value};
+
+use crate::{
+    config::{is_json_mime, mime_essence},
+    error::{AppError, Result},
+};
+
+/// Convert `content` of `mime_type` to JSON, JSON entries are returned as they are.
+///
+/// CSV becomes an array with an object per row keyed by the header row, values are all strings since CSV doesn't
+/// say what type they are.
+pub fn to_json(mime_type: &str, content: &[u8]) -> Result<Vec<u8>> {
+    if is_json_mime(mime_type) {
+        return Ok(content.to_vec());
+    }
+    let essence = mime_essence(mime_type);
+    let value = match essence.as_str() {
+        "text/csv" => csv_to_json(content).map_err(|e| invalid("CSV", &e))?,
+        "application/yaml" | "application/x-yaml" | "text/yaml" | "text/x-yaml" => {
+            serde_yaml::from_slice(content).map_err(|e| invalid("YAML", &e))?
+        }
+        "application/toml" | "text/toml" | "text/x-toml" => {
+            let text = str::from_utf8(content).map_err(|e| invalid("TOML", &e))?;
+            let table: toml::Table = toml::from_str(text).map_err(|e| invalid("TOML", &e))?;
+            toml_to_json(toml::Value::Table(table))
+        }
+        _ => {
+            return Err(AppError::UnsupportedMediaType(format!(
+                "{essence} can't be converted to JSON, only CSV, YAML and TOML can"
+            )));
+        }
+    };
+    Ok(serde_json::to_vec(&value).expect("a JSON value serializes"))
+}
+
+fn invalid(format: &str, error: &dyn std::fmt::Display) -> AppError {
+    AppError::UnsupportedMediaType(format!(
+        "entry isn't valid {format}, so can't be converted to JSON: {error}"
+    ))
+}
+
+fn csv_to_json(content: &[u8]) -> csv::Result<Value> {
+    let mut reader = csv::Reader::from_reader(content);
+    let headers = reader.headers()?.clone();
+    let rows = reader
+        .records()
+        .map(|record| {
+            let record = record?;
+            let row: Map<String, Value> = headers
+                .iter()
+                .zip(record.iter())
+                .map(|(header, value)| (header.to_string(), Value::String(value.to_string())))
+                .collect();
+            Ok(Value::Object(row))
+        })
+        .collect::<csv::Result<_>>()?;
+    Ok(Value::Array(rows))
+}
+
+/// Like serializing the TOML value, except that datetimes become RFC 3339 strings rather than the TOML crate's
+/// private wrapper object
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(items) => Value::Array(items.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
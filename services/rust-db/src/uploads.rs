@@ -0,0 +1,46 @@
+//! Fairness between projects sharing an instance: with `MAX_PROJECT_UPLOADS`, stores to a project beyond that many
+//! in flight are rejected with `429` rather than queueing for database connections other projects need.
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+};
+
+/// Stores in flight per project, projects are removed once they have none
+static IN_FLIGHT: Mutex<BTreeMap<Uuid, usize>> = Mutex::new(BTreeMap::new());
+
+/// Held for the duration of a store, releases the project's slot on drop, including when the request is cancelled
+pub struct UploadPermit(Option<Uuid>);
+
+impl Drop for UploadPermit {
+    fn drop(&mut self) {
+        let Some(project) = self.0 else {
+            return;
+        };
+        let mut in_flight = IN_FLIGHT.lock().expect("upload counts poisoned");
+        if let Some(count) = in_flight.get_mut(&project) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&project);
+            }
+        }
+    }
+}
+
+/// Take one of the project's upload slots, or fail straight away if they're all in use
+pub fn acquire(config: &Config, project: Uuid) -> Result<UploadPermit> {
+    let Some(max) = config.max_project_uploads else {
+        return Ok(UploadPermit(None));
+    };
+    let mut in_flight = IN_FLIGHT.lock().expect("upload counts poisoned");
+    let count = in_flight.entry(project).or_default();
+    if *count >= max {
+        return Err(AppError::ProjectBusy(project));
+    }
+    *count += 1;
+    Ok(UploadPermit(Some(project)))
+}
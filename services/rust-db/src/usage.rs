@@ -0,0 +1,123 @@
+//! Per-project request counts for quotas and billing, much coarser (and cheaper) than the audit log.
+//!
+//! Requests increment in-memory counters for the current hour, which are periodically added to `project_usage`,
+//! so a counter update never waits for the database. Counts not yet flushed are lost if the process is killed.
+
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use axum::{
+    extract::{RawPathParams, Request},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use uuid::Uuid;
+
+use crate::state::Pool;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Counts {
+    pub reads: i64,
+    pub writes: i64,
+    pub deletes: i64,
+}
+
+impl Counts {
+    fn add(&mut self, other: Self) {
+        self.reads += other.reads;
+        self.writes += other.writes;
+        self.deletes += other.deletes;
+    }
+}
+
+/// Counts since the last flush, by project and hour
+static PENDING: Mutex<BTreeMap<(Uuid, DateTime<Utc>), Counts>> = Mutex::new(BTreeMap::new());
+
+/// Start of the hour `at` falls in
+pub fn bucket(at: DateTime<Utc>) -> DateTime<Utc> {
+    at.duration_trunc(TimeDelta::hours(1)).expect("an hour always fits")
+}
+
+/// Count the request against the project in its path, `GET`s are reads, `POST`s and `PUT`s writes; requests to
+/// routes without a project (or with an invalid one) aren't counted
+pub async fn count_usage(params: RawPathParams, request: Request, next: Next) -> Response {
+    let project = params
+        .iter()
+        .find(|(name, _)| *name == "project")
+        .and_then(|(_, project)| project.parse::<Uuid>().ok());
+    let mut counts = Counts::default();
+    match *request.method() {
+        Method::GET | Method::HEAD => counts.reads = 1,
+        Method::POST | Method::PUT | Method::PATCH => counts.writes = 1,
+        Method::DELETE => counts.deletes = 1,
+        _ => {}
+    }
+    if let Some(project) = project {
+        add_pending(project, bucket(Utc::now()), counts);
+    }
+    next.run(request).await
+}
+
+fn add_pending(project: Uuid, bucket: DateTime<Utc>, counts: Counts) {
+    PENDING
+        .lock()
+        .expect("usage counters poisoned")
+        .entry((project, bucket))
+        .or_default()
+        .add(counts);
+}
+
+/// This process's unflushed counts for a project since `since`
+pub fn pending(project: Uuid, since: DateTime<Utc>) -> Counts {
+    let mut total = Counts::default();
+    for (_, counts) in PENDING
+        .lock()
+        .expect("usage counters poisoned")
+        .range((project, since)..=(project, DateTime::<Utc>::MAX_UTC))
+    {
+        total.add(*counts);
+    }
+    total
+}
+
+/// Add the pending counts to `project_usage`, counts which fail to save are kept for the next flush
+pub async fn flush(pool: &Pool) {
+    let pending = std::mem::take(&mut *PENDING.lock().expect("usage counters poisoned"));
+    for ((project, bucket), counts) in pending {
+        let result = sqlx::query(
+            r#"
+        INSERT INTO project_usage (project_id, bucket, reads, writes, deletes) VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (project_id, bucket) DO UPDATE SET
+            reads = project_usage.reads + EXCLUDED.reads,
+            writes = project_usage.writes + EXCLUDED.writes,
+            deletes = project_usage.deletes + EXCLUDED.deletes
+        "#,
+        )
+        .bind(project)
+        .bind(bucket)
+        .bind(counts.reads)
+        .bind(counts.writes)
+        .bind(counts.deletes)
+        .execute(&**pool)
+        .await;
+        if let Err(e) = result {
+            logfire::warn!(
+                "failed to flush usage project={project}: {error}",
+                project = project.to_string(),
+                error = e.to_string(),
+            );
+            add_pending(project, bucket, counts);
+        }
+    }
+}
+
+/// Flush every `interval`, forever
+pub async fn flush_periodically(pool: Pool, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        flush(&pool).await;
+    }
+}
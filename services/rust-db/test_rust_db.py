@@ -1,7 +1,20 @@
 """Integration tests for the KV database service."""
 
+import base64
+import concurrent.futures
+import gzip
+import hashlib
+import io
+import json
+import socket
+import tarfile
+import time
+import urllib.parse
 import uuid
+import zlib
+from datetime import datetime
 
+import pytest
 import requests
 
 BASE_URL = 'http://localhost:3003'
@@ -214,6 +227,14 @@ def test_delete_nonexistent_key() -> None:
 
     assert response.status_code == 404
 
+    # idempotent deletes succeed whether or not the key exists
+    response = requests.delete(
+        f'{BASE_URL}/project/{project_id}/nonexistent-key',
+        params={'idempotent': 'true'},
+        timeout=10,
+    )
+    assert response.status_code == 204
+
 
 def test_auto_create_project() -> None:
     """Test that storing to a new project UUID auto-creates the project."""
@@ -410,3 +431,2305 @@ def test_project_isolation() -> None:
 
     assert response1.content == b'project1 content'
     assert response2.content == b'project2 content'
+
+
+def test_get_content_type_override() -> None:
+    """Test that ?content_type= overrides the response Content-Type without changing the stored entry."""
+    project_id = new_project_id()
+    key = 'page.html'
+
+    requests.post(
+        f'{BASE_URL}/project/{project_id}/{key}',
+        data=b'<h1>hello</h1>',
+        headers={'Content-Type': 'text/plain'},
+        timeout=10,
+    )
+
+    override_response = requests.get(
+        f'{BASE_URL}/project/{project_id}/get/{key}',
+        params={'content_type': 'text/html; charset=utf-8'},
+        timeout=10,
+    )
+    assert override_response.status_code == 200
+    assert override_response.headers['Content-Type'] == 'text/html; charset=utf-8'
+    assert override_response.content == b'<h1>hello</h1>'
+
+    # Stored mime type is unchanged
+    get_response = requests.get(
+        f'{BASE_URL}/project/{project_id}/get/{key}',
+        timeout=10,
+    )
+    assert get_response.headers['Content-Type'] == 'text/plain'
+
+
+def test_get_invalid_content_type_override() -> None:
+    """Test that an invalid ?content_type= returns 400."""
+    project_id = new_project_id()
+    key = 'some-key'
+
+    requests.post(
+        f'{BASE_URL}/project/{project_id}/{key}',
+        data=b'content',
+        timeout=10,
+    )
+
+    response = requests.get(
+        f'{BASE_URL}/project/{project_id}/get/{key}',
+        params={'content_type': 'not a mime type'},
+        timeout=10,
+    )
+    assert response.status_code == 400
+    assert 'error' in response.json()
+
+
+def test_get_as_json() -> None:
+    """Test that ?as=json converts CSV, YAML and TOML entries to JSON without changing the stored entry."""
+    project_id = new_project_id()
+    base = f'{BASE_URL}/project/{project_id}'
+    for key, mime_type, content in [
+        ('rows.csv', 'text/csv', b'name,size\nsmall,1\nbig,10\n'),
+        ('config.yaml', 'application/yaml', b'name: demo\nsizes: [1, 2]\n'),
+        ('config.toml', 'application/toml', b'name = "demo"\n[build]\nat = 1979-05-27T07:32:00Z\n'),
+        ('page.html', 'text/html', b'<h1>hello</h1>'),
+        ('broken.yaml', 'application/yaml', b'name: [unclosed'),
+    ]:
+        requests.post(f'{base}/{key}', data=content, headers={'Content-Type': mime_type}, timeout=10)
+
+    for key, expected in [
+        ('rows.csv', [{'name': 'small', 'size': '1'}, {'name': 'big', 'size': '10'}]),
+        ('config.yaml', {'name': 'demo', 'sizes': [1, 2]}),
+        ('config.toml', {'name': 'demo', 'build': {'at': '1979-05-27T07:32:00Z'}}),
+    ]:
+        response = requests.get(f'{base}/get/{key}', params={'as': 'json'}, timeout=10)
+        assert response.status_code == 200
+        assert response.headers['Content-Type'] == 'application/json'
+        assert response.json() == expected
+
+    stored = requests.get(f'{base}/get/rows.csv', timeout=10)
+    assert stored.headers['Content-Type'] == 'text/csv'
+    assert stored.content == b'name,size\nsmall,1\nbig,10\n'
+    converted = requests.get(f'{base}/get/rows.csv', params={'as': 'json'}, timeout=10)
+    assert converted.headers['ETag'] != stored.headers['ETag']
+    response = requests.get(
+        f'{base}/get/rows.csv', params={'as': 'json'}, headers={'If-None-Match': converted.headers['ETag']}, timeout=10
+    )
+    assert response.status_code == 304
+
+    assert requests.get(f'{base}/get/page.html', params={'as': 'json'}, timeout=10).status_code == 415
+    assert requests.get(f'{base}/get/broken.yaml', params={'as': 'json'}, timeout=10).status_code == 415
+    assert requests.get(f'{base}/get/rows.csv', params={'as': 'xml'}, timeout=10).status_code == 400
+    params = {'as': 'json', 'content_type': 'text/plain'}
+    assert requests.get(f'{base}/get/rows.csv', params=params, timeout=10).status_code == 400
+
+
+def test_range_requests() -> None:
+    """Test single byte ranges, and If-Range with both an ETag and a Last-Modified date."""
+    project_id = new_project_id()
+    url = f'{BASE_URL}/project/{project_id}/get/file.bin'
+    requests.post(f'{BASE_URL}/project/{project_id}/file.bin', data=b'0123456789', timeout=10)
+
+    response = requests.get(url, timeout=10)
+    assert response.headers['Accept-Ranges'] == 'bytes'
+    etag, last_modified = response.headers['ETag'], response.headers['Last-Modified']
+
+    for range_, status, content, content_range in [
+        ('bytes=2-4', 206, b'234', 'bytes 2-4/10'),
+        ('bytes=7-', 206, b'789', 'bytes 7-9/10'),
+        ('bytes=-2', 206, b'89', 'bytes 8-9/10'),
+        ('bytes=8-100', 206, b'89', 'bytes 8-9/10'),
+        ('bytes=10-', 416, b'', 'bytes */10'),
+        ('bytes=0-1,4-5', 200, b'0123456789', None),
+        ('lines=1-2', 200, b'0123456789', None),
+    ]:
+        response = requests.get(url, headers={'Range': range_}, timeout=10)
+        assert (response.status_code, response.content) == (status, content), range_
+        assert response.headers.get('Content-Range') == content_range
+
+    def get_if_range(if_range: str) -> requests.Response:
+        return requests.get(url, headers={'Range': 'bytes=0-3', 'If-Range': if_range}, timeout=10)
+
+    assert get_if_range(etag).status_code == 206
+    assert get_if_range(last_modified).status_code == 206
+    assert get_if_range(f'W/{etag}').status_code == 200
+    assert get_if_range('Mon, 01 Jan 2001 00:00:00 GMT').status_code == 200
+
+    time.sleep(1.1)
+    requests.post(f'{BASE_URL}/project/{project_id}/file.bin', data=b'abcdefghij', timeout=10)
+    for if_range in [etag, last_modified]:
+        response = get_if_range(if_range)
+        assert (response.status_code, response.content) == (200, b'abcdefghij')
+
+
+def test_health() -> None:
+    """Test the health check, served on the main port when no ADMIN_PORT is configured."""
+    response = requests.get(f'{BASE_URL}/health', timeout=10)
+    assert response.status_code == 200
+    assert response.text == 'OK'
+
+
+def test_trailing_slash() -> None:
+    """Test that GET routes tolerate a missing or extra trailing slash, except where it's part of a key or prefix."""
+    project_id = new_project_id()
+    for key in ['dir/a.txt', 'dir/', 'dir']:
+        response = requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=key.encode(), timeout=10)
+        assert response.status_code == 201
+    response = requests.post(f'{BASE_URL}/project/{project_id}/ns/prod/x', data=b'x', timeout=10)
+    assert response.status_code == 201
+
+    assert requests.get(f'{BASE_URL}/health/', timeout=10).text == 'OK'
+    for path in ['list', 'list/']:
+        response = requests.get(f'{BASE_URL}/project/{project_id}/{path}', timeout=10)
+        assert response.status_code == 200
+        assert [entry['key'] for entry in response.json()] == ['dir', 'dir/', 'dir/a.txt']
+        response = requests.get(f'{BASE_URL}/project/{project_id}/ns/prod/{path}', params={'limit': 5}, timeout=10)
+        assert [entry['key'] for entry in response.json()] == ['x']
+    response = requests.get(f'{BASE_URL}/project/{project_id}/audit/', params={'limit': 1}, timeout=10)
+    assert response.status_code == 200
+    assert len(response.json()['events']) == 1
+
+    # keys and prefixes keep their slash
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/dir/', timeout=10)
+    assert response.content == b'dir/'
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/dir', timeout=10)
+    assert response.content == b'dir'
+    response = requests.get(f'{BASE_URL}/project/{project_id}/list/dir/', timeout=10)
+    assert [entry['key'] for entry in response.json()] == ['dir/', 'dir/a.txt']
+
+    # as do stores and deletes, `batch/` is a key rather than the batch route
+    response = requests.post(f'{BASE_URL}/project/{project_id}/batch/', data=b'b', timeout=10)
+    assert response.status_code == 201
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/batch/', timeout=10)
+    assert response.content == b'b'
+
+
+def test_ready() -> None:
+    """Test the readiness check passes once the schema is at the expected version."""
+    response = requests.get(f'{BASE_URL}/ready', timeout=10)
+    assert response.status_code == 200
+    assert response.text == 'OK'
+
+
+def test_list_changes() -> None:
+    """Test the changes feed reports stores, deletes and expiries since a time, in order and a page at a time."""
+    project_id = new_project_id()
+    base = f'{BASE_URL}/project/{project_id}'
+    requests.post(f'{base}/old.txt', data=b'old', timeout=10)
+    requests.post(f'{base}/gone.txt', data=b'gone', timeout=10)
+    since = requests.get(f'{base}/changes', params={'since': '2000-01-01T00:00:00Z'}, timeout=10).json()['now']
+
+    requests.post(f'{base}/a.txt', data=b'aa', timeout=10)
+    requests.delete(f'{base}/gone.txt', timeout=10)
+    requests.post(f'{base}/brief.txt', data=b'x', headers={'X-TTL-Seconds': '0'}, timeout=10)
+    requests.post(f'{base}/ns/dev/dev.txt', data=b'x', timeout=10)
+    # `changes` is still an ordinary key
+    assert requests.post(f'{base}/changes', data=b'c', timeout=10).status_code == 201
+
+    response = requests.get(f'{base}/changes', params={'since': since}, timeout=10)
+    assert response.status_code == 200
+    page = response.json()
+    assert page['next'] is None
+    assert page['now'] > since
+    changes = [(c['key'], c['deleted'], c['mime_type'], c['size']) for c in page['changes']]
+    assert changes == [
+        ('a.txt', False, 'application/octet-stream', 2),
+        ('gone.txt', True, None, None),
+        ('brief.txt', True, None, None),
+        ('changes', False, 'application/octet-stream', 1),
+    ]
+    updated = [c['updated_at'] for c in page['changes']]
+    assert updated == sorted(updated)
+
+    response = requests.get(f'{base}/changes', params={'since': page['now']}, timeout=10)
+    assert response.json()['changes'] == []
+
+    response = requests.get(f'{base}/changes', params={'since': since, 'namespace': 'dev'}, timeout=10)
+    assert [c['key'] for c in response.json()['changes']] == ['dev.txt']
+
+    keys = []
+    params = {'since': since, 'limit': 3}
+    while True:
+        page = requests.get(f'{base}/changes', params=params, timeout=10).json()
+        keys += [c['key'] for c in page['changes']]
+        if page['next'] is None:
+            break
+        params = {**page['next'], 'limit': 3}
+    assert keys == [key for key, *_ in changes]
+
+    assert requests.get(f'{base}/changes', timeout=10).status_code == 400
+
+
+def test_project_usage() -> None:
+    """Test that reads, writes and deletes are counted per project, not counting usage requests themselves."""
+    project_id = new_project_id()
+    # a store of the key `usage` counts as a write, only reading usage isn't counted
+    for key in ['a.txt', 'usage']:
+        requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=b'data', timeout=10)
+    for key in ['a.txt', 'usage', 'missing.txt']:
+        requests.get(f'{BASE_URL}/project/{project_id}/get/{key}', timeout=10)
+    requests.delete(f'{BASE_URL}/project/{project_id}/a.txt', timeout=10)
+    requests.get(f'{BASE_URL}/project/{new_project_id()}/get/a.txt', timeout=10)
+
+    for _ in range(2):
+        response = requests.get(f'{BASE_URL}/project/{project_id}/usage', timeout=10)
+        assert response.status_code == 200
+        usage = response.json()
+        assert (usage['hours'], usage['reads'], usage['writes'], usage['deletes']) == (24, 3, 2, 1)
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/usage', params={'hours': 0}, timeout=10)
+    assert response.status_code == 400
+
+
+def test_admin_timings() -> None:
+    """Test that the admin timings summary counts requests per route, slowest first."""
+    project_id = new_project_id()
+    before = {(t['method'], t['route']): t for t in requests.get(f'{BASE_URL}/admin/timings', timeout=10).json()}
+    for _ in range(3):
+        response = requests.get(f'{BASE_URL}/project/{project_id}/meta/missing.txt', timeout=10)
+        assert response.status_code == 404
+
+    response = requests.get(f'{BASE_URL}/admin/timings', timeout=10)
+    assert response.status_code == 200
+    timings = response.json()
+    assert [t['p99_ms'] for t in timings] == sorted((t['p99_ms'] for t in timings), reverse=True)
+    meta = next(t for t in timings if (t['method'], t['route']) == ('GET', '/project/{project}/meta/{*key}'))
+    # other tests may be running against the same server
+    previous = before.get(('GET', '/project/{project}/meta/{*key}'), {'count': 0})
+    assert meta['count'] >= previous['count'] + 3
+    assert 0 < meta['p50_ms'] <= meta['p95_ms'] <= meta['p99_ms'] <= meta['max_ms']
+    assert meta['error_rate'] == meta['errors'] / meta['count']
+
+
+def test_admin_list_projects() -> None:
+    """Test listing keys under a prefix in several projects at once, limited per project."""
+    projects = [new_project_id(), new_project_id(), new_project_id()]
+    for project_id, keys in zip(projects, [['a/1', 'a/2', 'a/3', 'b'], ['a/x'], []]):
+        for key in keys:
+            requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=b'xy', timeout=10)
+    requests.post(f'{BASE_URL}/project/{projects[1]}/ns/dev/a/dev', data=b'x', timeout=10)
+
+    request = {'projects': projects, 'prefix': 'a/', 'limit': 2}
+    response = requests.post(f'{BASE_URL}/admin/list', json=request, timeout=10)
+    assert response.status_code == 200
+    listed = response.json()
+    assert {project_id: [entry['key'] for entry in keys] for project_id, keys in listed.items()} == {
+        projects[0]: ['a/1', 'a/2'],
+        projects[1]: ['a/x'],
+        projects[2]: [],
+    }
+    assert listed[projects[1]] == [{'key': 'a/x', 'mime_type': 'application/octet-stream', 'size': 2}]
+
+    request = {'projects': projects[1:2], 'namespace': 'dev'}
+    response = requests.post(f'{BASE_URL}/admin/list', json=request, timeout=10)
+    assert [entry['key'] for entry in response.json()[projects[1]]] == ['a/dev']
+
+    response = requests.post(f'{BASE_URL}/admin/list', json={'projects': projects, 'limit': 0}, timeout=10)
+    assert response.status_code == 400
+
+
+def test_admin_merge_projects() -> None:
+    """Test merging one project's entries into another, with each way of handling keys stored in both."""
+
+    def setup() -> tuple[str, str]:
+        source, dest = new_project_id(), new_project_id()
+        for project_id, key, content in [
+            (source, 'only-source', b'source'),
+            (source, 'both', b'source'),
+            (source, 'ns/dev/both', b'dev'),
+            (dest, 'both', b'dest'),
+            (dest, 'expired', b'dest'),
+            (source, 'expired', b'source'),
+        ]:
+            ttl = {'X-TTL-Seconds': '0'} if (project_id, key) == (dest, 'expired') else {}
+            requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=content, headers=ttl, timeout=10)
+        return source, dest
+
+    def merge(source: str, dest: str, **kwargs: str) -> requests.Response:
+        request = {'from_project': source, 'to_project': dest, **kwargs}
+        return requests.post(f'{BASE_URL}/admin/merge', json=request, timeout=10)
+
+    source, dest = setup()
+    response = merge(source, dest)
+    if response.status_code == 400 and 'TENANT_SCHEMAS' in response.text:
+        pytest.skip("entries are in per-project schemas which can't be merged, TENANT_SCHEMAS is set")
+    assert response.status_code == 409
+    assert requests.get(f'{BASE_URL}/project/{source}/get/only-source', timeout=10).status_code == 200
+
+    response = merge(source, dest, on_conflict='skip')
+    assert response.status_code == 200
+    assert response.json() == {'moved': 3, 'overwritten': 0, 'skipped': 1}
+    for project_id, key, content in [
+        (dest, 'only-source', b'source'),
+        (dest, 'both', b'dest'),
+        (dest, 'expired', b'source'),
+        (source, 'both', b'source'),
+    ]:
+        response = requests.get(f'{BASE_URL}/project/{project_id}/get/{key}', timeout=10)
+        assert (response.status_code, response.content) == (200, content)
+    response = requests.get(f'{BASE_URL}/project/{dest}/ns/dev/get/both', timeout=10)
+    assert (response.status_code, response.content) == (200, b'dev')
+    assert requests.get(f'{BASE_URL}/project/{source}/get/only-source', timeout=10).status_code == 404
+
+    source, dest = setup()
+    response = merge(source, dest, on_conflict='overwrite')
+    assert response.json() == {'moved': 4, 'overwritten': 1, 'skipped': 0}
+    response = requests.get(f'{BASE_URL}/project/{dest}/get/both', timeout=10)
+    assert response.content == b'source'
+    response = requests.get(f'{BASE_URL}/project/{source}/list/', timeout=10)
+    assert response.json() == []
+    # the move shows up in both projects' audit logs
+    events = requests.get(f'{BASE_URL}/project/{source}/audit', timeout=10).json()['events']
+    assert ('only-source', 'delete') in [(event['key'], event['op']) for event in events]
+    events = requests.get(f'{BASE_URL}/project/{dest}/audit', timeout=10).json()['events']
+    assert ('only-source', 'insert') in [(event['key'], event['op']) for event in events]
+
+    assert merge(source, source).status_code == 400
+    assert merge(new_project_id(), dest).status_code == 404
+    assert merge(source, dest, on_conflict='replace').status_code == 422
+
+
+def test_find_key() -> None:
+    """Test finding every project and namespace holding a key, a page at a time."""
+    key = f'find-me/{uuid.uuid4()}.txt'
+    project_ids = [new_project_id(), new_project_id()]
+    for project_id in project_ids:
+        response = requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=b'here', timeout=10)
+        assert response.status_code == 201
+    response = requests.post(f'{BASE_URL}/project/{project_ids[0]}/ns/prod/{key}', data=b'here', timeout=10)
+    assert response.status_code == 201
+    # similar keys and expired entries don't match
+    response = requests.post(f'{BASE_URL}/project/{project_ids[0]}/{key}.bak', data=b'here', timeout=10)
+    assert response.status_code == 201
+    other_project = new_project_id()
+    response = requests.post(
+        f'{BASE_URL}/project/{other_project}/{key}', data=b'gone', headers={'X-TTL-Seconds': '0'}, timeout=10
+    )
+    assert response.status_code == 201
+
+    matches = []
+    after = None
+    pages = 0
+    while True:
+        params = {'key': key, 'limit': 2} | ({'after': after} if after else {})
+        response = requests.get(f'{BASE_URL}/admin/find-key', params=params, timeout=10)
+        assert response.status_code == 200
+        matches += response.json()['matches']
+        pages += 1
+        after = response.json()['next']
+        if after is None:
+            break
+    assert pages == 2
+    assert sorted((m['project_id'], m['namespace']) for m in matches) == sorted(
+        [(project_ids[0], 'default'), (project_ids[0], 'prod'), (project_ids[1], 'default')]
+    )
+    assert all(m['key'] == key for m in matches)
+
+    response = requests.get(f'{BASE_URL}/admin/find-key', params={'key': key, 'limit': 0}, timeout=10)
+    assert response.status_code == 400
+
+
+def test_key_collisions() -> None:
+    """Test the admin report of keys colliding under case folding and slash normalization."""
+    project_id = new_project_id()
+    for key in ['Readme.md', 'README.md', 'docs//a.md', 'docs/a.md', 'other.md']:
+        response = requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=b'x', timeout=10)
+        assert response.status_code == 201
+
+    response = requests.get(f'{BASE_URL}/admin/collisions/{project_id}', timeout=10)
+    assert response.status_code == 200
+    data = response.json()
+    if not data['slash_normalization']:
+        pytest.skip('keys are normalized on store, NORMALIZE_KEYS is set')
+    assert data['case_folding'] == [
+        {'namespace': 'default', 'normalized': 'readme.md', 'keys': ['README.md', 'Readme.md']}
+    ]
+    assert data['slash_normalization'] == [
+        {'namespace': 'default', 'normalized': 'docs/a.md', 'keys': ['docs//a.md', 'docs/a.md']}
+    ]
+
+
+def test_maintenance() -> None:
+    """Test the admin maintenance endpoint reports how long each step took."""
+    response = requests.post(f'{BASE_URL}/admin/maintenance', timeout=60)
+    assert response.status_code == 200
+    data = response.json()
+    assert data['vacuum_ms'] >= 0
+    assert data['reindex_ms'] is None
+
+    response = requests.post(f'{BASE_URL}/admin/maintenance', params={'reindex': 'true'}, timeout=60)
+    assert response.status_code == 200
+    assert response.json()['reindex_ms'] >= 0
+
+
+def test_store_entry_json() -> None:
+    """Test storing an entry via the JSON endpoint with base64 content."""
+    project_id = new_project_id()
+    content = bytes(range(256))
+
+    store_response = requests.post(
+        f'{BASE_URL}/project/{project_id}/entry',
+        json={
+            'key': 'docs/data.bin',
+            'mime_type': 'application/x-custom',
+            'content_base64': base64.b64encode(content).decode(),
+        },
+        timeout=10,
+    )
+    assert store_response.status_code == 201
+
+    get_response = requests.get(
+        f'{BASE_URL}/project/{project_id}/get/docs/data.bin',
+        timeout=10,
+    )
+    assert get_response.status_code == 200
+    assert get_response.content == content
+    assert get_response.headers['Content-Type'] == 'application/x-custom'
+
+
+def test_store_entry_json_invalid_base64() -> None:
+    """Test that invalid base64 content is rejected with 400."""
+    project_id = new_project_id()
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/entry',
+        json={'key': 'bad', 'mime_type': 'text/plain', 'content_base64': 'not base64!'},
+        timeout=10,
+    )
+    assert response.status_code == 400
+    assert 'error' in response.json()
+
+
+def test_expire_prefix() -> None:
+    """Test setting a TTL on every entry under a prefix."""
+    project_id = new_project_id()
+
+    for key in ['tmp/a.txt', 'tmp/b/c.txt', 'keep.txt']:
+        requests.post(
+            f'{BASE_URL}/project/{project_id}/{key}',
+            data=b'content',
+            headers={'Content-Type': 'text/plain'},
+            timeout=10,
+        )
+
+    expire_response = requests.post(
+        f'{BASE_URL}/project/{project_id}/expire/tmp/',
+        headers={'X-TTL-Seconds': '0'},
+        timeout=10,
+    )
+    assert expire_response.status_code == 200
+    assert expire_response.json() == {'count': 2}
+
+    # Expired entries are no longer visible
+    get_response = requests.get(
+        f'{BASE_URL}/project/{project_id}/get/tmp/a.txt',
+        timeout=10,
+    )
+    assert get_response.status_code == 404
+
+    list_response = requests.get(
+        f'{BASE_URL}/project/{project_id}/list/',
+        timeout=10,
+    )
+    assert [entry['key'] for entry in list_response.json()] == ['keep.txt']
+
+
+def test_touch_all() -> None:
+    """Test resetting the TTL of every entry, optionally under a prefix or only those which already have one."""
+    project_id = new_project_id()
+
+    for key in ['cache/a', 'cache/b', 'other']:
+        requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=b'x', headers={'X-TTL-Seconds': '60'}, timeout=10)
+    requests.post(f'{BASE_URL}/project/{project_id}/forever', data=b'x', timeout=10)
+
+    touch_url = f'{BASE_URL}/project/{project_id}/touch-all'
+    response = requests.post(touch_url, params={'prefix': 'cache/'}, headers={'X-TTL-Seconds': '0'}, timeout=10)
+    assert response.status_code == 200
+    assert response.json() == {'count': 2}
+
+    list_response = requests.get(f'{BASE_URL}/project/{project_id}/list/', timeout=10)
+    assert [entry['key'] for entry in list_response.json()] == ['forever', 'other']
+
+    # entries without an expiry are left alone with `only_expiring`
+    params = {'only_expiring': 'true'}
+    response = requests.post(touch_url, params=params, headers={'X-TTL-Seconds': '3600'}, timeout=10)
+    assert response.json() == {'count': 1}
+    response = requests.get(f'{BASE_URL}/project/{project_id}/dump', timeout=10)
+    dump = [json.loads(line) for line in response.text.splitlines()]
+    forever = next(entry for entry in dump if entry['key'] == 'forever')
+    assert forever['expires_at'] is None
+
+    # and otherwise given the TTL too
+    response = requests.post(touch_url, headers={'X-TTL-Seconds': '0'}, timeout=10)
+    assert response.json() == {'count': 2}
+    assert requests.get(f'{BASE_URL}/project/{project_id}/list/', timeout=10).json() == []
+
+    response = requests.post(touch_url, timeout=10)
+    assert response.status_code == 400
+
+
+def test_expire_prefix_dry_run() -> None:
+    """Test that a dry run expire lists the keys it would expire without expiring them."""
+    project_id = new_project_id()
+    for key in ['tmp/a.txt', 'tmp/b/c.txt', 'keep.txt']:
+        requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=b'x', timeout=10)
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/expire/tmp/',
+        params={'dry_run': 'true'},
+        headers={'X-TTL-Seconds': '0'},
+        timeout=10,
+    )
+    assert response.status_code == 200
+    assert response.json() == {'count': 2, 'keys': ['tmp/a.txt', 'tmp/b/c.txt']}
+
+    list_response = requests.get(f'{BASE_URL}/project/{project_id}/list/', timeout=10)
+    assert len(list_response.json()) == 3
+
+
+def test_expire_prefix_requires_ttl() -> None:
+    """Test that bulk expire without a valid X-TTL-Seconds header returns 400."""
+    project_id = new_project_id()
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/expire/tmp/',
+        headers={'X-TTL-Seconds': 'soon'},
+        timeout=10,
+    )
+    assert response.status_code == 400
+
+
+def test_expire_empty_prefix() -> None:
+    """Test that bulk expire refuses an empty prefix rather than expiring the whole project."""
+    project_id = new_project_id()
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/expire/',
+        headers={'X-TTL-Seconds': '60'},
+        timeout=10,
+    )
+    assert response.status_code == 400
+
+
+def test_retag_prefix() -> None:
+    """Test changing the mime type of every mutable entry under a prefix without re-uploading them."""
+    project_id = new_project_id()
+    for key in ['import/a.json', 'import/b/c.json', 'other.json']:
+        requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=b'{}', timeout=10)
+    requests.post(
+        f'{BASE_URL}/project/{project_id}/import/locked.json', data=b'{}', headers={'X-Immutable': 'true'}, timeout=10
+    )
+
+    url = f'{BASE_URL}/project/{project_id}/retag/import/'
+    response = requests.post(url, params={'dry_run': 'true'}, json={'mime_type': 'application/json'}, timeout=10)
+    assert response.json() == {'count': 2, 'keys': ['import/a.json', 'import/b/c.json']}
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/import/a.json', timeout=10)
+    assert response.headers['Content-Type'] == 'application/octet-stream'
+
+    response = requests.post(url, json={'mime_type': 'application/json'}, headers={'X-Actor': 'fixer'}, timeout=10)
+    assert response.status_code == 200
+    assert response.json() == {'count': 2}
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/import/a.json', timeout=10)
+    assert response.headers['Content-Type'] == 'application/json'
+    assert response.content == b'{}'
+    meta = requests.get(f'{BASE_URL}/project/{project_id}/meta/import/b/c.json', timeout=10).json()
+    assert meta['updated_by'] == 'fixer'
+    for key in ['other.json', 'import/locked.json']:
+        meta = requests.get(f'{BASE_URL}/project/{project_id}/meta/{key}', timeout=10).json()
+        assert meta['mime_type'] == 'application/octet-stream'
+
+    response = requests.post(url, json={'mime_type': 'not a mime type'}, timeout=10)
+    assert response.status_code == 400
+    response = requests.post(f'{BASE_URL}/project/{project_id}/retag/', json={'mime_type': 'text/plain'}, timeout=10)
+    assert response.status_code == 400
+
+
+def test_signed_url() -> None:
+    """Test serving an entry via a signed URL, and rejecting tampered signatures."""
+    project_id = new_project_id()
+    key = 'shared/report.txt'
+
+    requests.post(
+        f'{BASE_URL}/project/{project_id}/{key}',
+        data=b'shared content',
+        headers={'Content-Type': 'text/plain'},
+        timeout=10,
+    )
+
+    sign_response = requests.get(
+        f'{BASE_URL}/project/{project_id}/sign/{key}',
+        params={'expires_in': '60'},
+        timeout=10,
+    )
+    if sign_response.status_code == 403:
+        pytest.skip('signed URLs are not enabled, SIGNING_KEY is not set')
+    assert sign_response.status_code == 200
+    url: str = sign_response.json()['url']
+
+    get_response = requests.get(f'{BASE_URL}{url}', timeout=10)
+    assert get_response.status_code == 200
+    assert get_response.content == b'shared content'
+    assert get_response.headers['Content-Type'] == 'text/plain'
+
+    # Extending the expiry invalidates the signature
+    expires: int = sign_response.json()['expires']
+    tampered_url = url.replace(f'expires={expires}', f'expires={expires + 3600}')
+    tampered_response = requests.get(f'{BASE_URL}{tampered_url}', timeout=10)
+    assert tampered_response.status_code == 403
+
+
+def test_entry_metadata() -> None:
+    """Test the JSON metadata view of an entry."""
+    project_id = new_project_id()
+    key = 'docs/readme.md'
+    content = b'# Hello'
+
+    requests.post(
+        f'{BASE_URL}/project/{project_id}/{key}',
+        data=content,
+        headers={'Content-Type': 'text/markdown'},
+        timeout=10,
+    )
+
+    response = requests.get(
+        f'{BASE_URL}/project/{project_id}/meta/{key}',
+        timeout=10,
+    )
+    assert response.status_code == 200
+    meta: dict[str, str | int] = response.json()
+    sha256 = hashlib.sha256(content).hexdigest()
+    assert meta['key'] == key
+    assert meta['mime_type'] == 'text/markdown'
+    assert meta['size'] == len(content)
+    assert meta['sha256'] == sha256
+    assert meta['etag'] == f'"{sha256}"'
+    assert meta['created_at']
+    assert meta['updated_at']
+
+    missing_response = requests.get(
+        f'{BASE_URL}/project/{project_id}/meta/missing.md',
+        timeout=10,
+    )
+    assert missing_response.status_code == 404
+
+    # keys under `meta/` are stored and deleted as usual
+    response = requests.post(f'{BASE_URL}/project/{project_id}/meta/notes.txt', data=b'notes', timeout=10)
+    assert response.status_code == 201
+    response = requests.get(f'{BASE_URL}/project/{project_id}/meta/meta/notes.txt', timeout=10)
+    assert (response.json()['key'], response.json()['size']) == ('meta/notes.txt', 5)
+    response = requests.delete(f'{BASE_URL}/project/{project_id}/meta/notes.txt', timeout=10)
+    assert response.status_code == 204
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/meta/notes.txt', timeout=10).status_code == 404
+
+
+def test_client_timestamps() -> None:
+    """Test that with CLIENT_TIMESTAMPS imports can keep entries' original X-Created-At and X-Updated-At."""
+    project_id = new_project_id()
+    url = f'{BASE_URL}/project/{project_id}/imported.txt'
+    timestamps = {'X-Created-At': '2020-01-02T03:04:05Z', 'X-Updated-At': '2021-06-07T08:09:10+01:00'}
+
+    response = requests.post(url, data=b'old', headers=timestamps, timeout=10)
+    if response.status_code == 403:
+        assert response.json() == {
+            'error': 'Forbidden: X-Created-At and X-Updated-At are only accepted with CLIENT_TIMESTAMPS'
+        }
+        pytest.skip('client timestamps are not accepted, CLIENT_TIMESTAMPS is not set')
+    assert response.status_code == 201
+    meta = requests.get(f'{BASE_URL}/project/{project_id}/meta/imported.txt', timeout=10).json()
+    assert meta['created_at'] == '2020-01-02T03:04:05Z'
+    assert meta['updated_at'] == '2021-06-07T07:09:10Z'
+
+    # a later store without them keeps the original created_at
+    response = requests.post(url, data=b'new', timeout=10)
+    assert response.status_code == 200
+    meta = requests.get(f'{BASE_URL}/project/{project_id}/meta/imported.txt', timeout=10).json()
+    assert meta['created_at'] == '2020-01-02T03:04:05Z'
+    assert meta['updated_at'] > '2025'
+
+    response = requests.post(url, data=b'x', headers={'X-Created-At': 'yesterday'}, timeout=10)
+    assert response.status_code == 400
+    backwards = {'X-Created-At': '2022-01-01T00:00:00Z', 'X-Updated-At': '2021-01-01T00:00:00Z'}
+    response = requests.post(url, data=b'x', headers=backwards, timeout=10)
+    assert response.status_code == 400
+
+
+def test_entry_actor() -> None:
+    """Test that `X-Actor` is recorded as `created_by` on insert and `updated_by` on every store."""
+    project_id = new_project_id()
+    url = f'{BASE_URL}/project/{project_id}/notes.txt'
+    meta_url = f'{BASE_URL}/project/{project_id}/meta/notes.txt'
+
+    response = requests.post(url, data=b'v1', headers={'X-Actor': ' alice '}, timeout=10)
+    assert response.status_code == 201
+    meta = requests.get(meta_url, timeout=10).json()
+    assert meta['created_by'] == 'alice'
+    assert meta['updated_by'] == 'alice'
+
+    response = requests.post(url, data=b'v2', headers={'X-Actor': 'bob'}, timeout=10)
+    assert response.status_code == 200
+    meta = requests.get(meta_url, timeout=10).json()
+    assert meta['created_by'] == 'alice'
+    assert meta['updated_by'] == 'bob'
+
+    requests.post(url, data=b'v3', timeout=10)
+    meta = requests.get(meta_url, timeout=10).json()
+    assert meta['created_by'] == 'alice'
+    assert meta['updated_by'] is None
+
+    other_url = f'{BASE_URL}/project/{project_id}/other.txt'
+    requests.post(other_url, data=b'x', headers={'X-Actor': ''}, timeout=10)
+    meta = requests.get(f'{BASE_URL}/project/{project_id}/meta/other.txt', timeout=10).json()
+    assert meta['created_by'] is None
+    assert meta['updated_by'] is None
+
+
+def test_store_over_expired_entry() -> None:
+    """Test that storing over an expired entry creates a new entry, rather than updating the one which expired."""
+    project_id = new_project_id()
+    url = f'{BASE_URL}/project/{project_id}/notes.txt'
+    meta_url = f'{BASE_URL}/project/{project_id}/meta/notes.txt'
+
+    response = requests.post(url, data=b'v1', headers={'X-Actor': 'alice', 'X-TTL-Seconds': '0'}, timeout=10)
+    assert response.status_code == 201
+
+    response = requests.post(url, data=b'v2', headers={'X-Actor': 'bob'}, timeout=10)
+    assert response.status_code == 201
+    meta = requests.get(meta_url, timeout=10).json()
+    assert meta['created_by'] == 'bob'
+    assert meta['created_at'] == meta['updated_at']
+
+    response = requests.post(url, data=b'v3', headers={'X-Actor': 'carol'}, timeout=10)
+    assert response.status_code == 200
+    assert requests.get(meta_url, timeout=10).json()['created_by'] == 'bob'
+
+
+def test_audit_log() -> None:
+    """Test that every store, expiry and delete is recorded in the audit log, and that the log pages."""
+    project_id = new_project_id()
+    url = f'{BASE_URL}/project/{project_id}/tmp/a.txt'
+    audit_url = f'{BASE_URL}/project/{project_id}/audit'
+
+    requests.post(url, data=b'one', headers={'X-Actor': 'alice'}, timeout=10)
+    requests.post(url, data=b'three', headers={'X-Actor': 'bob'}, timeout=10)
+    requests.post(
+        f'{BASE_URL}/project/{project_id}/expire/tmp/',
+        headers={'X-TTL-Seconds': '60', 'X-Actor': 'cron'},
+        timeout=10,
+    )
+    requests.delete(url, timeout=10)
+
+    response = requests.get(audit_url, timeout=10)
+    assert response.status_code == 200
+    page = response.json()
+    assert page['next'] is None
+    events = [(e['key'], e['op'], e['actor'], e['size']) for e in page['events']]
+    assert events == [
+        ('tmp/a.txt', 'insert', 'alice', 3),
+        ('tmp/a.txt', 'update', 'bob', 5),
+        ('tmp/a.txt', 'update', 'cron', 5),
+        ('tmp/a.txt', 'delete', None, 5),
+    ]
+    assert all(e['namespace'] == 'default' for e in page['events'])
+
+    first = requests.get(audit_url, params={'limit': '3'}, timeout=10).json()
+    assert [e['id'] for e in first['events']] == [e['id'] for e in page['events'][:3]]
+    assert first['next'] == page['events'][2]['id']
+    rest = requests.get(audit_url, params={'after': str(first['next'])}, timeout=10).json()
+    assert [e['op'] for e in rest['events']] == ['delete']
+    assert rest['next'] is None
+
+    recorded_at = page['events'][0]['recorded_at']
+    before = requests.get(audit_url, params={'to': recorded_at.replace('+00:00', 'Z')}, timeout=10).json()
+    assert before['events'] == []
+    future = requests.get(audit_url, params={'from': '2999-01-01T00:00:00Z'}, timeout=10).json()
+    assert future['events'] == []
+
+
+def test_keys_shadowed_by_routes_still_storable() -> None:
+    """Test that fixed routes don't stop keys their paths spell out being stored and deleted."""
+    project_id = new_project_id()
+    keys = [
+        'audit',
+        'changes',
+        'dump',
+        'export',
+        'jsonquery',
+        'usage',
+        'get/a.txt',
+        'list/a b.txt',
+        'sign/a.txt',
+        'cas/abc',
+        'uploads/abc',
+    ]
+
+    for key in keys:
+        path = urllib.parse.quote(key)
+        response = requests.post(f'{BASE_URL}/project/{project_id}/{path}', data=key.encode(), timeout=10)
+        assert response.status_code == 201, key
+        response = requests.get(f'{BASE_URL}/project/{project_id}/get/{path}', timeout=10)
+        assert response.content == key.encode(), key
+        response = requests.delete(f'{BASE_URL}/project/{project_id}/{path}', timeout=10)
+        assert response.status_code == 204, key
+
+    # keys whose path is a store route can still be deleted
+    for key in ['batch', 'expire/x']:
+        requests.post(f'{BASE_URL}/project/{project_id}/entry', json={'key': key, 'content_base64': 'eA=='}, timeout=10)
+        assert requests.delete(f'{BASE_URL}/project/{project_id}/{key}', timeout=10).status_code == 204, key
+
+    # and the same within a namespace
+    base = f'{BASE_URL}/project/{project_id}/ns/dev'
+    assert requests.post(f'{base}/get/a.txt', data=b'x', timeout=10).status_code == 201
+    assert requests.get(f'{base}/get/get/a.txt', timeout=10).content == b'x'
+    assert requests.delete(f'{base}/get/a.txt', timeout=10).status_code == 204
+    assert requests.put(f'{base}/get/a.txt', data=b'x', timeout=10).status_code == 405
+
+
+def test_batch_partial_success() -> None:
+    """Test that a batch applies the operations which succeed and reports each failure separately."""
+    project_id = new_project_id()
+    requests.post(f'{BASE_URL}/project/{project_id}/old.txt', data=b'old', timeout=10)
+    requests.post(f'{BASE_URL}/project/{project_id}/locked.txt', data=b'x', headers={'X-Immutable': 'true'}, timeout=10)
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/batch',
+        json={
+            'operations': [
+                {'op': 'store', 'key': 'a.txt', 'mime_type': 'text/plain', 'content_base64': 'aGVsbG8='},
+                {'op': 'store', 'key': 'bad.txt', 'content_base64': '!!!'},
+                {'op': 'delete', 'key': 'old.txt'},
+                {'op': 'delete', 'key': 'missing.txt'},
+                {'op': 'store', 'key': 'locked.txt', 'content_base64': 'eA=='},
+                {'op': 'rename', 'key': 'a.txt'},
+            ]
+        },
+        timeout=10,
+    )
+    assert response.status_code == 207
+    results = response.json()['results']
+    assert [(r['index'], r['key'], r['status']) for r in results] == [
+        (0, 'a.txt', 201),
+        (1, 'bad.txt', 400),
+        (2, 'old.txt', 204),
+        (3, 'missing.txt', 404),
+        (4, 'locked.txt', 409),
+        (5, 'a.txt', 400),
+    ]
+    assert 'error' not in results[0]
+    assert 'content_base64' in results[1]['error']
+
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/a.txt', timeout=10).content == b'hello'
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/old.txt', timeout=10).status_code == 404
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/locked.txt', timeout=10).content == b'x'
+
+
+def test_batch_atomic() -> None:
+    """Test that with ?atomic=true one failure reverts the whole batch."""
+    project_id = new_project_id()
+    requests.post(f'{BASE_URL}/project/{project_id}/old.txt', data=b'old', timeout=10)
+
+    operations = [
+        {'op': 'store', 'key': 'a.txt', 'content_base64': 'aGVsbG8='},
+        {'op': 'delete', 'key': 'old.txt'},
+        {'op': 'delete', 'key': 'missing.txt'},
+    ]
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/batch',
+        params={'atomic': 'true'},
+        json={'operations': operations},
+        timeout=10,
+    )
+    assert response.status_code == 207
+    assert [r['status'] for r in response.json()['results']] == [424, 424, 404]
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/a.txt', timeout=10).status_code == 404
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/old.txt', timeout=10).content == b'old'
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/batch',
+        params={'atomic': 'true'},
+        json={'operations': operations[:2]},
+        timeout=10,
+    )
+    assert [r['status'] for r in response.json()['results']] == [201, 204]
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/a.txt', timeout=10).content == b'hello'
+
+
+def test_batch_dry_run() -> None:
+    """Test that ?dry_run=true reports what a batch's deletes would do without deleting anything."""
+    project_id = new_project_id()
+    requests.post(f'{BASE_URL}/project/{project_id}/old.txt', data=b'old', timeout=10)
+    requests.post(f'{BASE_URL}/project/{project_id}/locked.txt', data=b'x', headers={'X-Immutable': 'true'}, timeout=10)
+
+    operations = [
+        {'op': 'delete', 'key': 'old.txt'},
+        {'op': 'delete', 'key': 'locked.txt'},
+        {'op': 'delete', 'key': 'missing.txt'},
+        {'op': 'store', 'key': 'a.txt', 'content_base64': 'aGVsbG8='},
+    ]
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/batch',
+        params={'dry_run': 'true'},
+        json={'operations': operations},
+        timeout=10,
+    )
+    assert response.status_code == 207
+    assert [r['status'] for r in response.json()['results']] == [204, 403, 404, 400]
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/old.txt', timeout=10).content == b'old'
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/a.txt', timeout=10).status_code == 404
+
+    response = requests.post(f'{BASE_URL}/project/{project_id}/batch', json={'operations': operations[:3]}, timeout=10)
+    assert [r['status'] for r in response.json()['results']] == [204, 403, 404]
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/old.txt', timeout=10).status_code == 404
+
+
+def test_content_addressed() -> None:
+    """Test storing content under its SHA-256, deduplicating, and getting it back by hash."""
+    project_id = new_project_id()
+    content = b'artifact bytes'
+    sha256 = hashlib.sha256(content).hexdigest()
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/cas',
+        data=content,
+        headers={'Content-Type': 'application/zip'},
+        timeout=10,
+    )
+    assert response.status_code == 201
+    assert response.json()['sha256'] == sha256
+    assert response.json()['key'] == sha256
+    assert response.headers['Location'] == f'/project/{project_id}/cas/{sha256}'
+
+    response = requests.post(f'{BASE_URL}/project/{project_id}/cas', data=content, timeout=10)
+    assert response.status_code == 200
+    assert response.json()['sha256'] == sha256
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/cas/{sha256.upper()}', timeout=10)
+    assert response.status_code == 200
+    assert response.content == content
+    assert response.headers['Content-Type'] == 'application/zip'
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/cas/{"0" * 64}', timeout=10)
+    assert response.status_code == 404
+    response = requests.get(f'{BASE_URL}/project/{project_id}/cas/not-a-hash', timeout=10)
+    assert response.status_code == 400
+
+    # the namespace can't be written to directly, so keys always match their content
+    response = requests.post(f'{BASE_URL}/project/{project_id}/ns/cas/{"0" * 64}', data=b'forged', timeout=10)
+    assert response.status_code == 403
+
+
+def test_cas_keys_still_storable() -> None:
+    """Test that the content-addressed routes don't stop `cas/<name>` being stored, or `cas` being deleted."""
+    project_id = new_project_id()
+    response = requests.post(f'{BASE_URL}/project/{project_id}/cas/readme.txt', data=b'plain', timeout=10)
+    assert response.status_code == 201
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/cas/readme.txt', timeout=10)
+    assert response.content == b'plain'
+    response = requests.delete(f'{BASE_URL}/project/{project_id}/cas/readme.txt', timeout=10)
+    assert response.status_code == 204
+
+    # `cas` itself is reserved for raw body stores, but can be stored via the JSON endpoint
+    payload = {'key': 'cas', 'content_base64': base64.b64encode(b'plain').decode()}
+    response = requests.post(f'{BASE_URL}/project/{project_id}/entry', json=payload, timeout=10)
+    assert response.status_code == 201
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/cas', timeout=10)
+    assert response.content == b'plain'
+    response = requests.delete(f'{BASE_URL}/project/{project_id}/cas', timeout=10)
+    assert response.status_code == 204
+
+
+def test_entry_variants() -> None:
+    """Test storing other mime types of an entry as variants, and get picking one by the request's Accept."""
+    project_id = new_project_id()
+    url = f'{BASE_URL}/project/{project_id}/logo'
+
+    response = requests.post(url, data=b'jpeg', headers={'Content-Type': 'image/jpeg'}, timeout=10)
+    assert response.status_code == 201
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/logo', timeout=10)
+    assert 'Vary' not in response.headers
+
+    variant = {'Content-Type': 'image/webp', 'X-Variant': 'true'}
+    response = requests.post(url, data=b'webp', headers=variant, timeout=10)
+    assert response.status_code == 201
+    response = requests.post(url, data=b'webp2', headers=variant, timeout=10)
+    assert response.status_code == 200
+    meta = requests.get(f'{BASE_URL}/project/{project_id}/meta/logo', timeout=10).json()
+    assert meta['mime_type'] == 'image/jpeg'
+    assert meta['variants'] == ['image/webp']
+
+    cases = [
+        (None, 'image/jpeg', b'jpeg'),
+        ('*/*', 'image/jpeg', b'jpeg'),
+        ('image/webp,image/*;q=0.8', 'image/webp', b'webp2'),
+        ('image/jpeg;q=0.5, image/webp;q=0.9', 'image/webp', b'webp2'),
+        ('image/webp;q=0, */*', 'image/jpeg', b'jpeg'),
+        ('text/html', 'image/jpeg', b'jpeg'),
+    ]
+    for accept, mime_type, content in cases:
+        headers = {'Accept': accept} if accept else {}
+        response = requests.get(f'{BASE_URL}/project/{project_id}/get/logo', headers=headers, timeout=10)
+        assert response.status_code == 200, accept
+        assert response.headers['Content-Type'] == mime_type, accept
+        assert response.content == content, accept
+        assert response.headers['Vary'] == 'Accept'
+    # each variant has its own ETag
+    etag = response.headers['ETag']
+    response = requests.get(
+        f'{BASE_URL}/project/{project_id}/get/logo', headers={'Accept': 'image/webp', 'If-None-Match': etag}, timeout=10
+    )
+    assert response.status_code == 200
+
+    # the entry's own type can't be a variant, and variants need an existing entry
+    response = requests.post(url, data=b'x', headers={'Content-Type': 'image/jpeg', 'X-Variant': 'true'}, timeout=10)
+    assert response.status_code == 400
+    response = requests.post(f'{url}-missing', data=b'x', headers=variant, timeout=10)
+    assert response.status_code == 404
+
+    response = requests.delete(url, params={'variant': 'image/webp'}, timeout=10)
+    assert response.status_code == 204
+    response = requests.delete(url, params={'variant': 'image/webp'}, timeout=10)
+    assert response.status_code == 404
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/logo', headers={'Accept': 'image/webp'}, timeout=10)
+    assert response.content == b'jpeg'
+    assert 'Vary' not in response.headers
+
+    # overwriting the entry drops its variants, which were of the old content
+    requests.post(url, data=b'webp', headers=variant, timeout=10)
+    response = requests.post(url, data=b'png', headers={'Content-Type': 'image/png'}, timeout=10)
+    assert response.status_code == 200
+    meta = requests.get(f'{BASE_URL}/project/{project_id}/meta/logo', timeout=10).json()
+    assert meta['variants'] == []
+
+
+def test_entry_variants_quota_and_audit() -> None:
+    """Test that variants count towards the project's quota and their changes are recorded in the audit log."""
+    project_id = new_project_id()
+    response = requests.put(f'{BASE_URL}/project/{project_id}', json={'quota_bytes': 10}, timeout=10)
+    assert response.status_code == 201
+    url = f'{BASE_URL}/project/{project_id}/logo'
+    variant = {'Content-Type': 'image/webp', 'X-Variant': 'true', 'X-Actor': 'alice'}
+
+    assert requests.post(url, data=b'jpeg', headers={'Content-Type': 'image/jpeg'}, timeout=10).status_code == 201
+    response = requests.post(url, data=b'webp!', headers=variant, timeout=10)
+    assert response.status_code == 201
+    assert response.headers['X-Quota-Warning'] == '90'
+    response = requests.post(url, data=b'webp-too-big', headers=variant, timeout=10)
+    assert response.status_code == 507
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/logo', headers={'Accept': 'image/webp'}, timeout=10)
+    assert response.content == b'webp!'
+    # the variant's size leaves no room for another entry
+    assert requests.post(f'{BASE_URL}/project/{project_id}/a.txt', data=b'123', timeout=10).status_code == 507
+
+    requests.delete(url, params={'variant': 'image/webp'}, headers={'X-Actor': 'bob'}, timeout=10)
+    requests.post(url, data=b'webp', headers=variant, timeout=10)
+    # variants deleted with their entry are covered by the entry's delete
+    requests.delete(url, timeout=10)
+
+    events = requests.get(f'{BASE_URL}/project/{project_id}/audit', timeout=10).json()['events']
+    assert [(e['op'], e['actor'], e['size'], e.get('variant')) for e in events] == [
+        ('insert', None, 4, None),
+        ('insert', 'alice', 5, 'image/webp'),
+        ('delete', 'bob', 5, 'image/webp'),
+        ('insert', 'alice', 4, 'image/webp'),
+        ('delete', None, 4, None),
+    ]
+
+
+def test_swap_entry_value() -> None:
+    """Test replacing an entry's content only when it currently has the expected value."""
+    project_id = new_project_id()
+    headers = {'Content-Type': 'application/json'}
+    requests.post(f'{BASE_URL}/project/{project_id}/config.json', data=b'{"v":1}', headers=headers, timeout=10)
+    url = f'{BASE_URL}/project/{project_id}/cas-value/config.json'
+
+    def swap(expected: bytes, new: bytes) -> requests.Response:
+        request = {'expected_base64': base64.b64encode(expected).decode(), 'new_base64': base64.b64encode(new).decode()}
+        return requests.post(url, json=request, timeout=10)
+
+    response = swap(b'{"v":1}', b'{"v":2}')
+    assert response.status_code == 200
+    assert response.json()['sha256'] == hashlib.sha256(b'{"v":2}').hexdigest()
+    assert response.json()['mime_type'] == 'application/json'
+
+    # a stale expected value loses
+    response = swap(b'{"v":1}', b'{"v":3}')
+    assert response.status_code == 409
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/config.json', timeout=10).content == b'{"v":2}'
+
+    assert swap(b'', b'x').status_code == 409
+    request = {'expected_base64': '', 'new_base64': ''}
+    response = requests.post(f'{BASE_URL}/project/{project_id}/cas-value/missing', json=request, timeout=10)
+    assert response.status_code == 404
+
+    requests.post(f'{BASE_URL}/project/{project_id}/fixed', data=b'a', headers={'X-Immutable': 'true'}, timeout=10)
+    request = {'expected_base64': base64.b64encode(b'a').decode(), 'new_base64': ''}
+    response = requests.post(f'{BASE_URL}/project/{project_id}/cas-value/fixed', json=request, timeout=10)
+    assert response.status_code == 409
+
+    # deletes of keys under `cas-value/` still reach the entry rather than the route
+    payload = {'key': 'cas-value/x', 'content_base64': ''}
+    requests.post(f'{BASE_URL}/project/{project_id}/entry', json=payload, timeout=10)
+    response = requests.delete(f'{BASE_URL}/project/{project_id}/cas-value/x', timeout=10)
+    assert response.status_code == 204
+
+
+def test_create_only_store() -> None:
+    """Test that If-None-Match: * stores only when the key is free, with exactly one winner of a race."""
+    project_id = new_project_id()
+    url = f'{BASE_URL}/project/{project_id}/lock'
+    create_only = {'If-None-Match': '*'}
+
+    def store(index: int) -> int:
+        return requests.post(url, data=f'{index}'.encode(), headers=create_only, timeout=10).status_code
+
+    with concurrent.futures.ThreadPoolExecutor(max_workers=8) as executor:
+        statuses = list(executor.map(store, range(16)))
+    assert sorted(statuses) == [201] + [412] * 15
+
+    payload = {'key': 'lock', 'content_base64': 'eA=='}
+    response = requests.post(f'{BASE_URL}/project/{project_id}/entry', json=payload, headers=create_only, timeout=10)
+    assert response.status_code == 412
+
+    # an expired entry doesn't hold the key
+    requests.post(url, data=b'old', headers={'X-TTL-Seconds': '0'}, timeout=10)
+    assert requests.post(url, data=b'new', headers=create_only, timeout=10).status_code == 201
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/lock', timeout=10).content == b'new'
+
+    response = requests.post(url, data=b'x', headers={'If-None-Match': '"abc"'}, timeout=10)
+    assert response.status_code == 400
+
+
+def test_store_requires_other_key() -> None:
+    """Test that X-Require-Exists and X-Require-Absent make a store depend on another key in its namespace."""
+    base = f'{BASE_URL}/project/{new_project_id()}'
+    requires_lock = {'X-Require-Exists': 'lock'}
+    response = requests.post(f'{base}/result', data=b'r', headers=requires_lock, timeout=10)
+    assert response.status_code == 412
+    assert response.json()['error'] == 'Precondition failed: entry "lock" doesn\'t exist'
+    assert requests.get(f'{base}/get/result', timeout=10).status_code == 404
+
+    assert requests.post(f'{base}/lock', data=b'l', headers={'X-Require-Absent': 'lock'}, timeout=10).status_code == 201
+    assert requests.post(f'{base}/lock', data=b'l', headers={'X-Require-Absent': 'lock'}, timeout=10).status_code == 412
+    assert requests.post(f'{base}/result', data=b'r', headers=requires_lock, timeout=10).status_code == 201
+    assert requests.get(f'{base}/get/result', timeout=10).content == b'r'
+
+    # the required key is looked for in the store's namespace, and expired entries don't count
+    assert requests.post(f'{base}/ns/dev/result', data=b'r', headers=requires_lock, timeout=10).status_code == 412
+    requests.post(f'{base}/ns/dev/lock', data=b'l', headers={'X-TTL-Seconds': '0'}, timeout=10)
+    assert requests.post(f'{base}/ns/dev/result', data=b'r', headers=requires_lock, timeout=10).status_code == 412
+
+    headers = {'X-Require-Exists': 'lock', 'X-Require-Absent': 'other'}
+    assert requests.post(f'{base}/result', data=b'r', headers=headers, timeout=10).status_code == 400
+
+def test_resumable_upload() -> None:
+    """Test that an upload sent in chunks at their offsets is stored once its whole length has arrived."""
+    base = f'{BASE_URL}/project/{new_project_id()}'
+    content = bytes(range(256)) * 40
+    payload = {'key': 'big.bin', 'namespace': 'dev', 'mime_type': 'video/mp4', 'length': len(content)}
+    response = requests.post(f'{base}/uploads', json=payload, timeout=10)
+    assert response.status_code == 201, response.text
+    upload = response.json()
+    assert (upload['key'], upload['offset'], upload['length']) == ('big.bin', 0, len(content))
+    url = f'{BASE_URL}{response.headers["Location"]}'
+
+    def send(offset: int, chunk: bytes) -> requests.Response:
+        return requests.patch(url, data=chunk, headers={'Upload-Offset': str(offset)}, timeout=10)
+
+    response = send(0, content[:4000])
+    assert response.status_code == 204
+    assert response.headers['Upload-Offset'] == '4000'
+    # a chunk which doesn't continue from what's been received, e.g. resent after its response was lost
+    response = send(0, content[:4000])
+    assert response.status_code == 409
+    assert requests.head(url, timeout=10).headers['Upload-Offset'] == '4000'
+    assert send(4000, content[4000:] + b'extra').status_code == 413
+    assert requests.get(f'{base}/ns/dev/get/big.bin', timeout=10).status_code == 404
+
+    response = send(4000, content[4000:])
+    assert response.status_code == 201
+    response = requests.get(f'{base}/ns/dev/get/big.bin', timeout=10)
+    assert (response.content, response.headers['Content-Type']) == (content, 'video/mp4')
+    # the upload is gone once stored
+    assert send(len(content), b'').status_code == 404
+    assert requests.head(url, timeout=10).status_code == 404
+
+    assert requests.post(f'{base}/uploads', json={'key': 'x', 'length': -1}, timeout=10).status_code == 400
+    response = requests.post(f'{base}/uploads', json={'key': 'x', 'length': 100 * 1024 * 1024}, timeout=10)
+    assert response.status_code == 413
+
+    # keys `uploads/<id>` can still be stored and deleted
+    assert requests.post(f'{base}/uploads/abc', data=b'x', timeout=10).status_code == 201
+    assert requests.get(f'{base}/get/uploads/abc', timeout=10).content == b'x'
+    assert requests.delete(f'{base}/uploads/abc', timeout=10).status_code == 204
+    assert requests.delete(f'{base}/uploads', params={'idempotent': 'true'}, timeout=10).status_code == 204
+
+def test_max_project_uploads() -> None:
+    """Test that with MAX_PROJECT_UPLOADS a project's concurrent stores beyond the limit get 429, others' don't."""
+    project_id = new_project_id()
+    other_project = new_project_id()
+    content = b'x' * (1024 * 1024)
+
+    def store(index: int) -> int:
+        response = requests.post(f'{BASE_URL}/project/{project_id}/upload-{index}', data=content, timeout=30)
+        return response.status_code
+
+    with concurrent.futures.ThreadPoolExecutor(max_workers=16) as executor:
+        statuses = list(executor.map(store, range(32)))
+    if 429 not in statuses:
+        pytest.skip('no stores were rejected, MAX_PROJECT_UPLOADS is not set')
+    assert set(statuses) == {201, 429}
+
+    # slots are released once the stores finish, and other projects and reads were never limited
+    response = requests.post(f'{BASE_URL}/project/{other_project}/a.txt', data=b'a', timeout=10)
+    assert response.status_code == 201
+    response = requests.post(f'{BASE_URL}/project/{project_id}/after.txt', data=b'a', timeout=10)
+    assert response.status_code == 201
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/after.txt', timeout=10)
+    assert response.status_code == 200
+
+
+def test_store_created_vs_updated() -> None:
+    """Test that storing a new key returns 201 and overwriting an existing key returns 200."""
+    project_id = new_project_id()
+    key = 'config.json'
+
+    create_response = requests.post(
+        f'{BASE_URL}/project/{project_id}/{key}',
+        data=b'{"v": 1}',
+        headers={'Content-Type': 'application/json'},
+        timeout=10,
+    )
+    assert create_response.status_code == 201
+
+    update_response = requests.post(
+        f'{BASE_URL}/project/{project_id}/{key}',
+        data=b'{"v": 2}',
+        headers={'Content-Type': 'application/json'},
+        timeout=10,
+    )
+    assert update_response.status_code == 200
+
+    # The JSON endpoint makes the same distinction
+    json_update_response = requests.post(
+        f'{BASE_URL}/project/{project_id}/entry',
+        json={'key': key, 'mime_type': 'application/json', 'content_base64': base64.b64encode(b'{"v": 3}').decode()},
+        timeout=10,
+    )
+    assert json_update_response.status_code == 200
+
+
+def test_immutable_entry() -> None:
+    """Test that immutable entries can't be overwritten or deleted, except via the admin force-delete."""
+    project_id = new_project_id()
+    key = 'builds/abc123.tar'
+
+    store_response = requests.post(
+        f'{BASE_URL}/project/{project_id}/{key}',
+        data=b'artifact',
+        headers={'Content-Type': 'application/x-tar', 'X-Immutable': 'true'},
+        timeout=10,
+    )
+    assert store_response.status_code == 201
+
+    overwrite_response = requests.post(
+        f'{BASE_URL}/project/{project_id}/{key}',
+        data=b'tampered',
+        headers={'Content-Type': 'application/x-tar'},
+        timeout=10,
+    )
+    assert overwrite_response.status_code == 409
+
+    delete_response = requests.delete(
+        f'{BASE_URL}/project/{project_id}/{key}',
+        timeout=10,
+    )
+    assert delete_response.status_code == 403
+
+    get_response = requests.get(
+        f'{BASE_URL}/project/{project_id}/get/{key}',
+        timeout=10,
+    )
+    assert get_response.content == b'artifact'
+
+    force_delete_response = requests.delete(
+        f'{BASE_URL}/admin/project/{project_id}/{key}',
+        timeout=10,
+    )
+    assert force_delete_response.status_code == 204
+
+
+def test_list_size_and_mime_filters() -> None:
+    """Test filtering listings by content size and mime type, combined with a prefix."""
+    project_id = new_project_id()
+
+    entries = [
+        ('assets/small.png', 'image/png', 10),
+        ('assets/large.png', 'image/png', 5000),
+        ('assets/large.jpg', 'image/jpeg', 6000),
+        ('assets/large.json', 'application/json', 7000),
+        ('other/large.png', 'image/png', 8000),
+    ]
+    for key, mime_type, size in entries:
+        requests.post(
+            f'{BASE_URL}/project/{project_id}/{key}',
+            data=b'x' * size,
+            headers={'Content-Type': mime_type},
+            timeout=10,
+        )
+
+    response = requests.get(
+        f'{BASE_URL}/project/{project_id}/list/assets/',
+        params={'min_size': '1000', 'mime': 'image/*'},
+        timeout=10,
+    )
+    assert response.status_code == 200
+    result: list[dict[str, str | int]] = response.json()
+    assert [(entry['key'], entry['size']) for entry in result] == [
+        ('assets/large.jpg', 6000),
+        ('assets/large.png', 5000),
+    ]
+
+    response = requests.get(
+        f'{BASE_URL}/project/{project_id}/list/',
+        params={'max_size': '6000', 'mime': 'image/png'},
+        timeout=10,
+    )
+    assert [entry['key'] for entry in response.json()] == ['assets/large.png', 'assets/small.png']
+
+    response = requests.get(
+        f'{BASE_URL}/project/{project_id}/list/assets/',
+        params={'mime': 'image/jpeg, application/*'},
+        timeout=10,
+    )
+    assert [entry['key'] for entry in response.json()] == ['assets/large.jpg', 'assets/large.json']
+
+    invalid_response = requests.get(
+        f'{BASE_URL}/project/{project_id}/list/',
+        params={'min_size': '10', 'max_size': '5'},
+        timeout=10,
+    )
+    assert invalid_response.status_code == 400
+
+    invalid_response = requests.get(
+        f'{BASE_URL}/project/{project_id}/list/',
+        params={'mime': 'image/png,bogus'},
+        timeout=10,
+    )
+    assert invalid_response.status_code == 400
+
+
+def test_invalid_project_id() -> None:
+    """Test that a malformed project id returns 400 with the usual error body."""
+    response = requests.get(f'{BASE_URL}/project/not-a-uuid/get/foo', timeout=10)
+    assert response.status_code == 400
+    assert response.json() == {'error': 'Validation error: invalid project id, expected a UUID'}
+
+    response = requests.post(f'{BASE_URL}/project/not-a-uuid/foo', data=b'x', timeout=10)
+    assert response.status_code == 400
+    assert 'expected a UUID' in response.json()['error']
+
+
+def test_replayed_response_headers() -> None:
+    """Test that allowlisted headers sent on store are returned on get, and others are dropped."""
+    project_id = new_project_id()
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/fr/index.html',
+        data=b'<p>bonjour</p>',
+        headers={
+            'Content-Type': 'text/html',
+            'Content-Language': 'fr',
+            'Cache-Control': 'public, max-age=60',
+            'Set-Cookie': 'evil=1',
+        },
+        timeout=10,
+    )
+    assert response.status_code == 201
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/fr/index.html', timeout=10)
+    assert response.status_code == 200
+    assert response.headers['Content-Type'] == 'text/html'
+    assert response.headers['Content-Language'] == 'fr'
+    assert response.headers['Cache-Control'] == 'public, max-age=60'
+    assert 'Set-Cookie' not in response.headers
+
+    # overwriting without the headers clears them
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/fr/index.html',
+        data=b'<p>salut</p>',
+        headers={'Content-Type': 'text/html'},
+        timeout=10,
+    )
+    assert response.status_code == 200
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/fr/index.html', timeout=10)
+    assert 'Content-Language' not in response.headers
+
+
+def test_export_filtered() -> None:
+    """Test that export only includes entries matching the prefix and mime filters, and records them."""
+    project_id = new_project_id()
+
+    for key, mime_type in [
+        ('img/a.png', 'image/png'),
+        ('img/b.txt', 'text/plain'),
+        ('img/c.jpg', 'image/jpeg'),
+        ('docs/d.png', 'image/png'),
+    ]:
+        response = requests.post(
+            f'{BASE_URL}/project/{project_id}/{key}',
+            data=key.encode(),
+            headers={'Content-Type': mime_type},
+            timeout=10,
+        )
+        assert response.status_code == 201
+
+    response = requests.get(
+        f'{BASE_URL}/project/{project_id}/export',
+        params={'prefix': 'img/', 'mime': 'image/*'},
+        timeout=10,
+    )
+    assert response.status_code == 200
+    assert response.headers['Content-Type'] == 'application/x-tar'
+
+    with tarfile.open(fileobj=io.BytesIO(response.content)) as archive:
+        manifest = json.load(archive.extractfile('manifest.json'))
+        assert manifest['project'] == project_id
+        assert manifest['prefix'] == 'img/'
+        assert manifest['mime'] == 'image/*'
+        assert [e['key'] for e in manifest['entries']] == ['img/a.png', 'img/c.jpg']
+        for entry in manifest['entries']:
+            content = archive.extractfile(entry['path']).read()
+            assert content == entry['key'].encode()
+            assert entry['size'] == len(content)
+            assert entry['sha256'] == hashlib.sha256(content).hexdigest()
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/export', timeout=10)
+    with tarfile.open(fileobj=io.BytesIO(response.content)) as archive:
+        manifest = json.load(archive.extractfile('manifest.json'))
+        assert manifest['prefix'] is None
+        assert manifest['mime'] is None
+        assert len(manifest['entries']) == 4
+
+
+
+def test_dump_entries() -> None:
+    """Test that dump streams every live entry in every namespace as ND-JSON with base64 content."""
+    project_id = new_project_id()
+    base = f'{BASE_URL}/project/{project_id}'
+    requests.post(f'{base}/b.txt', data=b'bee', headers={'Content-Type': 'text/plain'}, timeout=10)
+    requests.post(f'{base}/a.bin', data=b'\x00\x01', headers={'X-Immutable': 'true'}, timeout=10)
+    requests.post(f'{base}/ns/dev/c.html', data=b'<p>', headers={'Cache-Control': 'no-store'}, timeout=10)
+    requests.post(f'{base}/gone', data=b'x', headers={'X-TTL-Seconds': '0'}, timeout=10)
+
+    response = requests.get(f'{base}/dump', timeout=10)
+    assert response.status_code == 200
+    assert response.headers['Content-Type'] == 'application/x-ndjson'
+    lines = [json.loads(line) for line in response.text.splitlines()]
+    assert [(line['namespace'], line['key']) for line in lines] == [
+        ('default', 'a.bin'),
+        ('default', 'b.txt'),
+        ('dev', 'c.html'),
+    ]
+    a, b, c = lines
+    assert base64.b64decode(a['content_base64']) == b'\x00\x01'
+    assert a['immutable'] is True
+    assert (b['mime_type'], base64.b64decode(b['content_base64'])) == ('text/plain', b'bee')
+    assert c['response_headers'] == {'cache-control': 'no-store'}
+
+    # the route doesn't shadow storing or deleting the key `dump`
+    assert requests.post(f'{base}/dump', data=b'x', timeout=10).status_code == 201
+    assert requests.delete(f'{base}/dump', timeout=10).status_code == 204
+
+
+def test_restore_dump() -> None:
+    """Test that restore-dump upserts each line of a dump, reporting lines which can't be restored."""
+    source = f'{BASE_URL}/project/{new_project_id()}'
+    requests.post(f'{source}/a.txt', data=b'aaa', headers={'Content-Type': 'text/plain'}, timeout=10)
+    requests.post(f'{source}/ns/dev/b', data=b'bbb', headers={'X-TTL-Seconds': '3600'}, timeout=10)
+    requests.post(f'{source}/c', data=b'ccc', headers={'Cache-Control': 'no-store'}, timeout=10)
+    dump = requests.get(f'{source}/dump', timeout=10).text.splitlines()
+    assert len(dump) == 3
+
+    target = f'{BASE_URL}/project/{new_project_id()}'
+    requests.post(f'{target}/locked', data=b'old', headers={'X-Immutable': 'true'}, timeout=10)
+    expired = {'key': 'gone', 'mime_type': 'text/plain', 'content_base64': '', 'expires_at': '2020-01-01T00:00:00Z'}
+    locked = {'key': 'locked', 'mime_type': 'text/plain', 'content_base64': 'bmV3'}
+    body = '\n'.join([dump[0], 'not json', '', json.dumps(expired), dump[1], json.dumps(locked), dump[2]])
+    response = requests.post(f'{target}/restore-dump', data=body.encode(), timeout=10)
+    assert response.status_code == 200, response.text
+    report = response.json()
+    assert (report['restored'], report['expired'], report['failed']) == (3, 1, 2)
+    assert [error['line'] for error in report['errors']] == [2, 6]
+    assert report['errors'][1]['error'] == 'Conflict: entry "locked" is immutable'
+
+    response = requests.get(f'{target}/get/a.txt', timeout=10)
+    assert (response.content, response.headers['Content-Type']) == (b'aaa', 'text/plain')
+    assert requests.get(f'{target}/get/c', timeout=10).headers['Cache-Control'] == 'no-store'
+    dumped = next(entry for entry in map(json.loads, dump) if entry['key'] == 'b')
+    restored_dump = requests.get(f'{target}/dump', timeout=10).text.splitlines()
+    restored = next(entry for entry in map(json.loads, restored_dump) if entry['key'] == 'b')
+    drift = datetime.fromisoformat(restored['expires_at']) - datetime.fromisoformat(dumped['expires_at'])
+    assert abs(drift.total_seconds()) < 5
+    assert requests.get(f'{target}/get/locked', timeout=10).content == b'old'
+    assert requests.get(f'{target}/get/gone', timeout=10).status_code == 404
+
+    # restoring the same dump again overwrites the entries
+    response = requests.post(f'{target}/restore-dump', data='\n'.join(dump).encode(), timeout=10)
+    assert response.json() == {'restored': 3, 'expired': 0, 'failed': 0, 'errors': []}
+
+    assert requests.delete(f'{target}/restore-dump', timeout=10).status_code == 404
+
+
+def test_store_json_response() -> None:
+    """Test that stores describe the stored entry when the client accepts JSON, and return an empty body otherwise."""
+    project_id = new_project_id()
+    content = b'hello json response'
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/a.txt',
+        data=gzip.compress(content),
+        headers={'Content-Type': 'text/plain', 'Content-Encoding': 'gzip', 'Accept': 'application/json'},
+        timeout=10,
+    )
+    assert response.status_code == 201
+    sha256 = hashlib.sha256(content).hexdigest()
+    assert response.json() == {
+        'key': 'a.txt',
+        'size': len(content),
+        'mime_type': 'text/plain',
+        'etag': f'"{sha256}"',
+        'sha256': sha256,
+    }
+
+    meta_response = requests.get(f'{BASE_URL}/project/{project_id}/meta/a.txt', timeout=10)
+    assert meta_response.json()['etag'] == response.json()['etag']
+
+    response = requests.post(f'{BASE_URL}/project/{project_id}/a.txt', data=content, timeout=10)
+    assert response.status_code == 200
+    assert response.content == b''
+
+
+def test_get_etag() -> None:
+    """Test that gets return an ETag, weak for large entries, and 304 when it matches If-None-Match."""
+    project_id = new_project_id()
+    requests.post(f'{BASE_URL}/project/{project_id}/small', data=b'small', timeout=10)
+    requests.post(f'{BASE_URL}/project/{project_id}/large', data=b'x' * (1024 * 1024 + 1), timeout=10)
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/small', timeout=10)
+    assert response.headers['ETag'] == f'"{hashlib.sha256(b"small").hexdigest()}"'
+    etag = response.headers['ETag']
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/small', headers={'If-None-Match': etag}, timeout=10)
+    assert response.status_code == 304
+    assert response.content == b''
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/large', timeout=10)
+    etag = response.headers['ETag']
+    assert etag.startswith('W/"')
+    assert requests.get(f'{BASE_URL}/project/{project_id}/meta/large', timeout=10).json()['etag'] == etag
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/large', headers={'If-None-Match': etag}, timeout=10)
+    assert response.status_code == 304
+
+    # storing again changes the weak ETag
+    requests.post(f'{BASE_URL}/project/{project_id}/large', data=b'y' * (1024 * 1024 + 1), timeout=10)
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/large', headers={'If-None-Match': etag}, timeout=10)
+    assert response.status_code == 200
+    assert response.headers['ETag'] != etag
+
+
+def test_store_location() -> None:
+    """Test that stores return a Location header pointing at the stored entry."""
+    project_id = new_project_id()
+
+    response = requests.post(f'{BASE_URL}/project/{project_id}/docs/a%20b.txt', data=b'hello', timeout=10)
+    location = response.headers['Location']
+    assert location.endswith(f'/project/{project_id}/get/docs/a%20b.txt')
+    if location.startswith('/'):
+        location = BASE_URL + location
+    assert requests.get(location, timeout=10).content == b'hello'
+
+    response = requests.post(f'{BASE_URL}/project/{project_id}/ns/dev/a.txt', data=b'dev', timeout=10)
+    assert response.headers['Location'].endswith(f'/project/{project_id}/ns/dev/get/a.txt')
+
+
+def test_store_compressed_body() -> None:
+    """Test that gzip and deflate bodies are decompressed before storing."""
+    project_id = new_project_id()
+    content = b'hello compressed world' * 100
+
+    bodies = [('a.txt', 'gzip', gzip.compress(content)), ('b.txt', 'deflate', zlib.compress(content))]
+    for key, encoding, body in bodies:
+        response = requests.post(
+            f'{BASE_URL}/project/{project_id}/{key}',
+            data=body,
+            headers={'Content-Type': 'text/plain', 'Content-Encoding': encoding},
+            timeout=10,
+        )
+        assert response.status_code == 201
+
+        response = requests.get(f'{BASE_URL}/project/{project_id}/get/{key}', timeout=10)
+        assert response.status_code == 200
+        assert response.headers['Content-Type'] == 'text/plain'
+        assert response.content == content
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/bad.txt',
+        data=b'not gzip',
+        headers={'Content-Type': 'text/plain', 'Content-Encoding': 'gzip'},
+        timeout=10,
+    )
+    assert response.status_code == 400
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/bad.txt',
+        data=b'x',
+        headers={'Content-Type': 'text/plain', 'Content-Encoding': 'br'},
+        timeout=10,
+    )
+    assert response.status_code == 415
+
+
+
+def test_require_content_type() -> None:
+    """Test that with REQUIRE_CONTENT_TYPE stores without a Content-Type are rejected rather than defaulted."""
+    project_id = new_project_id()
+
+    response = requests.post(f'{BASE_URL}/project/{project_id}/untyped', data=b'bytes', timeout=10)
+    if response.status_code == 201:
+        pytest.skip('stores default to application/octet-stream, REQUIRE_CONTENT_TYPE is not set')
+    assert response.status_code == 400
+    assert response.json() == {'error': 'Validation error: Content-Type header is required'}
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/untyped', timeout=10)
+    assert response.status_code == 404
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/typed',
+        data=b'bytes',
+        headers={'Content-Type': 'application/octet-stream'},
+        timeout=10,
+    )
+    assert response.status_code == 201
+
+
+def test_validate_json() -> None:
+    """Test that with VALIDATE_JSON stores of JSON entries which don't parse are rejected, other types aren't."""
+    base = f'{BASE_URL}/project/{new_project_id()}'
+    json_type = {'Content-Type': 'application/json; charset=utf-8'}
+
+    response = requests.post(f'{base}/bad.json', data=b'{"a": 1', headers=json_type, timeout=10)
+    if response.status_code == 201:
+        pytest.skip('JSON isn\'t validated, VALIDATE_JSON is not set')
+    assert response.status_code == 400
+    assert response.json()['error'].startswith("Validation error: content isn't valid JSON")
+    assert requests.get(f'{base}/get/bad.json', timeout=10).status_code == 404
+
+    headers = {'Content-Type': 'application/ld+json'}
+    assert requests.post(f'{base}/bad.jsonld', data=b'[', headers=headers, timeout=10).status_code == 400
+    payload = {'key': 'bad', 'mime_type': 'application/json', 'content_base64': base64.b64encode(b'nope').decode()}
+    assert requests.post(f'{base}/entry', json=payload, timeout=10).status_code == 400
+
+    assert requests.post(f'{base}/good.json', data=b'{"a": 1}', headers=json_type, timeout=10).status_code == 201
+    headers = {'Content-Type': 'text/plain'}
+    assert requests.post(f'{base}/notes.txt', data=b'{"a": 1', headers=headers, timeout=10).status_code == 201
+
+def test_json_query() -> None:
+    """Test that jsonquery returns the keys of JSON entries whose content matches a SQL/JSON path."""
+    base = f'{BASE_URL}/project/{new_project_id()}'
+    json_type = {'Content-Type': 'application/json'}
+    documents = {
+        'jobs/1': {'status': 'active', 'tags': ['urgent']},
+        'jobs/2': {'status': 'done', 'tags': []},
+        'jobs/3': {'status': 'active', 'tags': ['later']},
+        'other/4': {'status': 'active'},
+    }
+    for key, document in documents.items():
+        requests.post(f'{base}/{key}', data=json.dumps(document).encode(), headers=json_type, timeout=10)
+    requests.post(f'{base}/jobs/broken', data=b'{"status": ', headers=json_type, timeout=10)
+    text = {'Content-Type': 'text/plain'}
+    requests.post(f'{base}/jobs/text', data=b'{"status": "active"}', headers=text, timeout=10)
+    headers = {'Content-Type': 'application/ld+json'}
+    requests.post(f'{base}/ns/dev/jobs/5', data=b'{"status": "active"}', headers=headers, timeout=10)
+
+    def query(**params: str) -> dict:
+        response = requests.get(f'{base}/jsonquery', params=params, timeout=10)
+        assert response.status_code == 200, response.text
+        return response.json()
+
+    page = query(path='$.status == "active"')
+    assert [key['key'] for key in page['keys']] == ['jobs/1', 'jobs/3', 'other/4']
+    assert page['keys'][0]['mime_type'] == 'application/json'
+    assert page['next'] is None
+    keys = query(path='$.tags[*] ? (@ == "urgent")')['keys']
+    assert [key['key'] for key in keys] == ['jobs/1']
+    assert [key['key'] for key in query(path='$.status == "active"', prefix='jobs/')['keys']] == ['jobs/1', 'jobs/3']
+    assert [key['key'] for key in query(path='$.status', namespace='dev')['keys']] == ['jobs/5']
+
+    page = query(path='$.status == "active"', limit='2')
+    assert ([key['key'] for key in page['keys']], page['next']) == (['jobs/1', 'jobs/3'], 'jobs/3')
+    page = query(path='$.status == "active"', limit='2', after=page['next'])
+    assert ([key['key'] for key in page['keys']], page['next']) == (['other/4'], None)
+
+    response = requests.get(f'{base}/jsonquery', params={'path': '$.status ==='}, timeout=10)
+    assert response.status_code == 400
+    assert response.json()['error'].startswith('Validation error: invalid path')
+
+    assert requests.post(f'{base}/jsonquery', data=b'x', timeout=10).status_code == 201
+    assert requests.delete(f'{base}/jsonquery', timeout=10).status_code == 204
+
+def test_entry_size_limit() -> None:
+    """Test that entries over MAX_ENTRY_SIZE are rejected with 413, including once decompressed."""
+    project_id = new_project_id()
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/bomb.txt',
+        data=gzip.compress(b'\0' * (64 * 1024 * 1024)),
+        headers={'Content-Type': 'text/plain', 'Content-Encoding': 'gzip'},
+        timeout=10,
+    )
+    assert response.status_code == 413
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/bomb.txt', timeout=10)
+    assert response.status_code == 404
+
+
+def test_mime_size_limits() -> None:
+    """Test that a MIME_SIZE_LIMITS rule like `application/json=1024` applies to matching stores only."""
+    project_id = new_project_id()
+    content = json.dumps({'data': 'x' * 2048}).encode()
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/big.json',
+        data=content,
+        headers={'Content-Type': 'application/json; charset=utf-8'},
+        timeout=10,
+    )
+    if response.status_code == 201:
+        pytest.skip('no application/json size limit, MIME_SIZE_LIMITS is not set')
+    assert response.status_code == 413
+    assert response.json() == {
+        'error': f'Payload too large: application/json; charset=utf-8 entries are limited to 1024 bytes, '
+        f'got {len(content)}'
+    }
+
+    # the JSON endpoint and batches are limited the same way
+    payload = {'key': 'big.json', 'mime_type': 'application/json', 'content_base64': base64.b64encode(content).decode()}
+    response = requests.post(f'{BASE_URL}/project/{project_id}/entry', json=payload, timeout=10)
+    assert response.status_code == 413
+
+    response = requests.post(f'{BASE_URL}/project/{project_id}/big.txt', data=content, timeout=10)
+    assert response.status_code == 201
+
+
+def test_body_read_timeout() -> None:
+    """Test that a body trickling in slower than BODY_READ_TIMEOUT_SECS gets 408 and nothing is stored."""
+    project_id = new_project_id()
+    url = urllib.parse.urlsplit(BASE_URL)
+    with socket.create_connection((url.hostname, url.port), timeout=3) as conn:
+        head = f'POST /project/{project_id}/slow.txt HTTP/1.1\r\nHost: {url.netloc}\r\nContent-Length: 10\r\n\r\n'
+        # one byte of the promised ten
+        conn.sendall(head.encode() + b'x')
+        try:
+            status_line = conn.recv(1024).split(b'\r\n', 1)[0]
+        except TimeoutError:
+            pytest.skip('BODY_READ_TIMEOUT_SECS is not set below 3 seconds')
+    assert status_line == b'HTTP/1.1 408 Request Timeout'
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/slow.txt', timeout=10).status_code == 404
+
+
+def test_project_default_ttl() -> None:
+    """Test that a project's default TTL applies unless the store sends X-TTL-Seconds."""
+    project_id = new_project_id()
+
+    response = requests.put(f'{BASE_URL}/project/{project_id}', json={'default_ttl_secs': 0}, timeout=10)
+    assert response.status_code == 201
+    assert response.json()['default_ttl_secs'] == 0
+
+    stores = [('default.txt', {}), ('none.txt', {'X-TTL-Seconds': 'none'}), ('hour.txt', {'X-TTL-Seconds': '3600'})]
+    for key, headers in stores:
+        response = requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=b'x', headers=headers, timeout=10)
+        assert response.status_code == 201
+
+    # the default TTL of zero expires entries immediately
+    list_response = requests.get(f'{BASE_URL}/project/{project_id}/list/', timeout=10)
+    assert [entry['key'] for entry in list_response.json()] == ['hour.txt', 'none.txt']
+
+    response = requests.post(
+        f'{BASE_URL}/project/{project_id}/bad.txt', data=b'x', headers={'X-TTL-Seconds': 'soon'}, timeout=10
+    )
+    assert response.status_code == 400
+
+    response = requests.put(f'{BASE_URL}/project/{project_id}', json={'default_ttl_secs': None}, timeout=10)
+    assert response.status_code == 200
+    requests.post(f'{BASE_URL}/project/{project_id}/default.txt', data=b'x', timeout=10)
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/default.txt', timeout=10)
+    assert response.status_code == 200
+
+
+def test_project_settings_upsert() -> None:
+    """Test that putting project settings creates the project once, then replaces its settings idempotently."""
+    project_id = new_project_id()
+    url = f'{BASE_URL}/project/{project_id}'
+    settings = {
+        'name': 'Docs',
+        'description': 'built docs',
+        'default_ttl_secs': None,
+        'quota_bytes': 10,
+        'index_key': None,
+        'fallback_key': None,
+        'fallback_status': None,
+        'key_pattern': None,
+    }
+
+    response = requests.put(url, json=settings, timeout=10)
+    assert response.status_code == 201
+    project = response.json()
+    assert project == {'id': project_id, **settings, 'created_at': project['created_at']}
+
+    response = requests.put(url, json=settings, timeout=10)
+    assert response.status_code == 200
+    assert response.json() == project
+
+    # fields left out are cleared
+    response = requests.put(url, json={'name': 'Docs'}, timeout=10)
+    assert response.status_code == 200
+    assert response.json() == {**project, 'description': None, 'quota_bytes': None}
+
+    response = requests.put(url, json={'quota_bytes': -1}, timeout=10)
+    assert response.status_code == 400
+
+
+def test_static_site_fallbacks() -> None:
+    """Test that a project's index_key and fallback_key are served for missing keys, with fallback_status."""
+    project_id = new_project_id()
+    settings = {'index_key': 'index.html', 'fallback_key': '404.html', 'fallback_status': 404}
+    response = requests.put(f'{BASE_URL}/project/{project_id}', json=settings, timeout=10)
+    assert response.status_code == 201
+    for key, content in [('docs/index.html', b'docs'), ('docs/', b'exact'), ('404.html', b'not found')]:
+        requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=content, timeout=10)
+    get_url = f'{BASE_URL}/project/{project_id}/get'
+
+    # an exact match wins over the index
+    response = requests.get(f'{get_url}/docs/', timeout=10)
+    assert (response.status_code, response.content) == (200, b'exact')
+    requests.delete(f'{BASE_URL}/project/{project_id}/docs/', timeout=10)
+    response = requests.get(f'{get_url}/docs/', timeout=10)
+    assert (response.status_code, response.content) == (200, b'docs')
+
+    response = requests.get(f'{get_url}/missing.html', timeout=10)
+    assert (response.status_code, response.content) == (404, b'not found')
+    response = requests.get(f'{get_url}/missing/', timeout=10)
+    assert (response.status_code, response.content) == (404, b'not found')
+
+    # a single page app serves its shell for every path
+    settings = {'fallback_key': 'docs/index.html', 'fallback_status': 200}
+    requests.put(f'{BASE_URL}/project/{project_id}', json=settings, timeout=10)
+    response = requests.get(f'{get_url}/app/route', timeout=10)
+    assert (response.status_code, response.content) == (200, b'docs')
+
+    # without a fallback entry the original key is reported missing
+    requests.put(f'{BASE_URL}/project/{project_id}', json={'fallback_key': 'gone.html'}, timeout=10)
+    response = requests.get(f'{get_url}/missing.html', timeout=10)
+    assert response.status_code == 404
+    assert 'missing.html' in response.json()['error']
+
+    response = requests.put(f'{BASE_URL}/project/{project_id}', json={'fallback_status': 404}, timeout=10)
+    assert response.status_code == 400
+
+
+def test_auto_create_projects_disabled() -> None:
+    """Test that with AUTO_CREATE_PROJECTS=false stores to unknown projects get 404 until the project is created."""
+    project_id = new_project_id()
+    response = requests.post(f'{BASE_URL}/project/{project_id}/a.txt', data=b'a', timeout=10)
+    if response.status_code == 201:
+        pytest.skip('the store created the project, AUTO_CREATE_PROJECTS is not false')
+    assert response.status_code == 404
+    assert project_id in response.json()['error']
+    response = requests.post(f'{BASE_URL}/project/{project_id}/cas', data=b'a', timeout=10)
+    assert response.status_code == 404
+
+    response = requests.put(f'{BASE_URL}/project/{project_id}', json={}, timeout=10)
+    assert response.status_code == 201
+    response = requests.post(f'{BASE_URL}/project/{project_id}/a.txt', data=b'a', timeout=10)
+    assert response.status_code == 201
+
+
+def test_create_project() -> None:
+    """Test creating a project with an id chosen by the service, time-ordered with UUID_V7_IDS."""
+    response = requests.post(f'{BASE_URL}/admin/project', json={'name': 'Scratch'}, timeout=10)
+    assert response.status_code == 201
+    project = response.json()
+    assert project['name'] == 'Scratch'
+    project_id = uuid.UUID(project['id'])
+    assert response.headers['Location'].endswith(f'/project/{project_id}')
+
+    # the stored project has the returned id
+    response = requests.put(f'{BASE_URL}/project/{project_id}', json={'name': 'Renamed'}, timeout=10)
+    assert response.status_code == 200
+    assert response.json()['created_at'] == project['created_at']
+
+    response = requests.post(f'{BASE_URL}/admin/project', json={'quota_bytes': -1}, timeout=10)
+    assert response.status_code == 400
+
+    if project_id.version == 4:
+        pytest.skip('project ids are random, UUID_V7_IDS is not set')
+    assert project_id.version == 7
+    later = uuid.UUID(requests.post(f'{BASE_URL}/admin/project', json={}, timeout=10).json()['id'])
+    assert later.version == 7
+    assert later > project_id
+
+
+def test_project_quota() -> None:
+    """Test that stores taking a project over its quota_bytes are rejected and leave the old content in place."""
+    project_id = new_project_id()
+    response = requests.put(f'{BASE_URL}/project/{project_id}', json={'quota_bytes': 10}, timeout=10)
+    assert response.status_code == 201
+
+    response = requests.post(f'{BASE_URL}/project/{project_id}/a.txt', data=b'123456', timeout=10)
+    assert response.status_code == 201
+    response = requests.post(f'{BASE_URL}/project/{project_id}/b.txt', data=b'123456', timeout=10)
+    assert response.status_code == 507
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/b.txt', timeout=10).status_code == 404
+
+    # overwriting counts the new content rather than adding to the old
+    response = requests.post(f'{BASE_URL}/project/{project_id}/a.txt', data=b'1234567890', timeout=10)
+    assert response.status_code == 200
+    response = requests.post(f'{BASE_URL}/project/{project_id}/a.txt', data=b'12345678901', timeout=10)
+    assert response.status_code == 507
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/a.txt', timeout=10).content == b'1234567890'
+
+    # expired entries don't count
+    requests.post(f'{BASE_URL}/project/{project_id}/a.txt', data=b'x' * 10, headers={'X-TTL-Seconds': '0'}, timeout=10)
+    response = requests.post(f'{BASE_URL}/project/{project_id}/b.txt', data=b'123456', timeout=10)
+    assert response.status_code == 201
+
+
+def test_project_quota_warning() -> None:
+    """Test that stores leaving a project near its quota_bytes succeed with an X-Quota-Warning header."""
+    project_id = new_project_id()
+    response = requests.put(f'{BASE_URL}/project/{project_id}', json={'quota_bytes': 100}, timeout=10)
+    assert response.status_code == 201
+
+    response = requests.post(f'{BASE_URL}/project/{project_id}/a.txt', data=b'x' * 80, timeout=10)
+    assert response.status_code == 201
+    assert 'X-Quota-Warning' not in response.headers
+
+    response = requests.post(f'{BASE_URL}/project/{project_id}/b.txt', data=b'x' * 15, timeout=10)
+    assert response.status_code == 201
+    assert response.headers['X-Quota-Warning'] == '95'
+
+    entry = {'key': 'c.txt', 'mime_type': 'text/plain', 'content_base64': 'eHg='}
+    response = requests.post(f'{BASE_URL}/project/{project_id}/entry', json=entry, timeout=10)
+    assert response.status_code == 201
+    assert response.headers['X-Quota-Warning'] == '97'
+
+    # deleting brings usage back under the threshold
+    requests.delete(f'{BASE_URL}/project/{project_id}/b.txt', timeout=10)
+    response = requests.post(f'{BASE_URL}/project/{project_id}/c.txt', data=b'x', timeout=10)
+    assert response.status_code == 200
+    assert 'X-Quota-Warning' not in response.headers
+
+
+def test_project_key_pattern() -> None:
+    """Test that a project's key_pattern rejects stores of keys which don't match it, and can be changed."""
+    project_id = new_project_id()
+    settings_url = f'{BASE_URL}/project/{project_id}'
+    response = requests.put(settings_url, json={'key_pattern': '^[a-z0-9/_-]+$'}, timeout=10)
+    assert response.status_code == 201
+    assert response.json()['key_pattern'] == '^[a-z0-9/_-]+$'
+
+    assert requests.post(f'{BASE_URL}/project/{project_id}/logs/app_1', data=b'x', timeout=10).status_code == 201
+    response = requests.post(f'{BASE_URL}/project/{project_id}/Logs/App.txt', data=b'x', timeout=10)
+    assert response.status_code == 400
+    assert response.json()['error'].startswith('Invalid key: ')
+    batch = {'operations': [{'op': 'store', 'key': 'ok', 'content_base64': 'eA=='}, {'op': 'store', 'key': 'NO'}]}
+    response = requests.post(f'{BASE_URL}/project/{project_id}/batch', json=batch, timeout=10)
+    assert [result['status'] for result in response.json()['results']] == [201, 400]
+    # content-addressed keys aren't checked
+    response = requests.post(f'{BASE_URL}/project/{project_id}/cas', data=b'x', timeout=10)
+    assert response.status_code == 201
+
+    # a changed pattern applies straight away, and clearing it allows any key
+    requests.put(settings_url, json={'key_pattern': '^[A-Z][a-z/]+\\.txt$'}, timeout=10)
+    response = requests.post(f'{BASE_URL}/project/{project_id}/Logs/app.txt', data=b'x', timeout=10)
+    assert response.status_code == 201
+    requests.put(settings_url, json={}, timeout=10)
+    assert requests.post(f'{BASE_URL}/project/{project_id}/ANY_key!', data=b'x', timeout=10).status_code == 201
+
+    response = requests.put(settings_url, json={'key_pattern': '[unclosed'}, timeout=10)
+    assert response.status_code == 400
+
+
+def test_sunset_header() -> None:
+    """Test that entries expiring soon get a Sunset header, and entries without a TTL don't."""
+    project_id = new_project_id()
+
+    requests.post(f'{BASE_URL}/project/{project_id}/soon.txt', data=b'x', headers={'X-TTL-Seconds': '60'}, timeout=10)
+    requests.post(
+        f'{BASE_URL}/project/{project_id}/later.txt', data=b'x', headers={'X-TTL-Seconds': '864000'}, timeout=10
+    )
+    requests.post(f'{BASE_URL}/project/{project_id}/forever.txt', data=b'x', timeout=10)
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/soon.txt', timeout=10)
+    assert response.status_code == 200
+    assert response.headers['Sunset'].endswith(' GMT')
+
+    for key in ['later.txt', 'forever.txt']:
+        response = requests.get(f'{BASE_URL}/project/{project_id}/get/{key}', timeout=10)
+        assert response.status_code == 200
+        assert 'Sunset' not in response.headers
+
+
+def test_plain_text_errors() -> None:
+    """Test that errors are plain text when the client accepts text/plain, and JSON otherwise."""
+    project_id = new_project_id()
+
+    response = requests.get(
+        f'{BASE_URL}/project/{project_id}/get/missing', headers={'Accept': 'text/plain'}, timeout=10
+    )
+    assert response.status_code == 404
+    assert response.headers['Content-Type'].startswith('text/plain')
+    assert response.text == f'Key not found: missing in project {project_id}'
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/missing', headers={'Accept': '*/*'}, timeout=10)
+    assert response.status_code == 404
+    assert response.json() == {
+        'error': f'Key not found: missing in project {project_id}',
+        'project': project_id,
+        'key': 'missing',
+    }
+
+
+def test_problem_json_errors() -> None:
+    """Test that with PROBLEM_JSON errors are RFC 9457 problem details."""
+    project_id = new_project_id()
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/get/missing', timeout=10)
+    assert response.status_code == 404
+    if response.headers['Content-Type'] != 'application/problem+json':
+        pytest.skip('problem details are not enabled, PROBLEM_JSON is not set')
+    assert response.json() == {
+        'type': 'about:blank',
+        'title': 'Not Found',
+        'status': 404,
+        'detail': f'Key not found: missing in project {project_id}',
+        'instance': f'/project/{project_id}/get/missing',
+        'project': project_id,
+        'key': 'missing',
+    }
+
+
+def test_namespaces() -> None:
+    """Test that the same key can be stored independently in different namespaces."""
+    project_id = new_project_id()
+
+    for path, content in [('doc.txt', b'default'), ('ns/dev/doc.txt', b'dev'), ('ns/prod/doc.txt', b'prod')]:
+        response = requests.post(f'{BASE_URL}/project/{project_id}/{path}', data=content, timeout=10)
+        assert response.status_code == 201
+
+    assert requests.get(f'{BASE_URL}/project/{project_id}/get/doc.txt', timeout=10).content == b'default'
+    assert requests.get(f'{BASE_URL}/project/{project_id}/ns/dev/get/doc.txt', timeout=10).content == b'dev'
+    assert requests.get(f'{BASE_URL}/project/{project_id}/ns/default/get/doc.txt', timeout=10).content == b'default'
+
+    list_response = requests.get(f'{BASE_URL}/project/{project_id}/ns/prod/list/', timeout=10)
+    assert [entry['key'] for entry in list_response.json()] == ['doc.txt']
+    list_response = requests.get(f'{BASE_URL}/project/{project_id}/list/', timeout=10)
+    assert [entry['key'] for entry in list_response.json()] == ['doc.txt']
+
+    response = requests.delete(f'{BASE_URL}/project/{project_id}/ns/dev/doc.txt', timeout=10)
+    assert response.status_code == 204
+    assert requests.get(f'{BASE_URL}/project/{project_id}/ns/dev/get/doc.txt', timeout=10).status_code == 404
+    assert requests.get(f'{BASE_URL}/project/{project_id}/ns/prod/get/doc.txt', timeout=10).status_code == 200
+
+
+def test_list_limit() -> None:
+    """Test that list limits are applied, clamped to the server's maximum and echoed in X-List-Limit."""
+    project_id = new_project_id()
+    for key in ['a', 'b', 'c']:
+        requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=b'x', timeout=10)
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/list/', params={'limit': 2}, timeout=10)
+    assert response.status_code == 200
+    assert [entry['key'] for entry in response.json()] == ['a', 'b']
+    assert response.headers['X-List-Limit'] == '2'
+
+    # limits over the maximum are clamped rather than rejected
+    response = requests.get(f'{BASE_URL}/project/{project_id}/list/', params={'limit': 4_000_000_000}, timeout=10)
+    assert response.status_code == 200
+    assert len(response.json()) == 3
+    assert int(response.headers['X-List-Limit']) < 4_000_000_000
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/list/', params={'limit': 0}, timeout=10)
+    assert response.status_code == 400
+
+
+def test_list_truncated() -> None:
+    """Test that truncated lists say so and give a cursor for the next page, exactly `limit` keys isn't truncated."""
+    project_id = new_project_id()
+    for key in ['a', 'b c', 'd']:
+        requests.post(f'{BASE_URL}/project/{project_id}/{key.replace(" ", "%20")}', data=b'x', timeout=10)
+    list_url = f'{BASE_URL}/project/{project_id}/list/'
+
+    response = requests.get(list_url, params={'limit': 2}, timeout=10)
+    assert [entry['key'] for entry in response.json()] == ['a', 'b c']
+    assert response.headers['X-Result-Truncated'] == 'true'
+    assert response.headers['X-Next-Cursor'] == 'b%20c'
+
+    response = requests.get(f'{list_url}?limit=2&after={response.headers["X-Next-Cursor"]}', timeout=10)
+    assert [entry['key'] for entry in response.json()] == ['d']
+    assert response.headers['X-Result-Truncated'] == 'false'
+    assert 'X-Next-Cursor' not in response.headers
+
+    response = requests.get(list_url, params={'limit': 3}, timeout=10)
+    assert len(response.json()) == 3
+    assert response.headers['X-Result-Truncated'] == 'false'
+
+
+def test_list_key_range() -> None:
+    """Test listing keys from start (inclusive) to end (exclusive), within a prefix and when streamed."""
+    project_id = new_project_id()
+    for key in ['a', 'r/a', 'r/b', 'r/c', 'r/d', 's']:
+        requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=b'x', timeout=10)
+
+    def listed(path: str, **params: str) -> list[str]:
+        response = requests.get(f'{BASE_URL}/project/{project_id}/list/{path}', params=params, timeout=10)
+        assert response.status_code == 200
+        return [entry['key'] for entry in response.json()]
+
+    assert listed('r/', start='r/b', end='r/d') == ['r/b', 'r/c']
+    assert listed('', start='r/c') == ['r/c', 'r/d', 's']
+    assert listed('', end='r/b') == ['a', 'r/a']
+    assert listed('r/', start='b', end='r/b') == ['r/a']
+    assert listed('', start='r/b', end='r/b') == []
+
+    response = requests.get(
+        f'{BASE_URL}/project/{project_id}/list/',
+        params={'start': 'r/b', 'end': 'r/d'},
+        headers={'Accept': 'application/x-ndjson'},
+        timeout=10,
+    )
+    assert [json.loads(line)['key'] for line in response.text.splitlines()] == ['r/b', 'r/c']
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/list/', params={'start': 's', 'end': 'r'}, timeout=10)
+    assert response.status_code == 400
+
+
+def test_list_byte_order() -> None:
+    """Test that with LIST_COLLATE_C keys are listed in byte order, whatever the database's locale."""
+    project_id = new_project_id()
+    keys = ['aa', 'B', 'a-b', '\u00e9', 'a_b', 'Z']
+    for key in keys:
+        path = urllib.parse.quote(key)
+        requests.post(f'{BASE_URL}/project/{project_id}/{path}', data=b'x', timeout=10)
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/list/', timeout=10)
+    assert response.status_code == 200
+    listed = [entry['key'] for entry in response.json()]
+    assert sorted(listed) == sorted(keys)
+    byte_order = sorted(keys, key=lambda key: key.encode())
+    if listed != byte_order:
+        pytest.skip('the database collation is not byte ordered, LIST_COLLATE_C is not set')
+    assert listed == ['B', 'Z', 'a-b', 'a_b', 'aa', '\u00e9']
+
+    prefixed = requests.get(f'{BASE_URL}/project/{project_id}/list/a', timeout=10).json()
+    assert [entry['key'] for entry in prefixed] == ['a-b', 'a_b', 'aa']
+
+
+def test_list_ndjson() -> None:
+    """Test that listings are streamed one JSON object per line with Accept: application/x-ndjson."""
+    project_id = new_project_id()
+    for key in ['a', 'b', 'c']:
+        requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=b'x', timeout=10)
+
+    headers = {'Accept': 'application/x-ndjson'}
+    response = requests.get(f'{BASE_URL}/project/{project_id}/list/', headers=headers, timeout=10)
+    assert response.status_code == 200
+    assert response.headers['Content-Type'] == 'application/x-ndjson'
+    lines = response.text.splitlines()
+    assert [json.loads(line)['key'] for line in lines] == ['a', 'b', 'c']
+    assert 'X-List-Limit' not in response.headers
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/list/', params={'limit': 2}, headers=headers, timeout=10)
+    assert response.text.count('\n') == 2
+    assert response.headers['X-List-Limit'] == '2'
+
+    response = requests.get(
+        f'{BASE_URL}/project/{project_id}/list/', params={'delimiter': '/'}, headers=headers, timeout=10
+    )
+    assert response.status_code == 400
+
+
+def test_list_if_modified_since() -> None:
+    """Test that listings return 304 until an entry in the project is stored or deleted."""
+    project_id = new_project_id()
+    list_url = f'{BASE_URL}/project/{project_id}/list/'
+    requests.post(f'{BASE_URL}/project/{project_id}/a.txt', data=b'x', timeout=10)
+
+    # Last-Modified is withheld until the second it falls in has passed
+    time.sleep(1.1)
+    response = requests.get(list_url, timeout=10)
+    assert response.status_code == 200
+    last_modified = response.headers['Last-Modified']
+
+    response = requests.get(list_url, headers={'If-Modified-Since': last_modified}, timeout=10)
+    assert response.status_code == 304
+    assert response.headers['Last-Modified'] == last_modified
+
+    requests.delete(f'{BASE_URL}/project/{project_id}/a.txt', timeout=10)
+    response = requests.get(list_url, headers={'If-Modified-Since': last_modified}, timeout=10)
+    assert response.status_code == 200
+    assert response.json() == []
+
+
+def test_list_head() -> None:
+    """Test that HEAD on a list returns the matching keys' count and latest update, with GET's filters."""
+    project_id = new_project_id()
+    base = f'{BASE_URL}/project/{project_id}'
+    for key in ['docs/a.md', 'docs/b.md', 'img/x.png']:
+        requests.post(f'{base}/{key}', data=b'x', timeout=10)
+    requests.post(f'{base}/docs/gone', data=b'x', headers={'X-TTL-Seconds': '0'}, timeout=10)
+    requests.post(f'{base}/ns/dev/docs/c.md', data=b'x', timeout=10)
+    time.sleep(1.1)
+
+    response = requests.head(f'{base}/list/', timeout=10)
+    assert response.status_code == 200
+    assert response.headers['X-Total-Count'] == '3'
+    assert response.content == b''
+    response = requests.head(f'{base}/list/docs/', params={'limit': '1'}, timeout=10)
+    assert response.headers['X-Total-Count'] == '2'
+    last_modified = response.headers['Last-Modified']
+    assert requests.head(f'{base}/list/docs/', params={'start': 'docs/b'}, timeout=10).headers['X-Total-Count'] == '1'
+    assert requests.head(f'{base}/ns/dev/list/', timeout=10).headers['X-Total-Count'] == '1'
+
+    response = requests.head(f'{base}/list/docs/', headers={'If-Modified-Since': last_modified}, timeout=10)
+    assert response.status_code == 304
+    requests.post(f'{base}/docs/a.md', data=b'y', timeout=10)
+    time.sleep(1.1)
+    response = requests.head(f'{base}/list/docs/', headers={'If-Modified-Since': last_modified}, timeout=10)
+    assert response.status_code == 200
+
+    response = requests.head(f'{base}/list/none/', timeout=10)
+    assert response.headers['X-Total-Count'] == '0'
+    assert 'Last-Modified' not in response.headers
+
+def test_list_with_delimiter() -> None:
+    """Test directory-style listing, deeper keys are collapsed into common prefixes."""
+    project_id = new_project_id()
+
+    for key in ['docs/a.md', 'docs/b.md', 'docs/img/x.png', 'docs/img/y.png', 'docs/api/v1/z.md', 'top.txt']:
+        requests.post(f'{BASE_URL}/project/{project_id}/{key}', data=b'x', timeout=10)
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/list/docs/', params={'delimiter': '/'}, timeout=10)
+    assert response.status_code == 200
+    data = response.json()
+    assert [entry['key'] for entry in data['keys']] == ['docs/a.md', 'docs/b.md']
+    assert data['common_prefixes'] == ['docs/api/', 'docs/img/']
+
+    response = requests.get(
+        f'{BASE_URL}/project/{project_id}/list/docs/', params={'delimiter': '/', 'depth': '2'}, timeout=10
+    )
+    data = response.json()
+    assert [entry['key'] for entry in data['keys']] == ['docs/a.md', 'docs/b.md', 'docs/img/x.png', 'docs/img/y.png']
+    assert data['common_prefixes'] == ['docs/api/v1/']
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/list/', params={'delimiter': '/'}, timeout=10)
+    data = response.json()
+    assert [entry['key'] for entry in data['keys']] == ['top.txt']
+    assert data['common_prefixes'] == ['docs/']
+
+    response = requests.get(f'{BASE_URL}/project/{project_id}/list/', params={'depth': '1'}, timeout=10)
+    assert response.status_code == 400